@@ -0,0 +1,92 @@
+use rsg::scene::*;
+use rsg::components::*;
+use nalgebra_glm as glm;
+
+type Scene = RSGScene::<RSGComponentLinks, RSGSceneObserver>;
+
+// The old approach: walk the subtree and look for the nearest transform among
+// the ancestors of every node. Kept here only as the baseline to compare with,
+// including its local * parent composition order.
+fn update_world_transforms_ancestor_scan(components: &mut RSGComponentContainer, scene: &Scene, subtree_roots: &[RSGNodeKey]) {
+    let transforms = &mut components.transforms;
+    for subtree_root_key in subtree_roots {
+        for (key, _) in scene.traverse(*subtree_root_key) {
+            if let Some(transform_key) = scene.get_component_links(key).transform_key {
+                let mut world_transform = transforms[transform_key].local_transform;
+                for key in scene.ancestors(key) {
+                    let links = scene.get_component_links(key);
+                    if let Some(transform_key) = links.transform_key {
+                        world_transform *= transforms[transform_key].world_transform;
+                        break;
+                    }
+                    if links.layer_key.is_some() {
+                        break;
+                    }
+                }
+                transforms[transform_key].world_transform = world_transform;
+            }
+        }
+    }
+}
+
+fn transform_node(components: &mut RSGComponentContainer, with_transform: bool) -> RSGNode<RSGComponentLinks> {
+    if with_transform {
+        RSGNode::with_component_links(RSGComponentBuilder::new(components).transform(glm::translation(&glm::vec3(1.0, 0.0, 0.0))).links())
+    } else {
+        RSGNode::new()
+    }
+}
+
+fn make_deep(components: &mut RSGComponentContainer, scene: &mut Scene, root_key: RSGNodeKey, depth: usize) {
+    // a chain of plain groups, each with a transformed leaf, so that the
+    // nearest transform of every leaf is the root
+    let mut builder = RSGSubtreeBuilder::new(scene, root_key);
+    for i in 0..depth {
+        if i == 0 {
+            builder.append(transform_node(components, false));
+        } else {
+            builder.append_to((i - 1) * 2, transform_node(components, false));
+        }
+        builder.append_to(i * 2, transform_node(components, true));
+    }
+    builder.commit();
+}
+
+fn make_wide(components: &mut RSGComponentContainer, scene: &mut Scene, root_key: RSGNodeKey, width: usize) {
+    let group_key = scene.append(root_key, RSGNode::new());
+    for _ in 0..width {
+        let node = transform_node(components, true);
+        scene.append(group_key, node);
+    }
+}
+
+fn bench<F>(name: &str, iterations: u32, mut f: F) where F: FnMut() {
+    f();
+    let start = std::time::Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    println!("{:<40} {:>12.3} ms/iter", name, start.elapsed().as_secs_f64() * 1000.0 / iterations as f64);
+}
+
+fn run(name: &str, make: fn(&mut RSGComponentContainer, &mut Scene, RSGNodeKey, usize), size: usize, iterations: u32) {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+    make(&mut components, &mut scene, root_key, size);
+
+    bench(&format!("{} ancestor scan", name), iterations, || {
+        update_world_transforms_ancestor_scan(&mut components, &scene, &[root_key]);
+    });
+    bench(&format!("{} top-down", name), iterations, || {
+        update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    });
+
+    pool.shutdown();
+}
+
+fn main() {
+    run("deep (1000 levels)", make_deep, 1000, 100);
+    run("wide (100k siblings)", make_wide, 100_000, 20);
+}
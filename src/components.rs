@@ -1,27 +1,263 @@
 use crate::scene::*;
+use crate::materials::*;
 use nalgebra_glm as glm;
 
 slotmap::new_key_type! {
     pub struct RSGTransformKey;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGTransformTRS {
+    pub translation: glm::Vec3,
+    pub rotation: glm::Quat,
+    pub scale: glm::Vec3,
+    // rotation and scale are applied around the pivot (in local space)
+    pub pivot: glm::Vec3
+}
+
+impl Default for RSGTransformTRS {
+    fn default() -> Self {
+        RSGTransformTRS {
+            translation: glm::zero(),
+            rotation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+            pivot: glm::zero()
+        }
+    }
+}
+
+impl RSGTransformTRS {
+    pub fn new(translation: glm::Vec3, rotation: glm::Quat, scale: glm::Vec3) -> Self {
+        RSGTransformTRS {
+            translation,
+            rotation,
+            scale,
+            pivot: glm::zero()
+        }
+    }
+
+    pub fn from_matrix(m: &glm::Mat4) -> Self {
+        // assumes no shear or perspective; a negative determinant is folded into scale.x
+        let m3 = glm::mat4_to_mat3(m);
+        let mut scale = glm::vec3(glm::length(&m3.column(0).into_owned()),
+            glm::length(&m3.column(1).into_owned()),
+            glm::length(&m3.column(2).into_owned()));
+        if glm::determinant(&m3) < 0.0 {
+            scale.x = -scale.x;
+        }
+        let mut rot = m3;
+        for i in 0..3 {
+            if scale[i] != 0.0 {
+                let c = rot.column(i) / scale[i];
+                rot.set_column(i, &c);
+            }
+        }
+        // a zero scale leaves its axis undefined, rebuild it from the other two;
+        // with more than one of them there is no rotation left to recover
+        let rotation = match (0..3).filter(|i| scale[*i] == 0.0).collect::<Vec<_>>()[..] {
+            [] => glm::mat3_to_quat(&rot),
+            [i] => {
+                let axis = glm::cross(&rot.column((i + 1) % 3).into_owned(), &rot.column((i + 2) % 3).into_owned());
+                rot.set_column(i, &axis);
+                glm::mat3_to_quat(&rot)
+            },
+            _ => glm::quat_identity()
+        };
+        RSGTransformTRS {
+            translation: glm::vec3(m[12], m[13], m[14]),
+            rotation: glm::quat_normalize(&rotation),
+            scale,
+            pivot: glm::zero()
+        }
+    }
+
+    pub fn from_matrix_with_pivot(m: &glm::Mat4, pivot: glm::Vec3) -> Self {
+        // M = T(t) * T(p) * R * S * T(-p), so t = t' - p + R * S * p
+        let mut trs = RSGTransformTRS::from_matrix(m);
+        trs.translation += glm::quat_rotate_vec3(&trs.rotation, &trs.scale.component_mul(&pivot)) - pivot;
+        trs.pivot = pivot;
+        trs
+    }
+
+    pub fn to_matrix(&self) -> glm::Mat4 {
+        let m = glm::translation(&(self.translation + self.pivot))
+            * glm::quat_to_mat4(&self.rotation)
+            * glm::scaling(&self.scale);
+        if self.pivot == glm::zero() {
+            m
+        } else {
+            m * glm::translation(&-self.pivot)
+        }
+    }
+
+    // Position of the local origin in the parent's space.
+    pub fn origin(&self) -> glm::Vec3 {
+        self.translation + self.pivot - glm::quat_rotate_vec3(&self.rotation, &self.scale.component_mul(&self.pivot))
+    }
+
+    pub fn set_origin(&mut self, origin: &glm::Vec3) {
+        self.translation += origin - self.origin();
+    }
+
+    pub fn look_at(&mut self, target: &glm::Vec3, up: &glm::Vec3) {
+        // orients -Z (the camera direction) towards target, both given in the parent's space
+        let to_origin = self.origin() - target;
+        if to_origin == glm::zero() {
+            // already there, no direction to face
+            return;
+        }
+        let z = glm::normalize(&to_origin);
+        let mut x = glm::cross(up, &z);
+        if glm::length2(&x) <= f32::EPSILON * glm::length2(up) {
+            // up is parallel to the view direction (or zero), any other up will do
+            let other = if z.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+            x = glm::cross(&other, &z);
+        }
+        let x = glm::normalize(&x);
+        let y = glm::cross(&z, &x);
+        let m = glm::mat3(x.x, y.x, z.x,
+                          x.y, y.y, z.y,
+                          x.z, y.z, z.z);
+        let origin = self.origin();
+        self.rotation = glm::quat_normalize(&glm::mat3_to_quat(&m));
+        self.set_origin(&origin);
+    }
+
+    pub fn rotate_around(&mut self, point: &glm::Vec3, axis: &glm::Vec3, angle: f32) {
+        // orbits the origin around point and turns the rotation by the same amount
+        let q = glm::quat_angle_axis(angle, &glm::normalize(axis));
+        let origin = point + glm::quat_rotate_vec3(&q, &(self.origin() - point));
+        self.rotation = glm::quat_normalize(&(q * self.rotation));
+        self.set_origin(&origin);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RSGTransformInheritance {
+    #[default]
+    Full,
+    TranslationOnly,
+    NoScale,
+    NoRotation,
+    Absolute
+}
+
+impl RSGTransformInheritance {
+    pub fn filter_parent_world(&self, parent_world: &glm::Mat4) -> Option<glm::Mat4> {
+        // None means nothing is inherited
+        match self {
+            RSGTransformInheritance::Full => Some(*parent_world),
+            RSGTransformInheritance::TranslationOnly => Some(glm::translation(&glm::vec3(parent_world[12], parent_world[13], parent_world[14]))),
+            RSGTransformInheritance::NoScale => {
+                let trs = RSGTransformTRS::from_matrix(parent_world);
+                Some(glm::translation(&trs.translation) * glm::quat_to_mat4(&trs.rotation))
+            }
+            RSGTransformInheritance::NoRotation => {
+                let trs = RSGTransformTRS::from_matrix(parent_world);
+                Some(glm::translation(&trs.translation) * glm::scaling(&trs.scale))
+            }
+            RSGTransformInheritance::Absolute => None
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct RSGTransformComponent {
     pub local_transform: glm::Mat4,
-    pub world_transform: glm::Mat4
+    pub world_transform: glm::Mat4,
+    // when set, local_transform is rebuilt from this during the world transform update
+    pub trs: Option<RSGTransformTRS>,
+    pub inheritance: RSGTransformInheritance
 }
 
 impl RSGTransformComponent {
     pub fn new(local_transform: glm::Mat4) -> Self {
         RSGTransformComponent {
             local_transform: local_transform,
-            world_transform: local_transform
+            world_transform: local_transform,
+            trs: None,
+            inheritance: Default::default()
+        }
+    }
+
+    pub fn with_trs(trs: RSGTransformTRS) -> Self {
+        let local_transform = trs.to_matrix();
+        RSGTransformComponent {
+            local_transform,
+            world_transform: local_transform,
+            trs: Some(trs),
+            inheritance: Default::default()
+        }
+    }
+
+    pub fn update_local_transform(&mut self) {
+        if let Some(trs) = self.trs {
+            self.local_transform = trs.to_matrix();
+        }
+    }
+
+    fn inherited_parent_world(&self, parent_world: &glm::Mat4) -> glm::Mat4 {
+        self.inheritance.filter_parent_world(parent_world).unwrap_or_else(glm::one)
+    }
+
+    pub fn set_world_transform(&mut self, parent_world: &glm::Mat4, world: &glm::Mat4) {
+        self.local_transform = glm::inverse(&self.inherited_parent_world(parent_world)) * world;
+        if let Some(trs) = self.trs.as_mut() {
+            *trs = RSGTransformTRS::from_matrix_with_pivot(&self.local_transform, trs.pivot);
+        }
+    }
+
+    pub fn set_world_translation(&mut self, parent_world: &glm::Mat4, position: &glm::Vec3) {
+        let p = glm::inverse(&self.inherited_parent_world(parent_world)) * glm::vec4(position.x, position.y, position.z, 1.0);
+        let origin = glm::vec4_to_vec3(&p);
+        match self.trs.as_mut() {
+            Some(trs) => trs.set_origin(&origin),
+            None => {
+                self.local_transform[12] = origin.x;
+                self.local_transform[13] = origin.y;
+                self.local_transform[14] = origin.z;
+            }
+        }
+    }
+
+    pub fn set_world_rotation(&mut self, parent_world: &glm::Mat4, rotation: &glm::Quat) {
+        let parent_rotation = RSGTransformTRS::from_matrix(&self.inherited_parent_world(parent_world)).rotation;
+        let local_rotation = glm::quat_normalize(&(glm::quat_inverse(&parent_rotation) * rotation));
+        let mut trs = match self.trs {
+            Some(trs) => trs,
+            None => RSGTransformTRS::from_matrix(&self.local_transform)
+        };
+        let origin = trs.origin();
+        trs.rotation = local_rotation;
+        trs.set_origin(&origin);
+        match self.trs.as_mut() {
+            Some(t) => *t = trs,
+            None => self.local_transform = trs.to_matrix()
         }
     }
 }
 
 pub type RSGTransformComponentList = slotmap::SlotMap<RSGTransformKey, RSGTransformComponent>;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGTransformDerivedMatrices {
+    pub inverse_world_transform: glm::Mat4,
+    pub normal_matrix: glm::Mat3
+}
+
+impl RSGTransformDerivedMatrices {
+    pub fn new(world_transform: &glm::Mat4) -> Self {
+        let inverse_world_transform = glm::inverse(world_transform);
+        RSGTransformDerivedMatrices {
+            inverse_world_transform,
+            normal_matrix: glm::transpose(&glm::mat4_to_mat3(&inverse_world_transform))
+        }
+    }
+}
+
+// only transforms that have an entry here get their derived matrices maintained
+pub type RSGTransformDerivedData = slotmap::SecondaryMap<RSGTransformKey, RSGTransformDerivedMatrices>;
+
 slotmap::new_key_type! {
     pub struct RSGOpacityKey;
 }
@@ -47,19 +283,148 @@ slotmap::new_key_type! {
     pub struct RSGMaterialKey;
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct RSGMaterialComponent {
+    // When set, the material is the shared one with the node's entry in
+    // material_overrides applied, and there is no entry in material_data.
+    pub shared_material: Option<RSGSharedMaterialKey>
 }
 
 impl RSGMaterialComponent {
     pub fn new() -> Self {
         RSGMaterialComponent {
+            shared_material: None
+        }
+    }
+
+    pub fn with_shared_material(shared_material_key: RSGSharedMaterialKey) -> Self {
+        RSGMaterialComponent {
+            shared_material: Some(shared_material_key)
         }
     }
 }
 
 pub type RSGMaterialComponentList = slotmap::SlotMap<RSGMaterialKey, RSGMaterialComponent>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGTextureType {
+    Texture2D,
+    Cube,
+    Array2D
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGSamplerFilter {
+    Nearest,
+    Linear
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGSamplerMipmapMode {
+    None,
+    Nearest,
+    Linear
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGSamplerWrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RSGSampler {
+    pub min_filter: RSGSamplerFilter,
+    pub mag_filter: RSGSamplerFilter,
+    pub mipmap_mode: RSGSamplerMipmapMode,
+    pub wrap_u: RSGSamplerWrap,
+    pub wrap_v: RSGSamplerWrap,
+    pub wrap_w: RSGSamplerWrap
+}
+
+impl Default for RSGSampler {
+    fn default() -> Self {
+        RSGSampler {
+            min_filter: RSGSamplerFilter::Linear,
+            mag_filter: RSGSamplerFilter::Linear,
+            mipmap_mode: RSGSamplerMipmapMode::None,
+            wrap_u: RSGSamplerWrap::Repeat,
+            wrap_v: RSGSamplerWrap::Repeat,
+            wrap_w: RSGSamplerWrap::Repeat
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RSGTexture {
+    pub texture_type: RSGTextureType,
+    pub width: u32,
+    pub height: u32,
+    // 6 for cube maps
+    pub layers: u32,
+    pub mip_levels: u32,
+    pub has_alpha: bool,
+    // for backends with combined image samplers, when the material has no sampler of its own
+    pub sampler: RSGSampler,
+    pub source: String
+}
+
+impl RSGTexture {
+    pub fn new_2d(width: u32, height: u32, has_alpha: bool, source: &str) -> Self {
+        RSGTexture {
+            texture_type: RSGTextureType::Texture2D,
+            width,
+            height,
+            layers: 1,
+            mip_levels: 1,
+            has_alpha,
+            sampler: Default::default(),
+            source: source.to_owned()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RSGTextureRegistry {
+    textures: std::collections::HashMap<u32, RSGTexture>,
+    next_id: u32
+}
+
+impl RSGTextureRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add(&mut self, texture: RSGTexture) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.textures.insert(id, texture);
+        id
+    }
+
+    // replaces the texture, e.g. after the image was reloaded
+    pub fn update(&mut self, texture_id: u32, texture: RSGTexture) -> Option<RSGTexture> {
+        self.textures.get_mut(&texture_id).map(|t| std::mem::replace(t, texture))
+    }
+
+    pub fn remove(&mut self, texture_id: u32) -> Option<RSGTexture> {
+        self.textures.remove(&texture_id)
+    }
+
+    pub fn get(&self, texture_id: u32) -> Option<&RSGTexture> {
+        self.textures.get(&texture_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum RSGMaterialProperty {
     // name, default_value
@@ -73,14 +438,96 @@ pub enum RSGMaterialProperty {
     Int4(String, glm::IVec4),
     Mat2(String, glm::Mat2),
     Mat3(String, glm::Mat3),
-    Mat4(String, glm::Mat4)
+    Mat4(String, glm::Mat4),
+    // name, type, default texture id (none bound by default)
+    Texture(String, RSGTextureType, Option<u32>),
+    Sampler(String, RSGSampler)
+}
+
+impl RSGMaterialProperty {
+    pub fn name(&self) -> &str {
+        match self {
+            RSGMaterialProperty::Float(name, _) => name,
+            RSGMaterialProperty::Vec2(name, _) => name,
+            RSGMaterialProperty::Vec3(name, _) => name,
+            RSGMaterialProperty::Vec4(name, _) => name,
+            RSGMaterialProperty::Int(name, _) => name,
+            RSGMaterialProperty::Int2(name, _) => name,
+            RSGMaterialProperty::Int3(name, _) => name,
+            RSGMaterialProperty::Int4(name, _) => name,
+            RSGMaterialProperty::Mat2(name, _) => name,
+            RSGMaterialProperty::Mat3(name, _) => name,
+            RSGMaterialProperty::Mat4(name, _) => name,
+            RSGMaterialProperty::Texture(name, _, _) => name,
+            RSGMaterialProperty::Sampler(name, _) => name
+        }
+    }
+
+    pub fn default_value(&self) -> RSGMaterialCustomValue {
+        match self {
+            RSGMaterialProperty::Float(_, v) => RSGMaterialCustomValue::Float(*v),
+            RSGMaterialProperty::Vec2(_, v) => RSGMaterialCustomValue::Vec2(*v),
+            RSGMaterialProperty::Vec3(_, v) => RSGMaterialCustomValue::Vec3(*v),
+            RSGMaterialProperty::Vec4(_, v) => RSGMaterialCustomValue::Vec4(*v),
+            RSGMaterialProperty::Int(_, v) => RSGMaterialCustomValue::Int(*v),
+            RSGMaterialProperty::Int2(_, v) => RSGMaterialCustomValue::Int2(*v),
+            RSGMaterialProperty::Int3(_, v) => RSGMaterialCustomValue::Int3(*v),
+            RSGMaterialProperty::Int4(_, v) => RSGMaterialCustomValue::Int4(*v),
+            RSGMaterialProperty::Mat2(_, v) => RSGMaterialCustomValue::Mat2(*v),
+            RSGMaterialProperty::Mat3(_, v) => RSGMaterialCustomValue::Mat3(*v),
+            RSGMaterialProperty::Mat4(_, v) => RSGMaterialCustomValue::Mat4(*v),
+            RSGMaterialProperty::Texture(_, t, id) => RSGMaterialCustomValue::Texture(*t, *id),
+            RSGMaterialProperty::Sampler(_, v) => RSGMaterialCustomValue::Sampler(*v)
+        }
+    }
+
+    pub fn is_same_type(&self, value: &RSGMaterialCustomValue) -> bool {
+        match (self, value) {
+            (RSGMaterialProperty::Texture(_, a, _), RSGMaterialCustomValue::Texture(b, _)) => a == b,
+            _ => std::mem::discriminant(&self.default_value()) == std::mem::discriminant(value)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RSGShaderVertexInput {
+    pub semantic: RSGMeshVertexSemantic,
+    pub input_type: RSGMeshVertexInputType,
+    // matrices take one location per column
+    pub location: u32,
+    // left unbound when the mesh does not have it, e.g. vertex colors behind RSG_VERTEX_COLOR
+    pub optional: bool
+}
+
+impl RSGShaderVertexInput {
+    pub fn new(semantic: RSGMeshVertexSemantic, input_type: RSGMeshVertexInputType, location: u32) -> Self {
+        RSGShaderVertexInput { semantic, input_type, location, optional: false }
+    }
+
+    pub fn optional(semantic: RSGMeshVertexSemantic, input_type: RSGMeshVertexInputType, location: u32) -> Self {
+        RSGShaderVertexInput { semantic, input_type, location, optional: true }
+    }
+
+    // Float data can feed float inputs with a different component count, the
+    // missing components default to 0, 0, 0, 1. The same goes for ints.
+    pub fn accepts(&self, input_type: RSGMeshVertexInputType) -> bool {
+        let is_matrix = |t: RSGMeshVertexInputType| t.columns().1 > 1;
+        if is_matrix(self.input_type) || is_matrix(input_type) {
+            self.input_type == input_type
+        } else {
+            self.input_type.is_int() == input_type.is_int()
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct RSGMaterialShaderSet {
     pub vertex_shader: String,
     pub fragment_shader: String,
-    pub properties: Vec<RSGMaterialProperty>
+    pub properties: Vec<RSGMaterialProperty>,
+    pub vertex_inputs: Vec<RSGShaderVertexInput>,
+    // the float property that turns on alpha testing (RSG_ALPHA_TEST) when above zero
+    pub alpha_cutoff: Option<String>
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -95,7 +542,38 @@ pub enum RSGMaterialCustomValue {
     Int4(glm::IVec4),
     Mat2(glm::Mat2),
     Mat3(glm::Mat3),
-    Mat4(glm::Mat4)
+    Mat4(glm::Mat4),
+    // type, texture id
+    Texture(RSGTextureType, Option<u32>),
+    Sampler(RSGSampler)
+}
+
+impl RSGMaterialCustomValue {
+    // as spelled in GLSL
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RSGMaterialCustomValue::Float(_) => "float",
+            RSGMaterialCustomValue::Vec2(_) => "vec2",
+            RSGMaterialCustomValue::Vec3(_) => "vec3",
+            RSGMaterialCustomValue::Vec4(_) => "vec4",
+            RSGMaterialCustomValue::Int(_) => "int",
+            RSGMaterialCustomValue::Int2(_) => "ivec2",
+            RSGMaterialCustomValue::Int3(_) => "ivec3",
+            RSGMaterialCustomValue::Int4(_) => "ivec4",
+            RSGMaterialCustomValue::Mat2(_) => "mat2",
+            RSGMaterialCustomValue::Mat3(_) => "mat3",
+            RSGMaterialCustomValue::Mat4(_) => "mat4",
+            RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, _) => "texture2D",
+            RSGMaterialCustomValue::Texture(RSGTextureType::Cube, _) => "textureCube",
+            RSGMaterialCustomValue::Texture(RSGTextureType::Array2D, _) => "texture2DArray",
+            RSGMaterialCustomValue::Sampler(_) => "sampler"
+        }
+    }
+
+    // textures and samplers are bound as resources, not stored in uniform blocks
+    pub fn is_resource(&self) -> bool {
+        matches!(self, RSGMaterialCustomValue::Texture(_, _) | RSGMaterialCustomValue::Sampler(_))
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -109,26 +587,48 @@ pub enum RSGMaterialBuiltinValue {
     NormalMatrix
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGMaterialBuiltinMatrices {
+    pub model: glm::Mat4,
+    pub view: glm::Mat4,
+    pub projection: glm::Mat4,
+    pub normal: glm::Mat3
+}
+
+impl RSGMaterialBuiltinMatrices {
+    pub fn value(&self, builtin: RSGMaterialBuiltinValue) -> RSGMaterialCustomValue {
+        match builtin {
+            RSGMaterialBuiltinValue::ModelMatrix => RSGMaterialCustomValue::Mat4(self.model),
+            RSGMaterialBuiltinValue::ViewMatrix => RSGMaterialCustomValue::Mat4(self.view),
+            RSGMaterialBuiltinValue::ProjectionMatrix => RSGMaterialCustomValue::Mat4(self.projection),
+            RSGMaterialBuiltinValue::ModelViewMatrix => RSGMaterialCustomValue::Mat4(self.view * self.model),
+            RSGMaterialBuiltinValue::ViewProjectionMatrix => RSGMaterialCustomValue::Mat4(self.projection * self.view),
+            RSGMaterialBuiltinValue::ModelViewProjectionMatrix => RSGMaterialCustomValue::Mat4(self.projection * self.view * self.model),
+            RSGMaterialBuiltinValue::NormalMatrix => RSGMaterialCustomValue::Mat3(self.normal)
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RSGMaterialPropertyValue {
     Builtin(RSGMaterialBuiltinValue),
     Custom(RSGMaterialCustomValue)
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMaterialCullMode {
     None,
     Front,
     Back
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMaterialFrontFace {
     CCW,
     CW
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMaterialCompareOp {
     Never,
     Less,
@@ -149,7 +649,7 @@ bitflags::bitflags! {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMaterialBlendFactor {
     Zero,
     One,
@@ -172,7 +672,7 @@ pub enum RSGMaterialBlendFactor {
     OneMinusSrc1Alpha
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMaterialBlendOp {
     Add,
     Subtract,
@@ -181,10 +681,31 @@ pub enum RSGMaterialBlendOp {
     Max
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+// How colors are stored in textures and produced by fragment shaders, for the whole scene.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RSGAlphaConvention {
+    #[default]
+    Premultiplied,
+    Straight
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGMaterialBlendPreset {
+    PremultipliedAlpha,
+    StraightAlpha,
+    Additive,
+    Multiply,
+    Screen
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct RSGMaterialBlend {
     pub color_write: RSGMaterialColorMask,
     pub blend_enable: bool,
+    // When set, the factors and ops below are replaced by the ones of the preset
+    // for the alpha convention of the scene. The preset is also used when the
+    // inherited opacity enables blending.
+    pub preset: Option<RSGMaterialBlendPreset>,
     pub src_color: RSGMaterialBlendFactor,
     pub dst_color: RSGMaterialBlendFactor,
     pub op_color: RSGMaterialBlendOp,
@@ -198,6 +719,7 @@ impl Default for RSGMaterialBlend {
         RSGMaterialBlend {
             color_write: RSGMaterialColorMask::all(),
             blend_enable: false,
+            preset: None,
             src_color: RSGMaterialBlendFactor::One,
             dst_color: RSGMaterialBlendFactor::OneMinusSrcAlpha,
             op_color: RSGMaterialBlendOp::Add,
@@ -208,13 +730,158 @@ impl Default for RSGMaterialBlend {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+impl RSGMaterialBlend {
+    pub fn with_preset(preset: RSGMaterialBlendPreset) -> Self {
+        RSGMaterialBlend {
+            blend_enable: true,
+            preset: Some(preset),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_factors(&mut self, preset: RSGMaterialBlendPreset, convention: RSGAlphaConvention) {
+        use RSGMaterialBlendFactor::*;
+        let straight = convention == RSGAlphaConvention::Straight;
+        // with straight alpha, multiply and screen cannot take the source alpha into account
+        let (src_color, dst_color) = match preset {
+            RSGMaterialBlendPreset::PremultipliedAlpha => (One, OneMinusSrcAlpha),
+            RSGMaterialBlendPreset::StraightAlpha => (SrcAlpha, OneMinusSrcAlpha),
+            RSGMaterialBlendPreset::Additive if straight => (SrcAlpha, One),
+            RSGMaterialBlendPreset::Additive => (One, One),
+            RSGMaterialBlendPreset::Multiply if straight => (DstColor, Zero),
+            RSGMaterialBlendPreset::Multiply => (DstColor, OneMinusSrcAlpha),
+            RSGMaterialBlendPreset::Screen => (One, OneMinusSrcColor)
+        };
+        self.src_color = src_color;
+        self.dst_color = dst_color;
+        self.op_color = RSGMaterialBlendOp::Add;
+        self.src_alpha = One;
+        self.dst_alpha = OneMinusSrcAlpha;
+        self.op_alpha = RSGMaterialBlendOp::Add;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGMaterialStencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementAndClamp,
+    DecrementAndClamp,
+    Invert,
+    IncrementAndWrap,
+    DecrementAndWrap
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RSGMaterialStencilFace {
+    pub fail_op: RSGMaterialStencilOp,
+    pub depth_fail_op: RSGMaterialStencilOp,
+    pub pass_op: RSGMaterialStencilOp,
+    pub compare_op: RSGMaterialCompareOp
+}
+
+impl Default for RSGMaterialStencilFace {
+    fn default() -> Self {
+        RSGMaterialStencilFace {
+            fail_op: RSGMaterialStencilOp::Keep,
+            depth_fail_op: RSGMaterialStencilOp::Keep,
+            pass_op: RSGMaterialStencilOp::Keep,
+            compare_op: RSGMaterialCompareOp::Always
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RSGMaterialStencil {
+    pub stencil_test: bool,
+    pub front: RSGMaterialStencilFace,
+    pub back: RSGMaterialStencilFace,
+    pub read_mask: u32,
+    pub write_mask: u32,
+    pub reference: u32
+}
+
+impl Default for RSGMaterialStencil {
+    fn default() -> Self {
+        RSGMaterialStencil {
+            stencil_test: false,
+            front: Default::default(),
+            back: Default::default(),
+            read_mask: 0xFF,
+            write_mask: 0xFF,
+            reference: 0
+        }
+    }
+}
+
+impl RSGMaterialStencil {
+    // the same ops for both faces
+    pub fn with_ops(compare_op: RSGMaterialCompareOp, pass_op: RSGMaterialStencilOp, reference: u32) -> Self {
+        let face = RSGMaterialStencilFace { compare_op, pass_op, ..Default::default() };
+        RSGMaterialStencil {
+            stencil_test: true,
+            front: face,
+            back: face,
+            reference,
+            ..Default::default()
+        }
+    }
+}
+
+// What floats in graphics states are compared and hashed by, so that Eq and
+// Hash hold for any value: -0.0 is the same as 0.0, and a NaN equals itself.
+fn f32_bits(value: f32) -> u32 {
+    if value == 0.0 { 0 } else { value.to_bits() }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RSGMaterialDepthBias {
+    pub constant_factor: f32,
+    pub slope_factor: f32,
+    // 0 for no clamping
+    pub clamp: f32
+}
+
+impl RSGMaterialDepthBias {
+    fn bits(&self) -> [u32; 3] {
+        [f32_bits(self.constant_factor), f32_bits(self.slope_factor), f32_bits(self.clamp)]
+    }
+}
+
+impl PartialEq for RSGMaterialDepthBias {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits() == other.bits()
+    }
+}
+
+impl Eq for RSGMaterialDepthBias {}
+
+impl std::hash::Hash for RSGMaterialDepthBias {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.bits().hash(state);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGMaterialPolygonMode {
+    Fill,
+    Line
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct RSGMaterialGraphicsState {
     pub depth_test: bool,
     pub depth_write: bool,
     pub depth_op: RSGMaterialCompareOp,
+    pub depth_bias: Option<RSGMaterialDepthBias>,
     pub cull_mode: RSGMaterialCullMode,
     pub front_face: RSGMaterialFrontFace,
+    pub polygon_mode: RSGMaterialPolygonMode,
+    pub line_width: f32,
+    pub stencil: RSGMaterialStencil,
+    // the rectangle itself is up to the renderer
+    pub scissor_test: bool,
     pub blend: RSGMaterialBlend
 }
 
@@ -224,13 +891,52 @@ impl Default for RSGMaterialGraphicsState {
             depth_test: true,
             depth_write: true,
             depth_op: RSGMaterialCompareOp::Less,
+            depth_bias: None,
             cull_mode: RSGMaterialCullMode::Back,
             front_face: RSGMaterialFrontFace::CCW,
+            polygon_mode: RSGMaterialPolygonMode::Fill,
+            line_width: 1.0,
+            stencil: Default::default(),
+            scissor_test: false,
             blend: Default::default()
         }
     }
 }
 
+impl PartialEq for RSGMaterialGraphicsState {
+    fn eq(&self, other: &Self) -> bool {
+        self.depth_test == other.depth_test
+            && self.depth_write == other.depth_write
+            && self.depth_op == other.depth_op
+            && self.depth_bias == other.depth_bias
+            && self.cull_mode == other.cull_mode
+            && self.front_face == other.front_face
+            && self.polygon_mode == other.polygon_mode
+            && f32_bits(self.line_width) == f32_bits(other.line_width)
+            && self.stencil == other.stencil
+            && self.scissor_test == other.scissor_test
+            && self.blend == other.blend
+    }
+}
+
+impl Eq for RSGMaterialGraphicsState {}
+
+impl std::hash::Hash for RSGMaterialGraphicsState {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.depth_test.hash(state);
+        self.depth_write.hash(state);
+        self.depth_op.hash(state);
+        self.depth_bias.hash(state);
+        self.cull_mode.hash(state);
+        self.front_face.hash(state);
+        self.polygon_mode.hash(state);
+        f32_bits(self.line_width).hash(state);
+        self.stencil.hash(state);
+        self.scissor_test.hash(state);
+        self.blend.hash(state);
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct RSGMaterial {
     pub shader_set_id: u32,
@@ -239,20 +945,191 @@ pub struct RSGMaterial {
 }
 
 impl RSGMaterial {
-    pub fn effective_graphics_state(&self, inherited_opacity: f32) -> RSGMaterialGraphicsState {
+    pub fn effective_graphics_state(&self, inherited_opacity: f32, convention: RSGAlphaConvention) -> RSGMaterialGraphicsState {
+        // Only blending and depth writes are affected, stencil, depth bias,
+        // polygon mode and scissor are kept as they are. A material not writing
+        // any color (e.g. a stencil mask) has nothing to blend.
         let mut state = self.graphics_state;
+        if state.blend.color_write.is_empty() {
+            return state;
+        }
+        if let Some(preset) = state.blend.preset {
+            state.blend.set_factors(preset, convention);
+        }
         let has_transparency = inherited_opacity < 1.0 || state.blend.blend_enable;
         if has_transparency {
             state.depth_write = false;
             if !state.blend.blend_enable {
-                state.blend = Default::default();
                 state.blend.blend_enable = true;
+                if state.blend.preset.is_none() {
+                    let preset = match convention {
+                        RSGAlphaConvention::Premultiplied => RSGMaterialBlendPreset::PremultipliedAlpha,
+                        RSGAlphaConvention::Straight => RSGMaterialBlendPreset::StraightAlpha
+                    };
+                    state.blend.set_factors(preset, convention);
+                }
             }
         }
         state
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum RSGMaterialError {
+    UnknownShaderSet(u32),
+    // e.g. an override for a node without material
+    NoMaterial,
+    // the shared material was removed, or the key is from another container
+    NoSharedMaterial,
+    UnknownProperty { shader_set_id: u32, name: String },
+    TypeMismatch { shader_set_id: u32, name: String, expected: &'static str, found: String },
+    // a shader set whose alpha cutoff does not name one of its float properties
+    InvalidAlphaCutoff { name: String },
+    // all ids below the built-in ones are taken
+    OutOfShaderSetIds
+}
+
+impl std::fmt::Display for RSGMaterialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RSGMaterialError::UnknownShaderSet(id) =>
+                write!(f, "shader set {} is not registered", id),
+            RSGMaterialError::NoMaterial =>
+                write!(f, "there is no material"),
+            RSGMaterialError::NoSharedMaterial =>
+                write!(f, "there is no such shared material"),
+            RSGMaterialError::UnknownProperty { shader_set_id, name } =>
+                write!(f, "shader set {} has no property named '{}'", shader_set_id, name),
+            RSGMaterialError::TypeMismatch { shader_set_id, name, expected, found } =>
+                write!(f, "property '{}' of shader set {} is a {}, but the material provides a {}", name, shader_set_id, expected, found),
+            RSGMaterialError::InvalidAlphaCutoff { name } =>
+                write!(f, "the alpha cutoff '{}' is not a float property of the shader set", name),
+            RSGMaterialError::OutOfShaderSetIds =>
+                write!(f, "out of shader set ids")
+        }
+    }
+}
+
+impl std::error::Error for RSGMaterialError {}
+
+#[derive(Clone, Debug)]
+pub struct RSGMaterialShaderSetRegistry {
+    sets: std::collections::HashMap<u32, RSGMaterialShaderSet>,
+    next_id: u32
+}
+
+impl Default for RSGMaterialShaderSetRegistry {
+    // with the built-in sets, under their fixed ids
+    fn default() -> Self {
+        RSGMaterialShaderSetRegistry {
+            sets: RSGBuiltinShaderSet::ALL.iter().map(|builtin| (builtin.id(), builtin.shader_set())).collect(),
+            next_id: 0
+        }
+    }
+}
+
+impl RSGMaterialShaderSetRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Registering a set identical to an existing one returns the existing id.
+    pub fn register(&mut self, shader_set: RSGMaterialShaderSet) -> Result<u32, RSGMaterialError> {
+        if let Some(id) = self.find(&shader_set) {
+            return Ok(id);
+        }
+        if let Some(name) = shader_set.alpha_cutoff.as_ref() {
+            if !shader_set.properties.iter().any(|p| matches!(p, RSGMaterialProperty::Float(n, _) if n == name)) {
+                return Err(RSGMaterialError::InvalidAlphaCutoff { name: name.clone() });
+            }
+        }
+        let id = self.next_id;
+        if id >= RSGBuiltinShaderSet::FIRST_ID {
+            return Err(RSGMaterialError::OutOfShaderSetIds);
+        }
+        self.next_id += 1;
+        self.sets.insert(id, shader_set);
+        Ok(id)
+    }
+
+    pub fn find(&self, shader_set: &RSGMaterialShaderSet) -> Option<u32> {
+        self.sets.iter().find(|(_, s)| *s == shader_set).map(|(id, _)| *id)
+    }
+
+    pub fn get(&self, shader_set_id: u32) -> Option<&RSGMaterialShaderSet> {
+        self.sets.get(&shader_set_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &RSGMaterialShaderSet)> {
+        self.sets.iter().map(|(id, s)| (*id, s))
+    }
+
+    pub fn check_value(&self, shader_set_id: u32, name: &str, value: &RSGMaterialPropertyValue) -> Result<(), RSGMaterialError> {
+        // builtins are accepted for matrix properties only
+        let shader_set = self.get(shader_set_id).ok_or(RSGMaterialError::UnknownShaderSet(shader_set_id))?;
+        let property = shader_set.properties.iter().find(|p| p.name() == name)
+            .ok_or_else(|| RSGMaterialError::UnknownProperty { shader_set_id, name: name.to_owned() })?;
+        let found = match value {
+            RSGMaterialPropertyValue::Builtin(b) => match property {
+                RSGMaterialProperty::Mat3(_, _) | RSGMaterialProperty::Mat4(_, _) => None,
+                _ => Some(format!("builtin {:?}", b))
+            },
+            RSGMaterialPropertyValue::Custom(v) if property.is_same_type(v) => None,
+            RSGMaterialPropertyValue::Custom(v) => Some(v.type_name().to_owned())
+        };
+        match found {
+            Some(found) => Err(RSGMaterialError::TypeMismatch {
+                shader_set_id,
+                name: name.to_owned(),
+                expected: property.default_value().type_name(),
+                found
+            }),
+            None => Ok(())
+        }
+    }
+
+    // Checks the values of the material against the declared properties, and
+    // adds the declared default for each property without a value.
+    pub fn validate(&self, material: &mut RSGMaterial) -> Result<(), RSGMaterialError> {
+        let shader_set_id = material.shader_set_id;
+        let shader_set = self.get(shader_set_id).ok_or(RSGMaterialError::UnknownShaderSet(shader_set_id))?;
+        // sorted by name, so that the same error is reported every time
+        let mut values: Vec<_> = material.property_values.iter().collect();
+        values.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in values {
+            self.check_value(shader_set_id, name, value)?;
+        }
+        for property in shader_set.properties.iter() {
+            if !material.property_values.contains_key(property.name()) {
+                material.property_values.insert(property.name().to_owned(), RSGMaterialPropertyValue::Custom(property.default_value()));
+            }
+        }
+        Ok(())
+    }
+}
+
+slotmap::new_key_type! {
+    pub struct RSGSharedMaterialKey;
+}
+
+pub type RSGSharedMaterialList = slotmap::SlotMap<RSGSharedMaterialKey, ()>;
+pub type RSGSharedMaterialData = slotmap::SecondaryMap<RSGSharedMaterialKey, RSGMaterial>;
+
+// the materials using a shared material, with their node once update_inherited_properties has seen it
+pub type RSGSharedMaterialUsers = std::collections::HashMap<RSGMaterialKey, Option<RSGNodeKey>>;
+pub type RSGSharedMaterialUserData = slotmap::SecondaryMap<RSGSharedMaterialKey, RSGSharedMaterialUsers>;
+
+pub type RSGMaterialOverrides = smallvec::SmallVec<[(String, RSGMaterialPropertyValue); 4]>;
+pub type RSGMaterialOverrideData = slotmap::SecondaryMap<RSGMaterialKey, RSGMaterialOverrides>;
+
 pub type RSGMaterialComponentData = slotmap::SecondaryMap<RSGMaterialKey, RSGMaterial>;
 
 slotmap::new_key_type! {
@@ -272,7 +1149,7 @@ impl RSGMeshComponent {
 
 pub type RSGMeshComponentList = slotmap::SlotMap<RSGMeshKey, RSGMeshComponent>;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMeshVertexInputType {
     Float,
     Vec2,
@@ -287,7 +1164,7 @@ pub enum RSGMeshVertexInputType {
     Mat4
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMeshVertexInput {
     // [index,] type, view_index, offset
     Position(RSGMeshVertexInputType, u32, usize),
@@ -297,7 +1174,82 @@ pub enum RSGMeshVertexInput {
     TexCoord(u32, RSGMeshVertexInputType, u32, usize),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGMeshVertexSemantic {
+    Position,
+    Normal,
+    Tangent,
+    Color(u32),
+    TexCoord(u32)
+}
+
+impl RSGMeshVertexInputType {
+    pub fn component_count(&self) -> usize {
+        match self {
+            RSGMeshVertexInputType::Float | RSGMeshVertexInputType::Int => 1,
+            RSGMeshVertexInputType::Vec2 | RSGMeshVertexInputType::Int2 => 2,
+            RSGMeshVertexInputType::Vec3 | RSGMeshVertexInputType::Int3 => 3,
+            RSGMeshVertexInputType::Vec4 | RSGMeshVertexInputType::Int4 | RSGMeshVertexInputType::Mat2 => 4,
+            RSGMeshVertexInputType::Mat3 => 9,
+            RSGMeshVertexInputType::Mat4 => 16
+        }
+    }
+
+    // in bytes, all components are 32-bit
+    pub fn size(&self) -> usize {
+        4 * self.component_count()
+    }
+
+    pub fn is_int(&self) -> bool {
+        matches!(self, RSGMeshVertexInputType::Int | RSGMeshVertexInputType::Int2
+            | RSGMeshVertexInputType::Int3 | RSGMeshVertexInputType::Int4)
+    }
+
+    // the type of a column and the number of columns, matrices taking one location per column
+    pub fn columns(&self) -> (RSGMeshVertexInputType, u32) {
+        match self {
+            RSGMeshVertexInputType::Mat2 => (RSGMeshVertexInputType::Vec2, 2),
+            RSGMeshVertexInputType::Mat3 => (RSGMeshVertexInputType::Vec3, 3),
+            RSGMeshVertexInputType::Mat4 => (RSGMeshVertexInputType::Vec4, 4),
+            t => (*t, 1)
+        }
+    }
+}
+
+impl RSGMeshVertexInput {
+    pub fn semantic(&self) -> RSGMeshVertexSemantic {
+        match self {
+            RSGMeshVertexInput::Position(..) => RSGMeshVertexSemantic::Position,
+            RSGMeshVertexInput::Normal(..) => RSGMeshVertexSemantic::Normal,
+            RSGMeshVertexInput::Tangent(..) => RSGMeshVertexSemantic::Tangent,
+            RSGMeshVertexInput::Color(index, ..) => RSGMeshVertexSemantic::Color(*index),
+            RSGMeshVertexInput::TexCoord(index, ..) => RSGMeshVertexSemantic::TexCoord(*index)
+        }
+    }
+
+    pub fn input_type(&self) -> RSGMeshVertexInputType {
+        match self {
+            RSGMeshVertexInput::Position(t, _, _) | RSGMeshVertexInput::Normal(t, _, _) | RSGMeshVertexInput::Tangent(t, _, _)
+                | RSGMeshVertexInput::Color(_, t, _, _) | RSGMeshVertexInput::TexCoord(_, t, _, _) => *t
+        }
+    }
+
+    pub fn view_index(&self) -> u32 {
+        match self {
+            RSGMeshVertexInput::Position(_, v, _) | RSGMeshVertexInput::Normal(_, v, _) | RSGMeshVertexInput::Tangent(_, v, _)
+                | RSGMeshVertexInput::Color(_, _, v, _) | RSGMeshVertexInput::TexCoord(_, _, v, _) => *v
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        match self {
+            RSGMeshVertexInput::Position(_, _, o) | RSGMeshVertexInput::Normal(_, _, o) | RSGMeshVertexInput::Tangent(_, _, o)
+                | RSGMeshVertexInput::Color(_, _, _, o) | RSGMeshVertexInput::TexCoord(_, _, _, o) => *o
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct RSGMeshBufferView {
     pub buffer_id: u32,
     pub offset: usize,
@@ -305,13 +1257,13 @@ pub struct RSGMeshBufferView {
     pub stride: usize
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMeshIndexBufferView {
     U16(RSGMeshBufferView),
     U32(RSGMeshBufferView)
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RSGMeshTopology {
     Triangles,
     TriangleStrip,
@@ -320,7 +1272,7 @@ pub enum RSGMeshTopology {
     Points
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct RSGSubMesh {
     pub topology: RSGMeshTopology,
     pub vertex_count: u32,
@@ -333,7 +1285,9 @@ pub struct RSGSubMesh {
 pub struct RSGMesh {
     pub vertex_views: smallvec::SmallVec<[RSGMeshBufferView; 8]>,
     pub submeshes: smallvec::SmallVec<[RSGSubMesh; 1]>,
-    pub bounds_3d: Option<RSGAabb>
+    pub bounds_3d: Option<RSGAabb>,
+    // in local space; when missing, the sphere around bounds_3d is used
+    pub bounding_sphere: Option<RSGSphere>
 }
 
 pub type RSGMeshComponentData = slotmap::SecondaryMap<RSGMeshKey, RSGMesh>;
@@ -344,6 +1298,252 @@ pub struct RSGMeshBuffer {
     pub source: String
 }
 
+pub type RSGMeshBufferTable = std::collections::HashMap<u32, RSGMeshBuffer>;
+
+impl RSGMeshBuffer {
+    // views address the data as the bytes that get uploaded
+    pub fn byte_size(&self) -> usize {
+        self.data.len() * 4
+    }
+
+    fn read_bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let word = self.data.get((offset + i) / 4)?;
+            *byte = word.to_ne_bytes()[(offset + i) % 4];
+        }
+        Some(bytes)
+    }
+
+    pub fn read_f32(&self, offset: usize) -> Option<f32> {
+        self.read_bytes(offset).map(f32::from_ne_bytes)
+    }
+
+    pub fn read_u16(&self, offset: usize) -> Option<u16> {
+        self.read_bytes(offset).map(u16::from_ne_bytes)
+    }
+
+    pub fn read_u32(&self, offset: usize) -> Option<u32> {
+        self.read_bytes(offset).map(u32::from_ne_bytes)
+    }
+
+    // the index at position i of the view
+    pub fn read_index(&self, index_view: &RSGMeshIndexBufferView, i: usize) -> Option<u32> {
+        match index_view {
+            RSGMeshIndexBufferView::U16(view) => self.read_u16(view.offset + i * 2).map(|index| index as u32),
+            RSGMeshIndexBufferView::U32(view) => self.read_u32(view.offset + i * 4)
+        }
+    }
+}
+
+impl RSGMeshIndexBufferView {
+    pub fn view(&self) -> &RSGMeshBufferView {
+        match self {
+            RSGMeshIndexBufferView::U16(view) | RSGMeshIndexBufferView::U32(view) => view
+        }
+    }
+
+    pub fn element_size(&self) -> usize {
+        match self {
+            RSGMeshIndexBufferView::U16(_) => 2,
+            RSGMeshIndexBufferView::U32(_) => 4
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RSGMeshError {
+    MissingBuffer { buffer_id: u32 },
+    VertexViewOutOfRange { view_index: u32, end: usize, buffer_size: usize },
+    // vertex data is 32-bit, so view offsets and strides are multiples of 4
+    MisalignedVertexView { view_index: u32 },
+    InvalidViewIndex { submesh_index: usize, semantic: RSGMeshVertexSemantic, view_index: u32 },
+    InputOutsideStride { submesh_index: usize, semantic: RSGMeshVertexSemantic, end: usize, stride: usize },
+    VertexCountOutOfRange { submesh_index: usize, semantic: RSGMeshVertexSemantic, vertex_count: u32, available: usize },
+    // an index count without an index view, or the other way around
+    InconsistentIndices { submesh_index: usize },
+    IndexViewOutOfRange { submesh_index: usize, end: usize, buffer_size: usize },
+    MisalignedIndexView { submesh_index: usize },
+    IndexCountOutOfRange { submesh_index: usize, index_count: u32, available: usize },
+    // the first offending index of the submesh
+    IndexOutOfRange { submesh_index: usize, position: usize, index: u32, vertex_count: u32 }
+}
+
+impl std::fmt::Display for RSGMeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RSGMeshError::MissingBuffer { buffer_id } => write!(f, "buffer {} does not exist", buffer_id),
+            RSGMeshError::VertexViewOutOfRange { view_index, end, buffer_size } =>
+                write!(f, "vertex view {} ends at byte {}, but its buffer has {} bytes", view_index, end, buffer_size),
+            RSGMeshError::MisalignedVertexView { view_index } =>
+                write!(f, "the offset or stride of vertex view {} is not a multiple of 4", view_index),
+            RSGMeshError::InvalidViewIndex { submesh_index, semantic, view_index } =>
+                write!(f, "the {:?} input of submesh {} uses vertex view {}, which does not exist", semantic, submesh_index, view_index),
+            RSGMeshError::InputOutsideStride { submesh_index, semantic, end, stride } =>
+                write!(f, "the {:?} input of submesh {} ends at byte {} of a vertex, but the stride is {}", semantic, submesh_index, end, stride),
+            RSGMeshError::VertexCountOutOfRange { submesh_index, semantic, vertex_count, available } =>
+                write!(f, "submesh {} has {} vertices, but its {:?} view holds {}", submesh_index, vertex_count, semantic, available),
+            RSGMeshError::InconsistentIndices { submesh_index } =>
+                write!(f, "submesh {} needs both an index count and an index view, or neither", submesh_index),
+            RSGMeshError::IndexViewOutOfRange { submesh_index, end, buffer_size } =>
+                write!(f, "the index view of submesh {} ends at byte {}, but its buffer has {} bytes", submesh_index, end, buffer_size),
+            RSGMeshError::MisalignedIndexView { submesh_index } =>
+                write!(f, "the offset of the index view of submesh {} is not a multiple of the index size", submesh_index),
+            RSGMeshError::IndexCountOutOfRange { submesh_index, index_count, available } =>
+                write!(f, "submesh {} has {} indices, but its index view holds {}", submesh_index, index_count, available),
+            RSGMeshError::IndexOutOfRange { submesh_index, position, index, vertex_count } =>
+                write!(f, "index {} of submesh {} is {}, but the submesh has {} vertices", position, submesh_index, index, vertex_count)
+        }
+    }
+}
+
+impl std::error::Error for RSGMeshError {}
+
+impl RSGMesh {
+    // the positions a submesh draws, following its indices
+    fn submesh_positions(&self, submesh: &RSGSubMesh, buffers: &RSGMeshBufferTable) -> Option<Vec<glm::Vec3>> {
+        let (input_type, view_index, offset) = submesh.inputs.iter().find_map(|i| match i {
+            RSGMeshVertexInput::Position(t, v, o) => Some((*t, *v, *o)),
+            _ => None
+        })?;
+        let component_count = match input_type {
+            RSGMeshVertexInputType::Vec2 => 2,
+            // w is ignored
+            RSGMeshVertexInputType::Vec3 | RSGMeshVertexInputType::Vec4 => 3,
+            _ => return None
+        };
+        let view = self.vertex_views.get(view_index as usize)?;
+        // as in validate, this also rejects a stride of 0
+        if offset.saturating_add(input_type.size()) > view.stride {
+            return None;
+        }
+        let buffer = buffers.get(&view.buffer_id)?;
+        let read = |vertex: u32| {
+            let base = (vertex as usize).checked_mul(view.stride)?.checked_add(view.offset)?.checked_add(offset)?;
+            if base.checked_add(component_count * 4)? > view.offset.saturating_add(view.size) {
+                return None;
+            }
+            let mut position = glm::Vec3::zeros();
+            for c in 0..component_count {
+                position[c] = buffer.read_f32(base + c * 4)?;
+            }
+            Some(position)
+        };
+        match (submesh.index_count, &submesh.index_view) {
+            (Some(index_count), Some(index_view)) => {
+                let index_buffer = buffers.get(&index_view.view().buffer_id)?;
+                (0..index_count as usize).map(|i| index_buffer.read_index(index_view, i).and_then(read)).collect()
+            },
+            _ => (0..submesh.vertex_count).map(read).collect()
+        }
+    }
+
+    // In local space, from the Position inputs of all submeshes. None when a
+    // submesh has no float positions, or they cannot be read from the buffers.
+    pub fn compute_bounds(&self, buffers: &RSGMeshBufferTable) -> Option<(RSGAabb, RSGSphere)> {
+        let mut positions = Vec::new();
+        for submesh in self.submeshes.iter() {
+            positions.extend(self.submesh_positions(submesh, buffers)?);
+        }
+        let first = *positions.first()?;
+        let aabb = positions.iter().fold(RSGAabb { minimum: first, maximum: first }, |aabb, p| RSGAabb {
+            minimum: glm::min2(&aabb.minimum, p),
+            maximum: glm::max2(&aabb.maximum, p)
+        });
+        // around the center of the box, tighter than the sphere around the box itself
+        let center = aabb.center();
+        let radius = positions.iter().map(|p| glm::distance(p, &center)).fold(0.0, f32::max);
+        Some((aabb, RSGSphere { center, radius }))
+    }
+
+    // Checks that all views, inputs and indices stay within the buffers, reporting every problem.
+    pub fn validate(&self, buffers: &RSGMeshBufferTable) -> Result<(), Vec<RSGMeshError>> {
+        let mut errors = Vec::new();
+        // ends that overflow are reported as usize::MAX, out of range of any buffer
+        let end_of = |offset: usize, size: usize| offset.saturating_add(size);
+        let missing_buffer = |buffer_id, errors: &mut Vec<RSGMeshError>| {
+            let error = RSGMeshError::MissingBuffer { buffer_id };
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        };
+        for (view_index, view) in self.vertex_views.iter().enumerate() {
+            let view_index = view_index as u32;
+            match buffers.get(&view.buffer_id) {
+                Some(buffer) if end_of(view.offset, view.size) > buffer.byte_size() => errors.push(RSGMeshError::VertexViewOutOfRange {
+                    view_index,
+                    end: end_of(view.offset, view.size),
+                    buffer_size: buffer.byte_size()
+                }),
+                Some(_) => {},
+                None => missing_buffer(view.buffer_id, &mut errors)
+            }
+            if view.offset % 4 != 0 || view.stride % 4 != 0 {
+                errors.push(RSGMeshError::MisalignedVertexView { view_index });
+            }
+        }
+        for (submesh_index, submesh) in self.submeshes.iter().enumerate() {
+            for input in submesh.inputs.iter() {
+                let semantic = input.semantic();
+                let view = match self.vertex_views.get(input.view_index() as usize) {
+                    Some(view) => view,
+                    None => {
+                        errors.push(RSGMeshError::InvalidViewIndex { submesh_index, semantic, view_index: input.view_index() });
+                        continue;
+                    }
+                };
+                let end = end_of(input.offset(), input.input_type().size());
+                if end > view.stride {
+                    errors.push(RSGMeshError::InputOutsideStride { submesh_index, semantic, end, stride: view.stride });
+                    continue;
+                }
+                let available = if view.size >= end { (view.size - end) / view.stride + 1 } else { 0 };
+                if submesh.vertex_count as usize > available {
+                    errors.push(RSGMeshError::VertexCountOutOfRange { submesh_index, semantic, vertex_count: submesh.vertex_count, available });
+                }
+            }
+            let (index_count, index_view) = match (submesh.index_count, &submesh.index_view) {
+                (Some(count), Some(index_view)) => (count, index_view),
+                (None, None) => continue,
+                _ => {
+                    errors.push(RSGMeshError::InconsistentIndices { submesh_index });
+                    continue;
+                }
+            };
+            let view = index_view.view();
+            if view.offset % index_view.element_size() != 0 {
+                errors.push(RSGMeshError::MisalignedIndexView { submesh_index });
+            }
+            let available = view.size / index_view.element_size();
+            if index_count as usize > available {
+                errors.push(RSGMeshError::IndexCountOutOfRange { submesh_index, index_count, available });
+            }
+            let buffer = match buffers.get(&view.buffer_id) {
+                Some(buffer) => buffer,
+                None => {
+                    missing_buffer(view.buffer_id, &mut errors);
+                    continue;
+                }
+            };
+            if end_of(view.offset, view.size) > buffer.byte_size() {
+                errors.push(RSGMeshError::IndexViewOutOfRange { submesh_index, end: end_of(view.offset, view.size), buffer_size: buffer.byte_size() });
+                continue;
+            }
+            let out_of_range = (0..(index_count as usize).min(available))
+                .map(|position| (position, buffer.read_index(index_view, position).unwrap()))
+                .find(|(_, index)| *index >= submesh.vertex_count);
+            if let Some((position, index)) = out_of_range {
+                errors.push(RSGMeshError::IndexOutOfRange { submesh_index, position, index, vertex_count: submesh.vertex_count });
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct RSGAabb {
     pub minimum: glm::Vec3,
@@ -354,6 +1554,37 @@ impl RSGAabb {
     pub fn center(&self) -> glm::Vec3 {
         (self.minimum + self.maximum) * 0.5
     }
+
+    pub fn extents(&self) -> glm::Vec3 {
+        (self.maximum - self.minimum) * 0.5
+    }
+
+    pub fn union(&self, other: &RSGAabb) -> RSGAabb {
+        RSGAabb {
+            minimum: glm::min2(&self.minimum, &other.minimum),
+            maximum: glm::max2(&self.maximum, &other.maximum)
+        }
+    }
+
+    pub fn transformed(&self, m: &glm::Mat4) -> RSGAabb {
+        // the box around the transformed box (center + absolute-valued rotation/scale applied to the extents)
+        let c = self.center();
+        let e = self.extents();
+        let world_center = glm::vec4_to_vec3(&(m * glm::vec4(c.x, c.y, c.z, 1.0)));
+        let world_extents = glm::abs(&glm::mat4_to_mat3(m)) * e;
+        RSGAabb {
+            minimum: world_center - world_extents,
+            maximum: world_center + world_extents
+        }
+    }
+
+    pub fn union_opt(a: Option<RSGAabb>, b: Option<RSGAabb>) -> Option<RSGAabb> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.union(&b)),
+            (a, None) => a,
+            (None, b) => b
+        }
+    }
 }
 
 impl Default for RSGAabb {
@@ -370,41 +1601,135 @@ impl std::fmt::Display for RSGAabb {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGSphere {
+    pub center: glm::Vec3,
+    pub radius: f32
+}
+
+impl RSGSphere {
+    pub fn from_aabb(aabb: &RSGAabb, m: &glm::Mat4) -> Self {
+        // sphere around the local box
+        RSGSphere { center: aabb.center(), radius: glm::length(&aabb.extents()) }.transformed(m)
+    }
+
+    pub fn transformed(&self, m: &glm::Mat4) -> Self {
+        // the radius is scaled by the largest axis scale
+        let c = self.center;
+        let max_scale = (0..3).map(|i| glm::length(&glm::vec3(m[i * 4], m[i * 4 + 1], m[i * 4 + 2]))).fold(0.0, f32::max);
+        RSGSphere {
+            center: glm::vec4_to_vec3(&(m * glm::vec4(c.x, c.y, c.z, 1.0))),
+            radius: self.radius * max_scale
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RSGNodeBounds {
+    // world-space bounds of the node's own mesh
+    pub world_aabb: Option<RSGAabb>,
+    pub world_sphere: Option<RSGSphere>,
+    // world_aabb of the node and all its descendants, not crossing into child layers
+    pub subtree_aabb: Option<RSGAabb>
+}
+
+pub type RSGNodeBoundsData = slotmap::SecondaryMap<RSGNodeKey, RSGNodeBounds>;
+
 slotmap::new_key_type! {
     pub struct RSGLayerKey;
 }
 
 #[derive(Clone, Copy)]
 pub struct RSGLayerComponent {
+    // node with a camera component, within this layer
+    pub active_camera: Option<RSGNodeKey>
 }
 
 impl RSGLayerComponent {
     pub fn new() -> Self {
         RSGLayerComponent {
+            active_camera: None
         }
     }
 }
 
 pub type RSGLayerComponentList = slotmap::SlotMap<RSGLayerKey, RSGLayerComponent>;
 
+slotmap::new_key_type! {
+    pub struct RSGCameraKey;
+}
+
+#[derive(Clone, Copy)]
+pub struct RSGCameraComponent {
+    pub camera: RSGCamera,
+    // updated by update_inherited_properties when the node's world transform is
+    pub world_transform: glm::Mat4,
+    pub world_properties: RSGCameraWorldTransformDerivedProperties
+}
+
+impl RSGCameraComponent {
+    pub fn new(camera: RSGCamera) -> Self {
+        RSGCameraComponent {
+            camera,
+            world_transform: glm::one(),
+            world_properties: Default::default()
+        }
+    }
+
+    pub fn frustum(&self) -> RSGFrustum {
+        RSGFrustum::new(&self.camera, &self.world_transform)
+    }
+
+    pub fn view_matrix(&self) -> glm::Mat4 {
+        RSGCamera::view_matrix(&self.world_transform)
+    }
+
+    pub fn projection_matrix(&self, conventions: &RSGProjectionConventions) -> glm::Mat4 {
+        self.camera.projection_matrix(conventions)
+    }
+
+    pub fn view_projection_matrix(&self, conventions: &RSGProjectionConventions) -> glm::Mat4 {
+        self.camera.view_projection_matrix(&self.world_transform, conventions)
+    }
+}
+
+pub type RSGCameraComponentList = slotmap::SlotMap<RSGCameraKey, RSGCameraComponent>;
+
 #[derive(Clone, Copy, Default)]
 pub struct RSGComponentLinks {
     pub transform_key: Option<RSGTransformKey>,
     pub opacity_key: Option<RSGOpacityKey>,
     pub material_key: Option<RSGMaterialKey>,
     pub mesh_key: Option<RSGMeshKey>,
-    pub layer_key: Option<RSGLayerKey>
+    pub layer_key: Option<RSGLayerKey>,
+    pub camera_key: Option<RSGCameraKey>
 }
 
 #[derive(Default)]
 pub struct RSGComponentContainer {
     pub transforms: RSGTransformComponentList,
+    pub transform_derived: RSGTransformDerivedData,
     pub opacities: RSGOpacityComponentList,
     pub materials: RSGMaterialComponentList,
     pub material_data: RSGMaterialComponentData,
+    pub material_overrides: RSGMaterialOverrideData,
+    pub shared_materials: RSGSharedMaterialList,
+    pub shared_material_data: RSGSharedMaterialData,
+    pub shared_material_user_data: RSGSharedMaterialUserData,
+    pub shader_sets: RSGMaterialShaderSetRegistry,
+    pub textures: RSGTextureRegistry,
+    pub alpha_convention: RSGAlphaConvention,
+    // when set, materials using a texture with alpha are not opaque
+    pub texture_alpha_affects_opacity: bool,
     pub meshes: RSGMeshComponentList,
     pub mesh_data: RSGMeshComponentData,
-    pub layers: RSGLayerComponentList
+    pub layers: RSGLayerComponentList,
+    pub cameras: RSGCameraComponentList,
+    pub node_bounds: RSGNodeBoundsData,
+    // dirty world roots whose bounds update_world_bounds has not recalculated yet
+    pub stale_bounds_roots: RSGDirtySubtreeRootList,
+    // shared material users were added since the last update_inherited_properties
+    pub shared_material_users_added: bool
 }
 
 impl RSGComponentContainer {
@@ -418,12 +1743,19 @@ impl RSGComponentContainer {
     pub fn remove(&mut self, component_links: RSGComponentLinks) {
         if let Some(key) = component_links.transform_key {
             self.transforms.remove(key);
+            self.transform_derived.remove(key);
         }
         if let Some(key) = component_links.opacity_key {
             self.opacities.remove(key);
         }
         if let Some(key) = component_links.material_key {
-            self.materials.remove(key);
+            if let Some(shared_material_key) = self.materials.remove(key).and_then(|m| m.shared_material) {
+                if let Some(users) = self.shared_material_user_data.get_mut(shared_material_key) {
+                    users.remove(&key);
+                }
+            }
+            self.material_data.remove(key);
+            self.material_overrides.remove(key);
         }
         if let Some(key) = component_links.mesh_key {
             self.meshes.remove(key);
@@ -431,6 +1763,230 @@ impl RSGComponentContainer {
         if let Some(key) = component_links.layer_key {
             self.layers.remove(key);
         }
+        if let Some(key) = component_links.camera_key {
+            self.cameras.remove(key);
+        }
+    }
+
+    pub fn parent_world_transform<ObserverT>(&self, scene: &RSGScene<RSGComponentLinks, ObserverT>, node_key: RSGNodeKey) -> glm::Mat4
+        where ObserverT: RSGObserver
+    {
+        parent_world_transform(&self.transforms, scene, node_key).unwrap_or_else(glm::one)
+    }
+
+    pub fn enable_derived_matrices(&mut self, transform_key: RSGTransformKey) {
+        // inverse world and normal matrices, kept up to date by update_inherited_properties from now on
+        let world_transform = self.transforms[transform_key].world_transform;
+        self.transform_derived.insert(transform_key, RSGTransformDerivedMatrices::new(&world_transform));
+    }
+
+    pub fn disable_derived_matrices(&mut self, transform_key: RSGTransformKey) {
+        self.transform_derived.remove(transform_key);
+    }
+
+    pub fn set_active_camera<ObserverT>(&mut self, scene: &RSGScene<RSGComponentLinks, ObserverT>,
+        layer_node_key: RSGNodeKey, camera_node_key: Option<RSGNodeKey>)
+        where ObserverT: RSGObserver
+    {
+        if let Some(key) = camera_node_key {
+            assert!(scene.get_component_links(key).camera_key.is_some());
+            debug_assert!(scene.ancestors_with_node(key).find(|a| scene.get_component_links(*a).layer_key.is_some()) == Some(layer_node_key));
+        }
+        let layer_key = scene.get_component_links(layer_node_key).layer_key.unwrap();
+        self.layers[layer_key].active_camera = camera_node_key;
+    }
+
+    pub fn active_camera<ObserverT>(&self, scene: &RSGScene<RSGComponentLinks, ObserverT>, layer_node_key: RSGNodeKey) -> Option<&RSGCameraComponent>
+        where ObserverT: RSGObserver
+    {
+        let layer_key = scene.get_component_links(layer_node_key).layer_key.unwrap();
+        self.layers[layer_key].active_camera
+            .filter(|key| scene.is_valid(*key))
+            .and_then(|key| scene.get_component_links(key).camera_key)
+            .and_then(|camera_key| self.cameras.get(camera_key))
+    }
+
+    pub fn add_shared_material(&mut self, mut material: RSGMaterial) -> Result<RSGSharedMaterialKey, RSGMaterialError> {
+        self.shader_sets.validate(&mut material)?;
+        let key = self.shared_materials.insert(());
+        self.shared_material_data.insert(key, material);
+        self.shared_material_user_data.insert(key, Default::default());
+        Ok(key)
+    }
+
+    // Nodes still using the material are left without one, and lose their overrides.
+    pub fn remove_shared_material(&mut self, shared_material_key: RSGSharedMaterialKey) -> Option<RSGMaterial> {
+        self.shared_materials.remove(shared_material_key)?;
+        for (material_key, _) in self.shared_material_user_data.remove(shared_material_key).unwrap_or_default() {
+            if let Some(m) = self.materials.get_mut(material_key) {
+                m.shared_material = None;
+            }
+            self.material_overrides.remove(material_key);
+        }
+        self.shared_material_data.remove(shared_material_key)
+    }
+
+    // Records the nodes of the users added since the last update. Those are
+    // expected in the scene by now, so the dirty subtrees hold them and nothing
+    // else has to be searched; they are searched for once, a user not attached
+    // to a node by then stays without one.
+    fn resolve_shared_material_users<ObserverT>(&mut self, scene: &RSGScene<RSGComponentLinks, ObserverT>,
+        subtree_roots: &[RSGNodeKey])
+        where ObserverT: RSGObserver
+    {
+        if !std::mem::take(&mut self.shared_material_users_added) {
+            return;
+        }
+        for subtree_root_key in prune_nested_subtree_roots(scene, subtree_roots) {
+            for (key, _) in scene.traverse(subtree_root_key) {
+                let material_key = match scene.get_component_links(key).material_key {
+                    Some(material_key) => material_key,
+                    None => continue
+                };
+                let shared_material_key = self.materials.get(material_key).and_then(|m| m.shared_material);
+                if let Some(users) = shared_material_key.and_then(|k| self.shared_material_user_data.get_mut(k)) {
+                    users.insert(material_key, Some(key));
+                }
+            }
+        }
+    }
+
+    // The nodes using the shared material, as of the last update_inherited_properties.
+    pub fn shared_material_users<ObserverT>(&self, scene: &RSGScene<RSGComponentLinks, ObserverT>,
+        shared_material_key: RSGSharedMaterialKey) -> Vec<RSGNodeKey>
+        where ObserverT: RSGObserver
+    {
+        let mut users: Vec<RSGNodeKey> = self.shared_material_user_data.get(shared_material_key)
+            .map(|users| users.iter()
+                .filter_map(|(material_key, node_key)| node_key.filter(|key| scene.is_valid(*key)
+                    && scene.get_component_links(*key).material_key == Some(*material_key)))
+                .collect())
+            .unwrap_or_default();
+        users.sort();
+        users
+    }
+
+    // Changes the shared material for all its users, marking them dirty; users
+    // added since the last update are dirty already. The result is validated
+    // like a newly added material, together with the users' overrides when the
+    // shader set changes, and is discarded on error.
+    pub fn edit_shared_material<ObserverT, F>(&mut self, scene: &mut RSGScene<RSGComponentLinks, ObserverT>,
+        shared_material_key: RSGSharedMaterialKey, f: F) -> Result<(), RSGMaterialError>
+        where ObserverT: RSGObserver, F: FnOnce(&mut RSGMaterial)
+    {
+        let old = self.shared_material_data.get(shared_material_key).ok_or(RSGMaterialError::NoSharedMaterial)?;
+        let mut material = old.clone();
+        f(&mut material);
+        self.shader_sets.validate(&mut material)?;
+        let state_changed = old.shader_set_id != material.shader_set_id || old.graphics_state != material.graphics_state;
+        let users = &self.shared_material_user_data[shared_material_key];
+        if material.shader_set_id != old.shader_set_id {
+            // the overrides of the users have to fit the new shader set as well
+            for material_key in users.keys() {
+                for (name, value) in self.material_overrides.get(*material_key).into_iter().flatten() {
+                    self.shader_sets.check_value(material.shader_set_id, name, value)?;
+                }
+            }
+        }
+        for node_key in self.shared_material_users(scene, shared_material_key) {
+            scene.mark_dirty(node_key, RSGDirtyFlags::MATERIAL_VALUES.bits());
+            if state_changed {
+                scene.mark_dirty(node_key, RSGDirtyFlags::MATERIAL.bits());
+            }
+        }
+        self.shared_material_data[shared_material_key] = material;
+        Ok(())
+    }
+
+    // the shared material for nodes using one, the own copy otherwise; overrides are not applied
+    pub fn material(&self, material_key: RSGMaterialKey) -> Option<&RSGMaterial> {
+        match self.materials.get(material_key)?.shared_material {
+            Some(key) => self.shared_material_data.get(key),
+            None => self.material_data.get(material_key)
+        }
+    }
+
+    pub fn material_property_value(&self, material_key: RSGMaterialKey, name: &str) -> Option<&RSGMaterialPropertyValue> {
+        self.material_overrides.get(material_key)
+            .and_then(|overrides| overrides.iter().find(|(n, _)| n == name).map(|(_, v)| v))
+            .or_else(|| self.material(material_key).and_then(|m| m.property_values.get(name)))
+    }
+
+    // For a node using a shared material this only touches the node's own
+    // override table. The caller is expected to mark the node MATERIAL_VALUES dirty.
+    pub fn set_material_override(&mut self, material_key: RSGMaterialKey, name: &str,
+        value: RSGMaterialPropertyValue) -> Result<(), RSGMaterialError>
+    {
+        let shader_set_id = self.material(material_key).ok_or(RSGMaterialError::NoMaterial)?.shader_set_id;
+        self.shader_sets.check_value(shader_set_id, name, &value)?;
+        if self.materials[material_key].shared_material.is_some() {
+            if !self.material_overrides.contains_key(material_key) {
+                self.material_overrides.insert(material_key, smallvec::SmallVec::new());
+            }
+            let overrides = &mut self.material_overrides[material_key];
+            match overrides.iter_mut().find(|(n, _)| n == name) {
+                Some(o) => o.1 = value,
+                None => overrides.push((name.to_owned(), value))
+            }
+        } else {
+            self.material_data[material_key].property_values.insert(name.to_owned(), value);
+        }
+        Ok(())
+    }
+
+    pub fn clear_material_override(&mut self, material_key: RSGMaterialKey, name: &str) -> bool {
+        match self.material_overrides.get_mut(material_key) {
+            Some(overrides) => {
+                let count = overrides.len();
+                overrides.retain(|(n, _)| n != name);
+                overrides.len() != count
+            }
+            None => false
+        }
+    }
+
+    // Copy on write: a node using a shared material gets its own copy, with the
+    // overrides baked in, and stops following the shared one.
+    pub fn material_mut(&mut self, material_key: RSGMaterialKey) -> &mut RSGMaterial {
+        if let Some(shared_material_key) = self.materials[material_key].shared_material.take() {
+            self.shared_material_user_data[shared_material_key].remove(&material_key);
+            let mut material = self.shared_material_data[shared_material_key].clone();
+            for (name, value) in self.material_overrides.remove(material_key).unwrap_or_default() {
+                material.property_values.insert(name, value);
+            }
+            self.material_data.insert(material_key, material);
+        }
+        &mut self.material_data[material_key]
+    }
+
+    pub fn inherited_opacity(&self, links: &RSGComponentLinks) -> f32 {
+        links.opacity_key.map_or(1.0, |key| self.opacities[key].inherited_opacity)
+    }
+
+    // The transform placing the node: its own, or for a node without one that
+    // of the nearest transformed ancestor within its layer.
+    pub fn inherited_transform_key<ObserverT>(&self, scene: &RSGScene<RSGComponentLinks, ObserverT>, node_key: RSGNodeKey) -> Option<RSGTransformKey>
+        where ObserverT: RSGObserver
+    {
+        for key in scene.ancestors_with_node(node_key) {
+            let links = scene.get_component_links(key);
+            if links.transform_key.is_some() || links.layer_key.is_some() {
+                return links.transform_key;
+            }
+        }
+        None
+    }
+
+    pub fn world_transform<ObserverT>(&self, scene: &RSGScene<RSGComponentLinks, ObserverT>, node_key: RSGNodeKey) -> glm::Mat4
+        where ObserverT: RSGObserver
+    {
+        self.inherited_transform_key(scene, node_key).map_or_else(glm::one, |key| self.transforms[key].world_transform)
+    }
+
+    // what the material of the node is drawn with, see RSGMaterial::effective_graphics_state
+    pub fn effective_graphics_state(&self, links: &RSGComponentLinks) -> Option<RSGMaterialGraphicsState> {
+        let material = self.material(links.material_key?)?;
+        Some(material.effective_graphics_state(self.inherited_opacity(links), self.alpha_convention))
     }
 
     pub fn is_opaque(&self, links: &RSGComponentLinks) -> bool {
@@ -440,13 +1996,35 @@ impl RSGComponentContainer {
             }
         }
         if let Some(material_key) = links.material_key {
-            if self.material_data[material_key].graphics_state.blend.blend_enable {
-                return false;
+            if let Some(material) = self.material(material_key) {
+                if material.graphics_state.blend.blend_enable {
+                    return false;
+                }
+                if self.texture_alpha_affects_opacity && self.uses_texture_with_alpha(material_key, material) {
+                    return false;
+                }
             }
         }
         return true;
     }
 
+    fn uses_texture_with_alpha(&self, material_key: RSGMaterialKey, material: &RSGMaterial) -> bool {
+        let properties = match self.shader_sets.get(material.shader_set_id) {
+            Some(shader_set) => &shader_set.properties,
+            None => return false
+        };
+        properties.iter().any(|p| {
+            let value = match self.material_property_value(material_key, p.name()) {
+                Some(RSGMaterialPropertyValue::Custom(v)) => *v,
+                _ => p.default_value()
+            };
+            match value {
+                RSGMaterialCustomValue::Texture(_, Some(id)) => self.textures.get(id).is_some_and(|t| t.has_alpha),
+                _ => false
+            }
+        })
+    }
+
     pub fn print_scene<ObserverT>(&self, scene: &RSGScene<RSGComponentLinks, ObserverT>,
         start_node_key: RSGNodeKey, max_depth: Option<u32>)
         where ObserverT: RSGObserver
@@ -474,8 +2052,11 @@ impl RSGComponentContainer {
             }
 
             if let Some(material_key) = component_links.material_key {
-                let material = &self.material_data[material_key];
-                println!("{}    material property value count={}", indent, material.property_values.len());
+                let m = &self.materials[material_key];
+                let value_count = self.material(material_key).map_or(0, |material| material.property_values.len());
+                let override_count = self.material_overrides.get(material_key).map_or(0, |o| o.len());
+                println!("{}    material shared={:?} property value count={} override count={}", indent,
+                    m.shared_material, value_count, override_count);
             }
 
             if let Some(mesh_key) = component_links.mesh_key {
@@ -483,8 +2064,13 @@ impl RSGComponentContainer {
                 println!("{}    mesh submesh count={}", indent, mesh.submeshes.len());
             }
 
-            if let Some(_) = component_links.layer_key {
-                println!("{}    layer root", indent);
+            if let Some(layer_key) = component_links.layer_key {
+                println!("{}    layer root active camera={:?}", indent, self.layers[layer_key].active_camera);
+            }
+
+            if let Some(camera_key) = component_links.camera_key {
+                let c = &self.cameras[camera_key];
+                println!("{}    camera {:?} {:?}", indent, c.camera, c.world_properties);
             }
         }
     }
@@ -492,35 +2078,102 @@ impl RSGComponentContainer {
 
 pub struct RSGComponentBuilder<'a> {
     links: RSGComponentLinks,
-    container: &'a mut RSGComponentContainer
+    container: &'a mut RSGComponentContainer,
+    mesh_buffers: Option<&'a RSGMeshBufferTable>
 }
 
 impl<'a> RSGComponentBuilder<'a> {
     pub fn new(container: &'a mut RSGComponentContainer) -> Self {
         RSGComponentBuilder {
             links: Default::default(),
-            container: container
+            container: container,
+            mesh_buffers: None
         }
     }
 
+    // applies to the meshes added after, filling in missing bounds from the vertex data
+    pub fn auto_bounds(&mut self, buffers: &'a RSGMeshBufferTable) -> &mut Self {
+        self.mesh_buffers = Some(buffers);
+        self
+    }
+
     pub fn transform(&mut self, local_transform: glm::Mat4) -> &mut Self {
         self.links.transform_key = Some(self.container.transforms.insert(RSGTransformComponent::new(local_transform)));
         self
     }
 
+    pub fn trs(&mut self, trs: RSGTransformTRS) -> &mut Self {
+        self.links.transform_key = Some(self.container.transforms.insert(RSGTransformComponent::with_trs(trs)));
+        self
+    }
+
+    pub fn inheritance(&mut self, inheritance: RSGTransformInheritance) -> &mut Self {
+        // applies to the transform added before
+        let transform_key = self.links.transform_key.expect("inheritance needs a transform added before");
+        self.container.transforms[transform_key].inheritance = inheritance;
+        self
+    }
+
+    pub fn derived_matrices(&mut self) -> &mut Self {
+        // applies to the transform added before
+        let transform_key = self.links.transform_key.expect("derived matrices need a transform added before");
+        self.container.enable_derived_matrices(transform_key);
+        self
+    }
+
     pub fn opacity(&mut self, opacity: f32) -> &mut Self {
         self.links.opacity_key = Some(self.container.opacities.insert(RSGOpacityComponent::new(opacity)));
         self
     }
 
+    // Missing values get the declared defaults. Panics when the material does
+    // not validate, use try_material to get the error instead.
     pub fn material(&mut self, material: RSGMaterial) -> &mut Self {
+        if let Err(e) = self.try_material(material) {
+            panic!("invalid material: {}", e);
+        }
+        self
+    }
+
+    pub fn try_material(&mut self, mut material: RSGMaterial) -> Result<&mut Self, RSGMaterialError> {
+        self.container.shader_sets.validate(&mut material)?;
         let key = self.container.materials.insert(RSGMaterialComponent::new());
         self.links.material_key = Some(key);
         self.container.material_data.insert(key, material);
+        Ok(self)
+    }
+
+    pub fn shared_material(&mut self, shared_material_key: RSGSharedMaterialKey) -> &mut Self {
+        assert!(self.container.shared_materials.contains_key(shared_material_key));
+        let key = self.container.materials.insert(RSGMaterialComponent::with_shared_material(shared_material_key));
+        self.container.shared_material_user_data[shared_material_key].insert(key, None);
+        self.container.shared_material_users_added = true;
+        self.links.material_key = Some(key);
+        self
+    }
+
+    // applies to the material added last; panics when there is none or the
+    // override is invalid, use try_material_override to get the error instead
+    pub fn material_override(&mut self, name: &str, value: RSGMaterialPropertyValue) -> &mut Self {
+        if let Err(e) = self.try_material_override(name, value) {
+            panic!("invalid material override: {}", e);
+        }
         self
     }
 
-    pub fn mesh(&mut self, mesh: RSGMesh) -> &mut Self {
+    pub fn try_material_override(&mut self, name: &str, value: RSGMaterialPropertyValue) -> Result<&mut Self, RSGMaterialError> {
+        let key = self.links.material_key.ok_or(RSGMaterialError::NoMaterial)?;
+        self.container.set_material_override(key, name, value)?;
+        Ok(self)
+    }
+
+    pub fn mesh(&mut self, mut mesh: RSGMesh) -> &mut Self {
+        if let (None, Some(buffers)) = (mesh.bounds_3d, self.mesh_buffers) {
+            if let Some((aabb, sphere)) = mesh.compute_bounds(buffers) {
+                mesh.bounds_3d = Some(aabb);
+                mesh.bounding_sphere = Some(sphere);
+            }
+        }
         let key = self.container.meshes.insert(RSGMeshComponent::new());
         self.links.mesh_key = Some(key);
         self.container.mesh_data.insert(key, mesh);
@@ -532,6 +2185,11 @@ impl<'a> RSGComponentBuilder<'a> {
         self
     }
 
+    pub fn camera(&mut self, camera: RSGCamera) -> &mut Self {
+        self.links.camera_key = Some(self.container.cameras.insert(RSGCameraComponent::new(camera)));
+        self
+    }
+
     pub fn links(&mut self) -> RSGComponentLinks {
         self.links
     }
@@ -604,32 +2262,94 @@ impl RSGSceneObserver {
     }
 }
 
-fn update_world_transforms<ObserverT>(
-    transform_components: RSGTransformComponentList,
+fn prune_nested_subtree_roots<ObserverT>(
     scene: &RSGScene<RSGComponentLinks, ObserverT>,
-    subtree_roots: &[RSGNodeKey]) -> RSGTransformComponentList
+    subtree_roots: &[RSGNodeKey]) -> RSGDirtySubtreeRootList
     where ObserverT: RSGObserver
 {
-    let mut transforms = transform_components;
-    for subtree_root_key in subtree_roots {
-        for (key, _) in scene.traverse(*subtree_root_key) {
-            if let Some(transform_key) = scene.get_component_links(key).transform_key {
-                let mut world_transform = transforms[transform_key].local_transform;
-                for key in scene.ancestors(key) {
-                    let links = scene.get_component_links(key);
-                    if let Some(transform_key) = links.transform_key {
-                        world_transform *= transforms[transform_key].world_transform;
-                        break;
-                    }
-                    if links.layer_key.is_some() {
-                        break;
-                    }
+    // drops duplicates, removed nodes, and roots that are inside the subtree of another root
+    let root_set: std::collections::HashSet<RSGNodeKey> = subtree_roots.iter()
+        .filter(|key| scene.is_valid(**key)).cloned().collect();
+    let mut seen = std::collections::HashSet::new();
+    let mut result = RSGDirtySubtreeRootList::new();
+    for key in subtree_roots {
+        if root_set.contains(key) && seen.insert(*key) && !scene.ancestors(*key).any(|a| root_set.contains(&a)) {
+            result.push(*key);
+        }
+    }
+    result
+}
+
+fn parent_world_transform<ObserverT>(
+    transforms: &RSGTransformComponentList,
+    scene: &RSGScene<RSGComponentLinks, ObserverT>,
+    node_key: RSGNodeKey) -> Option<glm::Mat4>
+    where ObserverT: RSGObserver
+{
+    // None when there is nothing to inherit (no transform up to the layer or scene root)
+    for key in scene.ancestors(node_key) {
+        let links = scene.get_component_links(key);
+        if let Some(transform_key) = links.transform_key {
+            return Some(transforms[transform_key].world_transform);
+        }
+        if links.layer_key.is_some() {
+            return None;
+        }
+    }
+    None
+}
+
+fn update_world_transforms<ObserverT>(
+    transform_components: RSGTransformComponentList,
+    transform_derived_data: RSGTransformDerivedData,
+    camera_components: RSGCameraComponentList,
+    scene: &RSGScene<RSGComponentLinks, ObserverT>,
+    subtree_roots: &[RSGNodeKey]) -> (RSGTransformComponentList, RSGTransformDerivedData, RSGCameraComponentList)
+    where ObserverT: RSGObserver
+{
+    // Single top-down pass per (non-nested) dirty root. stk[depth] holds the
+    // world transform the node at that depth passes down to its children.
+    let mut transforms = transform_components;
+    let mut derived = transform_derived_data;
+    let mut cameras = camera_components;
+    let mut stk = smallvec::SmallVec::<[Option<glm::Mat4>; 64]>::new();
+    for subtree_root_key in prune_nested_subtree_roots(scene, subtree_roots) {
+        stk.clear();
+        let root_parent_world = parent_world_transform(&transforms, scene, subtree_root_key);
+        for (key, depth) in scene.traverse(subtree_root_key) {
+            let depth = depth as usize;
+            stk.truncate(depth);
+            let parent_world = if depth == 0 { root_parent_world } else { stk[depth - 1] };
+            let links = scene.get_component_links(key);
+            let inherited = if let Some(transform_key) = links.transform_key {
+                let t = &mut transforms[transform_key];
+                t.update_local_transform();
+                // column vectors: the local transform applies first, then the parent's
+                t.world_transform = match parent_world.and_then(|m| t.inheritance.filter_parent_world(&m)) {
+                    Some(m) => m * t.local_transform,
+                    None => t.local_transform
+                };
+                if let Some(d) = derived.get_mut(transform_key) {
+                    *d = RSGTransformDerivedMatrices::new(&t.world_transform);
                 }
-                transforms[transform_key].world_transform = world_transform;
+                Some(t.world_transform)
+            } else if links.layer_key.is_some() {
+                None
+            } else {
+                parent_world
+            };
+            if let Some(camera_key) = links.camera_key {
+                let c = &mut cameras[camera_key];
+                c.world_transform = inherited.unwrap_or_else(glm::one);
+                c.world_properties = match links.transform_key.and_then(|transform_key| derived.get(transform_key)) {
+                    Some(d) => RSGCameraWorldTransformDerivedProperties::with_derived_matrices(&c.world_transform, d),
+                    None => RSGCameraWorldTransformDerivedProperties::new(&c.world_transform)
+                };
             }
+            stk.push(inherited);
         }
     }
-    transforms
+    (transforms, derived, cameras)
 }
 
 fn update_inherited_opacities<ObserverT>(
@@ -671,28 +2391,196 @@ pub fn update_inherited_properties<ObserverT>(
     pool.scoped(|scope| {
         let (transform_tx, transform_rx) = std::sync::mpsc::channel();
         if !dirty_world_roots.is_empty() {
-            let transforms = std::mem::replace(&mut components.transforms, Default::default());
+            let transforms = std::mem::take(&mut components.transforms);
+            let derived = std::mem::take(&mut components.transform_derived);
+            let cameras = std::mem::take(&mut components.cameras);
             scope.execute(move || {
-                transform_tx.send(update_world_transforms(transforms, scene, dirty_world_roots)).unwrap();
+                transform_tx.send(update_world_transforms(transforms, derived, cameras, scene, dirty_world_roots)).unwrap();
             });
         }
 
         let (opacity_tx, opacity_rx) = std::sync::mpsc::channel();
         if !dirty_opacity_roots.is_empty() {
-            let opacities = std::mem::replace(&mut components.opacities, Default::default());
+            let opacities = std::mem::take(&mut components.opacities);
             scope.execute(move || {
                 opacity_tx.send(update_inherited_opacities(opacities, scene, dirty_opacity_roots)).unwrap();
             });
         }
 
         if !dirty_world_roots.is_empty() {
-            components.transforms = transform_rx.recv().unwrap();
+            let (transforms, derived, cameras) = transform_rx.recv().unwrap();
+            components.transforms = transforms;
+            components.transform_derived = derived;
+            components.cameras = cameras;
         }
 
         if !dirty_opacity_roots.is_empty() {
             components.opacities = opacity_rx.recv().unwrap();
         }
     });
+    // added subtrees are always among the dirty world roots
+    components.resolve_shared_material_users(scene, dirty_world_roots);
+    // without cached bounds there is nothing to go stale
+    if !components.node_bounds.is_empty() && !dirty_world_roots.is_empty() {
+        components.stale_bounds_roots.extend_from_slice(dirty_world_roots);
+        components.stale_bounds_roots = prune_nested_subtree_roots(scene, &components.stale_bounds_roots);
+    }
+}
+
+// The world transform of a node during a top-down walk, from that of its
+// parent; same as RSGComponentContainer::world_transform without the
+// ancestor scan.
+fn node_world_transform(components: &RSGComponentContainer, links: &RSGComponentLinks, parent_world_transform: &glm::Mat4) -> glm::Mat4 {
+    match links.transform_key {
+        Some(transform_key) => components.transforms[transform_key].world_transform,
+        None if links.layer_key.is_some() => glm::one(),
+        None => *parent_world_transform
+    }
+}
+
+fn calculate_own_bounds(
+    components: &RSGComponentContainer,
+    links: &RSGComponentLinks,
+    world_transform: &glm::Mat4) -> (Option<RSGAabb>, Option<RSGSphere>)
+{
+    let mesh = match links.mesh_key.and_then(|mesh_key| components.mesh_data.get(mesh_key)) {
+        Some(mesh) => mesh,
+        None => return (None, None)
+    };
+    match mesh.bounds_3d {
+        Some(aabb) => {
+            let sphere = match mesh.bounding_sphere {
+                Some(sphere) => sphere.transformed(world_transform),
+                None => RSGSphere::from_aabb(&aabb, world_transform)
+            };
+            (Some(aabb.transformed(world_transform)), Some(sphere))
+        }
+        None => (None, None)
+    }
+}
+
+fn node_bounds_mut(node_bounds: &mut RSGNodeBoundsData, node_key: RSGNodeKey) -> &mut RSGNodeBounds {
+    if !node_bounds.contains_key(node_key) {
+        node_bounds.insert(node_key, Default::default());
+    }
+    node_bounds.get_mut(node_key).unwrap()
+}
+
+fn aggregate_subtree_bounds<ObserverT>(
+    node_bounds: &mut RSGNodeBoundsData,
+    scene: &RSGScene<RSGComponentLinks, ObserverT>,
+    node_key: RSGNodeKey)
+    where ObserverT: RSGObserver
+{
+    // expects the children's subtree_aabb to be up-to-date
+    let mut subtree_aabb = node_bounds.get(node_key).and_then(|b| b.world_aabb);
+    for child_key in scene.children(node_key) {
+        if scene.get_component_links(child_key).layer_key.is_some() {
+            continue;
+        }
+        subtree_aabb = RSGAabb::union_opt(subtree_aabb, node_bounds.get(child_key).and_then(|b| b.subtree_aabb));
+    }
+    node_bounds_mut(node_bounds, node_key).subtree_aabb = subtree_aabb;
+}
+
+pub fn update_world_bounds<ObserverT>(
+    components: &mut RSGComponentContainer,
+    scene: &RSGScene<RSGComponentLinks, ObserverT>,
+    dirty_world_roots: &[RSGNodeKey],
+    dirty_mesh_nodes: &[RSGNodeKey])
+    where ObserverT: RSGObserver
+{
+    // To be called after update_inherited_properties. Own bounds are recalculated
+    // for everything in the dirty world subtrees and for the dirty mesh nodes, then
+    // the subtree bounds are aggregated bottom-up, ending at the enclosing layer.
+    // Removing a subtree leaves the ancestors' subtree_aabb conservatively large
+    // until something else changes below them.
+    let mut node_bounds = std::mem::take(&mut components.node_bounds);
+    let world_roots = prune_nested_subtree_roots(scene, dirty_world_roots);
+    let root_set: std::collections::HashSet<RSGNodeKey> = world_roots.iter().cloned().collect();
+    let mut changed_keys = RSGDirtySubtreeRootList::new();
+
+    let mut subtree_keys = Vec::new();
+    // world transforms of the nodes on the path from the subtree root, by depth
+    let mut world_transforms: Vec<glm::Mat4> = Vec::new();
+    for root_key in world_roots.iter() {
+        subtree_keys.clear();
+        world_transforms.clear();
+        for (key, depth) in scene.traverse(*root_key) {
+            world_transforms.truncate(depth as usize);
+            let world_transform = match world_transforms.last() {
+                Some(parent_world_transform) => node_world_transform(components, scene.get_component_links(key), parent_world_transform),
+                None => components.world_transform(scene, key)
+            };
+            world_transforms.push(world_transform);
+            subtree_keys.push((key, world_transform));
+        }
+        for (key, world_transform) in subtree_keys.iter() {
+            let (world_aabb, world_sphere) = calculate_own_bounds(components, scene.get_component_links(*key), world_transform);
+            let b = node_bounds_mut(&mut node_bounds, *key);
+            b.world_aabb = world_aabb;
+            b.world_sphere = world_sphere;
+        }
+        // reverse pre-order visits all descendants of a node before the node itself
+        for (key, _) in subtree_keys.iter().rev() {
+            aggregate_subtree_bounds(&mut node_bounds, scene, *key);
+        }
+        changed_keys.push(*root_key);
+    }
+
+    for key in dirty_mesh_nodes {
+        if !scene.is_valid(*key) || scene.ancestors_with_node(*key).any(|a| root_set.contains(&a)) {
+            continue;
+        }
+        let world_transform = components.world_transform(scene, *key);
+        let (world_aabb, world_sphere) = calculate_own_bounds(components, scene.get_component_links(*key), &world_transform);
+        let b = node_bounds_mut(&mut node_bounds, *key);
+        b.world_aabb = world_aabb;
+        b.world_sphere = world_sphere;
+        aggregate_subtree_bounds(&mut node_bounds, scene, *key);
+        changed_keys.push(*key);
+    }
+
+    // Each affected ancestor once, deepest first. Depths count from the enclosing
+    // layer (or the scene root), the walks never cross it so that is enough for
+    // the order; a walk stops at the first ancestor seen before.
+    let mut ancestor_keys = std::collections::HashMap::new();
+    let mut path = Vec::new();
+    for key in changed_keys.iter() {
+        if scene.get_component_links(*key).layer_key.is_some() {
+            continue;
+        }
+        path.clear();
+        let mut top_depth = 0;
+        for ancestor_key in scene.ancestors(*key) {
+            if let Some(depth) = ancestor_keys.get(&ancestor_key) {
+                top_depth = depth + 1;
+                break;
+            }
+            path.push(ancestor_key);
+            if scene.get_component_links(ancestor_key).layer_key.is_some() {
+                break;
+            }
+        }
+        for (i, ancestor_key) in path.iter().rev().enumerate() {
+            ancestor_keys.insert(*ancestor_key, top_depth + i);
+        }
+    }
+    let mut ancestor_keys: Vec<(RSGNodeKey, usize)> = ancestor_keys.into_iter().collect();
+    ancestor_keys.sort_by_key(|e| std::cmp::Reverse(e.1));
+    for (key, _) in ancestor_keys {
+        aggregate_subtree_bounds(&mut node_bounds, scene, key);
+    }
+
+    // Every node below a dirty world root has an entry by now, so more entries
+    // than nodes means some belong to removed nodes. Dropping them all at once
+    // keeps this cheap.
+    if node_bounds.len() > scene.node_count() {
+        node_bounds.retain(|key, _| scene.is_valid(key));
+    }
+
+    components.node_bounds = node_bounds;
+    components.stale_bounds_roots.clear();
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -728,6 +2616,66 @@ impl Default for RSGCamera {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RSGDepthRange {
+    // Vulkan, D3D, Metal, WebGPU
+    ZeroToOne,
+    // OpenGL
+    MinusOneToOne
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGProjectionConventions {
+    pub depth_range: RSGDepthRange,
+    // near maps to the far end of the depth range and vice versa
+    pub reversed_z: bool,
+    // for backends where clip space Y points down (Vulkan)
+    pub flip_y: bool
+}
+
+impl Default for RSGProjectionConventions {
+    fn default() -> Self {
+        RSGProjectionConventions {
+            depth_range: RSGDepthRange::MinusOneToOne,
+            reversed_z: false,
+            flip_y: false
+        }
+    }
+}
+
+impl RSGCamera {
+    pub fn projection_matrix(&self, conventions: &RSGProjectionConventions) -> glm::Mat4 {
+        // right-handed, looking towards -Z; fov is the vertical field of view in degrees, xmag/ymag are half extents
+        let mut m = match (self, conventions.depth_range) {
+            (RSGCamera::Perspective(p), RSGDepthRange::ZeroToOne) => glm::perspective_rh_zo(p.aspect_ratio, p.fov.to_radians(), p.near, p.far),
+            (RSGCamera::Perspective(p), RSGDepthRange::MinusOneToOne) => glm::perspective_rh_no(p.aspect_ratio, p.fov.to_radians(), p.near, p.far),
+            (RSGCamera::Orthographic(o), RSGDepthRange::ZeroToOne) => glm::ortho_rh_zo(-o.xmag, o.xmag, -o.ymag, o.ymag, o.near, o.far),
+            (RSGCamera::Orthographic(o), RSGDepthRange::MinusOneToOne) => glm::ortho_rh_no(-o.xmag, o.xmag, -o.ymag, o.ymag, o.near, o.far)
+        };
+        if conventions.reversed_z {
+            // z' = w - z for [0, 1], z' = -z for [-1, 1]
+            let mut r: glm::Mat4 = glm::one();
+            r[(2, 2)] = -1.0;
+            if conventions.depth_range == RSGDepthRange::ZeroToOne {
+                r[(2, 3)] = 1.0;
+            }
+            m = r * m;
+        }
+        if conventions.flip_y {
+            m = glm::scaling(&glm::vec3(1.0, -1.0, 1.0)) * m;
+        }
+        m
+    }
+
+    pub fn view_matrix(camera_world_transform: &glm::Mat4) -> glm::Mat4 {
+        glm::inverse(camera_world_transform)
+    }
+
+    pub fn view_projection_matrix(&self, camera_world_transform: &glm::Mat4, conventions: &RSGProjectionConventions) -> glm::Mat4 {
+        self.projection_matrix(conventions) * RSGCamera::view_matrix(camera_world_transform)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct RSGCameraWorldTransformDerivedProperties {
     pub position: glm::Vec3,
@@ -745,10 +2693,18 @@ impl Default for RSGCameraWorldTransformDerivedProperties {
 
 impl RSGCameraWorldTransformDerivedProperties {
     pub fn new(world_transform: &glm::Mat4) -> Self {
+        let normal_matrix = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(world_transform)));
+        RSGCameraWorldTransformDerivedProperties::with_normal_matrix(world_transform, &normal_matrix)
+    }
+
+    pub fn with_derived_matrices(world_transform: &glm::Mat4, derived: &RSGTransformDerivedMatrices) -> Self {
+        RSGCameraWorldTransformDerivedProperties::with_normal_matrix(world_transform, &derived.normal_matrix)
+    }
+
+    fn with_normal_matrix(world_transform: &glm::Mat4, normal_matrix: &glm::Mat3) -> Self {
         let camera_world = world_transform;
         let camera_position = glm::vec3(camera_world[12], camera_world[13], camera_world[14]);
-        let scaling_correct_camera_world = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&camera_world)));
-        let camera_direction = glm::normalize(&(scaling_correct_camera_world * glm::vec3(0.0, 0.0, -1.0)));
+        let camera_direction = glm::normalize(&(normal_matrix * glm::vec3(0.0, 0.0, -1.0)));
         RSGCameraWorldTransformDerivedProperties {
             position: camera_position,
             direction: camera_direction
@@ -756,42 +2712,257 @@ impl RSGCameraWorldTransformDerivedProperties {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RSGFrustumTest {
+    Outside,
+    Intersecting,
+    Inside
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGFrustum {
+    // left, right, bottom, top, near, far; (normal, d) with the normals pointing inwards
+    pub planes: [glm::Vec4; 6]
+}
+
+impl RSGFrustum {
+    pub fn new(camera: &RSGCamera, camera_world_transform: &glm::Mat4) -> Self {
+        // view space planes (the camera looks towards -Z), fov is the vertical field of view in degrees
+        let view_planes = match camera {
+            RSGCamera::Perspective(p) => {
+                let ty = (p.fov.to_radians() * 0.5).tan();
+                let tx = ty * p.aspect_ratio;
+                [
+                    glm::vec4(1.0, 0.0, -tx, 0.0),
+                    glm::vec4(-1.0, 0.0, -tx, 0.0),
+                    glm::vec4(0.0, 1.0, -ty, 0.0),
+                    glm::vec4(0.0, -1.0, -ty, 0.0),
+                    glm::vec4(0.0, 0.0, -1.0, -p.near),
+                    glm::vec4(0.0, 0.0, 1.0, p.far)
+                ]
+            }
+            RSGCamera::Orthographic(o) => [
+                glm::vec4(1.0, 0.0, 0.0, o.xmag),
+                glm::vec4(-1.0, 0.0, 0.0, o.xmag),
+                glm::vec4(0.0, 1.0, 0.0, o.ymag),
+                glm::vec4(0.0, -1.0, 0.0, o.ymag),
+                glm::vec4(0.0, 0.0, -1.0, -o.near),
+                glm::vec4(0.0, 0.0, 1.0, o.far)
+            ]
+        };
+        // a plane P in view space is V^T * P in world space, V being the inverse camera world transform
+        let view_transposed = glm::transpose(&glm::inverse(camera_world_transform));
+        let mut planes = [glm::zero(); 6];
+        for (i, p) in view_planes.iter().enumerate() {
+            let w = view_transposed * p;
+            planes[i] = w / glm::length(&glm::vec4_to_vec3(&w));
+        }
+        RSGFrustum { planes }
+    }
+
+    #[inline]
+    fn distance(plane: &glm::Vec4, p: &glm::Vec3) -> f32 {
+        plane.x * p.x + plane.y * p.y + plane.z * p.z + plane.w
+    }
+
+    pub fn test_point(&self, p: &glm::Vec3) -> bool {
+        self.planes.iter().all(|plane| RSGFrustum::distance(plane, p) >= 0.0)
+    }
+
+    pub fn test_sphere(&self, sphere: &RSGSphere) -> RSGFrustumTest {
+        let mut result = RSGFrustumTest::Inside;
+        for plane in self.planes.iter() {
+            let d = RSGFrustum::distance(plane, &sphere.center);
+            if d < -sphere.radius {
+                return RSGFrustumTest::Outside;
+            }
+            if d < sphere.radius {
+                result = RSGFrustumTest::Intersecting;
+            }
+        }
+        result
+    }
+
+    pub fn test_aabb(&self, aabb: &RSGAabb) -> RSGFrustumTest {
+        let mut result = RSGFrustumTest::Inside;
+        for plane in self.planes.iter() {
+            // the corners furthest along and against the plane normal
+            let mut p = aabb.minimum;
+            let mut n = aabb.maximum;
+            for i in 0..3 {
+                if plane[i] >= 0.0 {
+                    p[i] = aabb.maximum[i];
+                    n[i] = aabb.minimum[i];
+                }
+            }
+            if RSGFrustum::distance(plane, &p) < 0.0 {
+                return RSGFrustumTest::Outside;
+            }
+            if RSGFrustum::distance(plane, &n) < 0.0 {
+                result = RSGFrustumTest::Intersecting;
+            }
+        }
+        result
+    }
+}
+
 #[inline]
-fn calculate_sorting_distance(world_transform: &glm::Mat4, bounds: &RSGAabb,
+fn calculate_sorting_distance(world_transform: &glm::Mat4, bounds: Option<&RSGAabb>,
     camera_properties: &RSGCameraWorldTransformDerivedProperties) -> f32
 {
-    let center = bounds.center();
+    // meshes without bounds are sorted by their origin
+    let center = bounds.map(|b| b.center()).unwrap_or_else(glm::zero);
     let world_center = glm::vec4_to_vec3(&(world_transform * glm::vec4(center.x, center.y, center.z, 1.0)));
     glm::dot(&(world_center - camera_properties.position), &camera_properties.direction)
 }
 
 pub type RSGRenderList = Vec<(RSGNodeKey, f32)>;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RSGCullingStats {
+    // mesh nodes not drawn, rejected based on their own bounds or as part of a subtree
+    pub culled_nodes: u32,
+    // subtrees (possibly a single node) rejected as a whole based on the aggregated bounds, their nodes are not visited
+    pub culled_subtrees: u32
+}
+
+// The bounds cached by update_world_bounds are used unless the node is in a
+// subtree moved or added since; those and nodes it has never seen get theirs
+// calculated here.
+fn node_world_aabb(
+    components: &RSGComponentContainer,
+    links: &RSGComponentLinks,
+    key: RSGNodeKey,
+    world_transform: &glm::Mat4,
+    stale: bool) -> Option<RSGAabb>
+{
+    match components.node_bounds.get(key).and_then(|b| b.world_aabb).filter(|_| !stale) {
+        Some(aabb) => Some(aabb),
+        None => calculate_own_bounds(components, links, world_transform).0
+    }
+}
+
+fn count_layer_mesh_nodes<ObserverT>(
+    scene: &RSGScene<RSGComponentLinks, ObserverT>,
+    layer_node_key: RSGNodeKey,
+    subtree_root_key: RSGNodeKey) -> (u32, bool)
+    where ObserverT: RSGObserver
+{
+    // Counts what build_layer_render_lists would have visited: in pre-order up
+    // to and including the mesh of the first nested layer node, true when one
+    // was reached.
+    let mut count = 0;
+    for (key, _) in scene.traverse(subtree_root_key) {
+        let links = scene.get_component_links(key);
+        if links.mesh_key.is_some() {
+            count += 1;
+        }
+        if links.layer_key.is_some() && key != layer_node_key {
+            return (count, true);
+        }
+    }
+    (count, false)
+}
+
 pub fn build_layer_render_lists<ObserverT>(
     components: &RSGComponentContainer,
     scene: &RSGScene<RSGComponentLinks, ObserverT>,
     layer_node_key: RSGNodeKey,
     camera_properties_3d: Option<RSGCameraWorldTransformDerivedProperties>,
+    frustum: Option<&RSGFrustum>,
     opaque_list: &mut RSGRenderList,
-    alpha_list: &mut RSGRenderList)
+    alpha_list: &mut RSGRenderList) -> RSGCullingStats
     where ObserverT: RSGObserver
 {
+    // Nodes without usable bounds are never culled. Aggregated bounds, when
+    // update_world_bounds maintains them, allow skipping entire subtrees; their
+    // mesh nodes are still walked for the stats, but not tested. Subtrees
+    // added or moved since the last update_world_bounds (the stale bounds
+    // roots) and their ancestors are not rejected as a whole, and the nodes in
+    // them are tested with freshly calculated bounds. Mesh changes are only
+    // picked up by update_world_bounds. Without camera properties and frustum,
+    // those of the layer's active camera are used; a layer without one is 2D.
     opaque_list.clear();
     alpha_list.clear();
+    let mut stats = RSGCullingStats::default();
+    assert!(scene.get_component_links(layer_node_key).layer_key.is_some());
+    let active_frustum;
+    let (camera_properties_3d, frustum) = match (camera_properties_3d, frustum) {
+        (None, None) => match components.active_camera(scene, layer_node_key) {
+            Some(camera) => {
+                active_frustum = camera.frustum();
+                (Some(camera.world_properties), Some(&active_frustum))
+            }
+            None => (None, None)
+        },
+        given => given
+    };
+
+    // the aggregated bounds of these do not cover everything below them
+    let mut stale_root_keys = std::collections::HashSet::new();
+    let mut stale_ancestor_keys = std::collections::HashSet::new();
+    if frustum.is_some() {
+        stale_root_keys.extend(components.stale_bounds_roots.iter().filter(|key| scene.is_valid(**key)));
+        for root_key in stale_root_keys.iter() {
+            for key in scene.ancestors_with_node(*root_key) {
+                if !stale_ancestor_keys.insert(key) {
+                    break;
+                }
+            }
+        }
+    }
 
+    // depth-first, pre-order; true when the node is known to be fully inside
+    // the frustum, true when it is in a stale subtree, and the parent's world
+    // transform
+    let mut stk = smallvec::SmallVec::<[(RSGNodeKey, bool, bool, glm::Mat4); 128]>::new();
+    stk.push((layer_node_key, frustum.is_none(), false, glm::one()));
+    let mut children = smallvec::SmallVec::<[RSGNodeKey; 16]>::new();
     let mut stacking_order_2d = 0;
-    for (key, _) in scene.traverse(layer_node_key) {
+    let mut reached_nested_layer = false;
+    while let Some((key, mut inside, mut stale, parent_world_transform)) = stk.pop() {
+        if reached_nested_layer {
+            // collecting stops at the first nested layer, after its own mesh
+            break;
+        }
         let links = scene.get_component_links(key);
-        if key == layer_node_key {
-            assert!(links.layer_key.is_some());
+        reached_nested_layer = links.layer_key.is_some() && key != layer_node_key;
+        stale = stale || stale_root_keys.contains(&key);
+        if let (false, Some(f)) = (inside, frustum) {
+            let subtree_aabb = components.node_bounds.get(key)
+                .filter(|_| !stale && !stale_ancestor_keys.contains(&key))
+                .and_then(|b| b.subtree_aabb);
+            match subtree_aabb.map(|aabb| f.test_aabb(&aabb)) {
+                Some(RSGFrustumTest::Outside) => {
+                    let (count, nested_layer) = count_layer_mesh_nodes(scene, layer_node_key, key);
+                    stats.culled_subtrees += 1;
+                    stats.culled_nodes += count;
+                    reached_nested_layer = nested_layer;
+                    continue;
+                }
+                Some(RSGFrustumTest::Inside) => inside = true,
+                _ => {}
+            }
+        }
+        let world_transform = node_world_transform(components, links, &parent_world_transform);
+        if !reached_nested_layer {
+            children.clear();
+            children.extend(scene.children(key));
+            stk.extend(children.iter().rev().map(|child_key| (*child_key, inside, stale, world_transform)));
         }
+
         if let Some(mesh_key) = links.mesh_key {
+            if let (false, Some(f)) = (inside, frustum) {
+                if let Some(aabb) = node_world_aabb(components, links, key, &world_transform, stale) {
+                    if f.test_aabb(&aabb) == RSGFrustumTest::Outside {
+                        stats.culled_nodes += 1;
+                        continue;
+                    }
+                }
+            }
             let mesh_data = components.mesh_data.get(mesh_key).unwrap();
             if let Some(cam_props) = camera_properties_3d {
-                let sort_dist = calculate_sorting_distance(
-                    &components.transforms[links.transform_key.unwrap()].world_transform,
-                    &mesh_data.bounds_3d.unwrap(),
-                    &cam_props);
+                let sort_dist = calculate_sorting_distance(&world_transform, mesh_data.bounds_3d.as_ref(), &cam_props);
                 if components.is_opaque(links) {
                     // front to back
                     let pos = opaque_list.binary_search_by(|e| e.1.partial_cmp(&sort_dist).unwrap()).unwrap_or_else(|i| i);
@@ -811,13 +2982,523 @@ pub fn build_layer_render_lists<ObserverT>(
                 stacking_order_2d += 1;
             }
         }
-        if links.layer_key.is_some() && key != layer_node_key {
-            break;
-        }
     }
 
     if camera_properties_3d.is_none() {
         // tree order was back to front, so reverse to get front to back
         opaque_list.reverse();
     }
+
+    stats
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGRenderPass {
+    Opaque,
+    Alpha
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RSGRenderListSortMode {
+    // keep the order from build_layer_render_lists, batching only what is already adjacent
+    Depth,
+    // group opaque entries by shader set, graphics state and mesh buffers, then front to back;
+    // alpha entries stay back to front
+    State
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RSGSortKey(pub u64);
+
+impl RSGSortKey {
+    pub const LAYER_BITS: u32 = 10;
+    pub const SHADER_SET_BITS: u32 = 11;
+    pub const STATE_BITS: u32 = 11;
+    pub const MESH_BITS: u32 = 13;
+    pub const DEPTH_BITS: u32 = 18;
+    pub const ORDER_BITS: u32 = Self::SHADER_SET_BITS + Self::STATE_BITS + Self::MESH_BITS;
+
+    // state first:  | layer 10 | pass 1 | shader set 11 | state 11 | mesh 13 | depth 18 |
+    // depth first:  | layer 10 | pass 1 | depth 18 | order 35 |
+    // Fields saturate: values past their range share the last one. layer is the index of
+    // the layer in the frame; layers from the last value on are not ordered among each
+    // other anymore. shader set, state and mesh are small indices, not ids or hashes, and
+    // saturating them only costs batching. depth is 0 for what is drawn first: the nearest
+    // for opaque entries, the farthest for alpha entries. order is the position in the
+    // source list, so entries of the same depth keep the order they were given in.
+    pub fn state_first(layer: u32, pass: RSGRenderPass, shader_set: u64, state: u64, mesh: u64, depth: f64) -> Self {
+        let ids = (Self::saturate(shader_set, Self::SHADER_SET_BITS) << Self::STATE_BITS
+            | Self::saturate(state, Self::STATE_BITS)) << Self::MESH_BITS
+            | Self::saturate(mesh, Self::MESH_BITS);
+        Self::with_layer_and_pass(layer, pass, ids << Self::DEPTH_BITS | Self::quantize_depth(depth))
+    }
+
+    pub fn depth_first(layer: u32, pass: RSGRenderPass, depth: f64, order: u64) -> Self {
+        Self::with_layer_and_pass(layer, pass, Self::quantize_depth(depth) << Self::ORDER_BITS | Self::saturate(order, Self::ORDER_BITS))
+    }
+
+    fn saturate(value: u64, count: u32) -> u64 {
+        value.min((1 << count) - 1)
+    }
+
+    fn quantize_depth(depth: f64) -> u64 {
+        (depth.clamp(0.0, 1.0) * ((1u64 << Self::DEPTH_BITS) - 1) as f64) as u64
+    }
+
+    fn with_layer_and_pass(layer: u32, pass: RSGRenderPass, key: u64) -> Self {
+        let layer = Self::saturate(layer as u64, Self::LAYER_BITS);
+        let pass = match pass {
+            RSGRenderPass::Opaque => 0,
+            RSGRenderPass::Alpha => 1
+        };
+        RSGSortKey((layer << 1 | pass) << (Self::ORDER_BITS + Self::DEPTH_BITS) | key)
+    }
+}
+
+// What has to be the same for entries to be drawn without switching pipelines or buffers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RSGDrawState {
+    pub shader_set_id: Option<u32>,
+    pub graphics_state: Option<RSGMaterialGraphicsState>,
+    pub vertex_buffers: smallvec::SmallVec<[u32; 4]>,
+    pub index_buffers: smallvec::SmallVec<[u32; 1]>
+}
+
+impl RSGDrawState {
+    pub fn new(components: &RSGComponentContainer, links: &RSGComponentLinks) -> Self {
+        let material = links.material_key.and_then(|key| components.material(key));
+        let mesh = links.mesh_key.and_then(|key| components.mesh_data.get(key));
+        let mut vertex_buffers = smallvec::SmallVec::new();
+        let mut index_buffers = smallvec::SmallVec::new();
+        if let Some(mesh) = mesh {
+            vertex_buffers.extend(mesh.vertex_views.iter().map(|v| v.buffer_id));
+            for view in mesh.submeshes.iter().filter_map(|s| s.index_view) {
+                let buffer_id = match view {
+                    RSGMeshIndexBufferView::U16(v) | RSGMeshIndexBufferView::U32(v) => v.buffer_id
+                };
+                if !index_buffers.contains(&buffer_id) {
+                    index_buffers.push(buffer_id);
+                }
+            }
+        }
+        RSGDrawState {
+            shader_set_id: material.map(|m| m.shader_set_id),
+            graphics_state: components.effective_graphics_state(links),
+            vertex_buffers,
+            index_buffers
+        }
+    }
+}
+
+fn hash_of<T: std::hash::Hash>(value: &T) -> u64 {
+    use std::hash::Hasher;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RSGSortedRenderEntry {
+    pub node_key: RSGNodeKey,
+    // as in the source render list
+    pub distance: f32,
+    pub layer: u32,
+    pub pass: RSGRenderPass,
+    pub sort_key: RSGSortKey,
+    pub draw_state: RSGDrawState
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGRenderBatch {
+    // into RSGSortedRenderList::entries, all with the same layer, pass and draw state
+    pub first: usize,
+    pub count: usize
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RSGSortedRenderList {
+    pub entries: Vec<RSGSortedRenderEntry>,
+    pub batches: Vec<RSGRenderBatch>,
+    // the sort key indices, in the order of first use
+    shader_set_indices: std::collections::HashMap<Option<u32>, u64>,
+    graphics_state_indices: std::collections::HashMap<Option<RSGMaterialGraphicsState>, u64>,
+    mesh_indices: std::collections::HashMap<RSGDrawBuffers, u64>
+}
+
+// vertex and index buffer ids
+type RSGDrawBuffers = (smallvec::SmallVec<[u32; 4]>, smallvec::SmallVec<[u32; 1]>);
+
+fn sort_key_index<T: Eq + std::hash::Hash>(indices: &mut std::collections::HashMap<T, u64>, value: T) -> u64 {
+    let next = indices.len() as u64;
+    *indices.entry(value).or_insert(next)
+}
+
+impl RSGSortedRenderList {
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.batches.clear();
+        self.shader_set_indices.clear();
+        self.graphics_state_indices.clear();
+        self.mesh_indices.clear();
+    }
+
+    // Adds the entries of a list made by build_layer_render_lists. Lists of
+    // multiple layers and passes can be added, their order is given by the
+    // layer index and then the pass. Such a list is sorted in drawing order, so
+    // the depth of an entry is its distance scaled between those of the first
+    // and the last entry; this also covers 2D lists, whose distances are the
+    // stacking order.
+    pub fn add_render_list<ObserverT>(&mut self,
+        components: &RSGComponentContainer,
+        scene: &RSGScene<RSGComponentLinks, ObserverT>,
+        render_list: &RSGRenderList,
+        layer: u32,
+        pass: RSGRenderPass,
+        mode: RSGRenderListSortMode)
+        where ObserverT: RSGObserver
+    {
+        let depth_first = pass == RSGRenderPass::Alpha || mode == RSGRenderListSortMode::Depth;
+        let (first, last) = match (render_list.first(), render_list.last()) {
+            (Some(first), Some(last)) => (first.1 as f64, last.1 as f64),
+            _ => return
+        };
+        let depth = |distance: f32| if first == last { 0.0 } else { (distance as f64 - first) / (last - first) };
+        for (order, (node_key, distance)) in render_list.iter().enumerate() {
+            let draw_state = RSGDrawState::new(components, scene.get_component_links(*node_key));
+            let sort_key = if depth_first {
+                RSGSortKey::depth_first(layer, pass, depth(*distance), order as u64)
+            } else {
+                let shader_set = sort_key_index(&mut self.shader_set_indices, draw_state.shader_set_id);
+                let state = sort_key_index(&mut self.graphics_state_indices, draw_state.graphics_state);
+                let mesh = sort_key_index(&mut self.mesh_indices, (draw_state.vertex_buffers.clone(), draw_state.index_buffers.clone()));
+                RSGSortKey::state_first(layer, pass, shader_set, state, mesh, depth(*distance))
+            };
+            self.entries.push(RSGSortedRenderEntry {
+                node_key: *node_key,
+                distance: *distance,
+                layer,
+                pass,
+                sort_key,
+                draw_state
+            });
+        }
+    }
+
+    // Sorts by key and groups consecutive entries with the same draw state. The
+    // sort is stable, so entries with equal keys stay in the order they were added.
+    pub fn sort_and_batch(&mut self) {
+        self.entries.sort_by_key(|e| e.sort_key);
+        self.batches.clear();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let compatible = match self.batches.last() {
+                Some(batch) => {
+                    let first = &self.entries[batch.first];
+                    first.layer == entry.layer && first.pass == entry.pass && first.draw_state == entry.draw_state
+                }
+                None => false
+            };
+            if compatible {
+                self.batches.last_mut().unwrap().count += 1;
+            } else {
+                self.batches.push(RSGRenderBatch { first: i, count: 1 });
+            }
+        }
+    }
+
+    pub fn batch_entries(&self, batch_index: usize) -> &[RSGSortedRenderEntry] {
+        let b = &self.batches[batch_index];
+        &self.entries[b.first..b.first + b.count]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RSGInstancingOptions {
+    // material properties taken per instance, the values of all others have to match;
+    // textures and samplers cannot be per instance and are ignored here
+    pub per_instance_properties: Vec<String>,
+    // smaller groups are left as regular draws
+    pub min_instance_count: usize
+}
+
+impl Default for RSGInstancingOptions {
+    fn default() -> Self {
+        RSGInstancingOptions {
+            per_instance_properties: Vec::new(),
+            min_instance_count: 2
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGInstancedDraw {
+    // the mesh, material and graphics state of the draw are those of this node
+    pub node_key: RSGNodeKey,
+    // into RSGInstancedRenderList::instance_nodes
+    pub first_instance: usize,
+    pub instance_count: usize,
+    // into RSGInstancedRenderList::instance_data, in floats
+    pub data_offset: usize,
+    pub data_stride: usize
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RSGInstancedRenderList {
+    pub draws: Vec<RSGInstancedDraw>,
+    pub instance_nodes: Vec<RSGNodeKey>,
+    // Per instance: the world matrix (column-major), the inherited opacity, then
+    // the per-instance properties declared by the shader set, in the order of
+    // the options. Integer values are stored bit for bit.
+    pub instance_data: Vec<f32>,
+    // entries not drawn instanced, in their original order
+    pub remaining: RSGRenderList
+}
+
+impl RSGInstancedRenderList {
+    pub fn clear(&mut self) {
+        self.draws.clear();
+        self.instance_nodes.clear();
+        self.instance_data.clear();
+        self.remaining.clear();
+    }
+
+    pub fn draw_instances(&self, draw_index: usize) -> &[RSGNodeKey] {
+        let d = &self.draws[draw_index];
+        &self.instance_nodes[d.first_instance..d.first_instance + d.instance_count]
+    }
+
+    pub fn instance_values(&self, draw_index: usize, instance: usize) -> &[f32] {
+        let d = &self.draws[draw_index];
+        let offset = d.data_offset + instance * d.data_stride;
+        &self.instance_data[offset..offset + d.data_stride]
+    }
+}
+
+fn push_value_floats(value: &RSGMaterialCustomValue, data: &mut Vec<f32>) {
+    let ints = |v: &[i32], data: &mut Vec<f32>| data.extend(v.iter().map(|i| f32::from_bits(*i as u32)));
+    match value {
+        RSGMaterialCustomValue::Float(v) => data.push(*v),
+        RSGMaterialCustomValue::Vec2(v) => data.extend_from_slice(v.as_slice()),
+        RSGMaterialCustomValue::Vec3(v) => data.extend_from_slice(v.as_slice()),
+        RSGMaterialCustomValue::Vec4(v) => data.extend_from_slice(v.as_slice()),
+        RSGMaterialCustomValue::Int(v) => ints(&[*v], data),
+        RSGMaterialCustomValue::Int2(v) => ints(v.as_slice(), data),
+        RSGMaterialCustomValue::Int3(v) => ints(v.as_slice(), data),
+        RSGMaterialCustomValue::Int4(v) => ints(v.as_slice(), data),
+        RSGMaterialCustomValue::Mat2(m) => data.extend_from_slice(m.as_slice()),
+        RSGMaterialCustomValue::Mat3(m) => data.extend_from_slice(m.as_slice()),
+        RSGMaterialCustomValue::Mat4(m) => data.extend_from_slice(m.as_slice()),
+        RSGMaterialCustomValue::Texture(_, _) | RSGMaterialCustomValue::Sampler(_) => {}
+    }
+}
+
+struct RSGInstanceCandidate<'a> {
+    material_key: RSGMaterialKey,
+    material: &'a RSGMaterial,
+    graphics_state: RSGMaterialGraphicsState,
+    mesh: &'a RSGMesh,
+    shader_set: &'a RSGMaterialShaderSet,
+    // the values of the per-instance properties the shader set has, resources excluded
+    instance_values: smallvec::SmallVec<[RSGMaterialCustomValue; 4]>
+}
+
+impl<'a> RSGInstanceCandidate<'a> {
+    // None also when a per-instance value is not a plain value of the property's
+    // type, e.g. a builtin, as there is nothing to put in the instance data then
+    fn new(components: &'a RSGComponentContainer, links: &RSGComponentLinks, options: &RSGInstancingOptions) -> Option<Self> {
+        let material_key = links.material_key?;
+        let material = components.material(material_key)?;
+        let shader_set = components.shader_sets.get(material.shader_set_id)?;
+        let mut instance_values = smallvec::SmallVec::new();
+        for property in options.per_instance_properties.iter()
+            .filter_map(|name| shader_set.properties.iter().find(|p| p.name() == name))
+            .filter(|p| !p.default_value().is_resource())
+        {
+            match components.material_property_value(material_key, property.name()) {
+                Some(RSGMaterialPropertyValue::Custom(v)) if property.is_same_type(v) => instance_values.push(*v),
+                _ => return None
+            }
+        }
+        Some(RSGInstanceCandidate {
+            material_key,
+            material,
+            graphics_state: components.effective_graphics_state(links)?,
+            mesh: components.mesh_data.get(links.mesh_key?)?,
+            shader_set,
+            instance_values
+        })
+    }
+
+    fn hash(&self) -> u64 {
+        hash_of(&(self.material.shader_set_id, &self.graphics_state, &self.mesh.vertex_views, &self.mesh.submeshes))
+    }
+
+    fn is_compatible(&self, other: &Self, components: &RSGComponentContainer, options: &RSGInstancingOptions) -> bool {
+        self.material.shader_set_id == other.material.shader_set_id
+            && self.graphics_state == other.graphics_state
+            && self.mesh.vertex_views == other.mesh.vertex_views
+            && self.mesh.submeshes == other.mesh.submeshes
+            && self.shader_set.properties.iter()
+                .filter(|p| !options.per_instance_properties.iter().any(|name| name == p.name()))
+                .all(|p| components.material_property_value(self.material_key, p.name())
+                    == components.material_property_value(other.material_key, p.name()))
+    }
+}
+
+// Groups entries of an opaque render list that share a mesh, a shader set, the
+// effective graphics state and all but the per-instance property values, into
+// instanced draws. Draws are in the order of their first instance; the order
+// within an instanced draw is not meaningful beyond that.
+pub fn build_instanced_draws<ObserverT>(
+    components: &RSGComponentContainer,
+    scene: &RSGScene<RSGComponentLinks, ObserverT>,
+    opaque_list: &RSGRenderList,
+    options: &RSGInstancingOptions,
+    instanced: &mut RSGInstancedRenderList)
+    where ObserverT: RSGObserver
+{
+    instanced.clear();
+    let mut candidates = Vec::with_capacity(opaque_list.len());
+    // candidate index -> the entries of its group, for the first candidate of each group
+    let mut groups: Vec<(usize, Vec<usize>)> = Vec::new();
+    let mut buckets: std::collections::HashMap<u64, smallvec::SmallVec<[usize; 2]>> = Default::default();
+    let mut remaining = Vec::new();
+    for (i, (node_key, _)) in opaque_list.iter().enumerate() {
+        let candidate = match RSGInstanceCandidate::new(components, scene.get_component_links(*node_key), options) {
+            Some(c) => c,
+            None => {
+                remaining.push(i);
+                candidates.push(None);
+                continue;
+            }
+        };
+        let bucket = buckets.entry(candidate.hash()).or_default();
+        let group = bucket.iter().copied().find(|g| {
+            let first = candidates[groups[*g].0].as_ref().unwrap();
+            candidate.is_compatible(first, components, options)
+        });
+        match group {
+            Some(g) => groups[g].1.push(i),
+            None => {
+                bucket.push(groups.len());
+                groups.push((i, vec![i]));
+            }
+        }
+        candidates.push(Some(candidate));
+    }
+
+    for (first, entries) in groups {
+        if entries.len() < options.min_instance_count.max(1) {
+            remaining.extend(entries);
+            continue;
+        }
+        let data_offset = instanced.instance_data.len();
+        for i in entries.iter() {
+            let node_key = opaque_list[*i].0;
+            let links = scene.get_component_links(node_key);
+            let candidate = candidates[*i].as_ref().unwrap();
+            let world_transform = components.world_transform(scene, node_key);
+            instanced.instance_data.extend_from_slice(world_transform.as_slice());
+            instanced.instance_data.push(components.inherited_opacity(links));
+            for value in candidate.instance_values.iter() {
+                push_value_floats(value, &mut instanced.instance_data);
+            }
+            instanced.instance_nodes.push(node_key);
+        }
+        instanced.draws.push(RSGInstancedDraw {
+            node_key: opaque_list[first].0,
+            first_instance: instanced.instance_nodes.len() - entries.len(),
+            instance_count: entries.len(),
+            data_offset,
+            data_stride: (instanced.instance_data.len() - data_offset) / entries.len()
+        });
+    }
+
+    remaining.sort_unstable();
+    instanced.remaining.extend(remaining.into_iter().map(|i| opaque_list[i]));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RSGDrawValueRange {
+    pub node_key: RSGNodeKey,
+    pub shader_set_id: Option<u32>,
+    // into RSGDrawValueTable::values, one value per shader set property, in declaration order
+    pub first: usize,
+    pub count: usize
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RSGDrawValueTable {
+    pub values: Vec<RSGMaterialCustomValue>,
+    // one entry per render list entry
+    pub draws: Vec<RSGDrawValueRange>
+}
+
+impl RSGDrawValueTable {
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.draws.clear();
+    }
+
+    pub fn draw_values(&self, draw_index: usize) -> &[RSGMaterialCustomValue] {
+        let d = &self.draws[draw_index];
+        &self.values[d.first..d.first + d.count]
+    }
+}
+
+fn convert_builtin_value(value: RSGMaterialCustomValue, property: &RSGMaterialProperty) -> RSGMaterialCustomValue {
+    // e.g. a normal matrix is often declared as a mat4 in uniform blocks
+    match (value, property) {
+        (RSGMaterialCustomValue::Mat3(m), RSGMaterialProperty::Mat4(_, _)) => RSGMaterialCustomValue::Mat4(glm::mat3_to_mat4(&m)),
+        (RSGMaterialCustomValue::Mat4(m), RSGMaterialProperty::Mat3(_, _)) => RSGMaterialCustomValue::Mat3(glm::mat4_to_mat3(&m)),
+        (v, p) if p.is_same_type(&v) => v,
+        (_, p) => p.default_value()
+    }
+}
+
+pub fn resolve_render_list_values<ObserverT>(
+    components: &RSGComponentContainer,
+    scene: &RSGScene<RSGComponentLinks, ObserverT>,
+    render_list: &RSGRenderList,
+    camera: Option<&RSGCameraComponent>,
+    conventions: &RSGProjectionConventions,
+    table: &mut RSGDrawValueTable)
+    where ObserverT: RSGObserver
+{
+    // Properties without a value in the material, or with a value of the wrong
+    // type, get the default declared in the shader set. Without a camera the
+    // view and projection matrices are identity.
+    table.clear();
+    let (view, projection) = match camera {
+        Some(c) => (c.view_matrix(), c.projection_matrix(conventions)),
+        None => (glm::one(), glm::one())
+    };
+    for (node_key, _) in render_list {
+        let links = scene.get_component_links(*node_key);
+        let first = table.values.len();
+        let material = links.material_key.and_then(|key| components.material(key));
+        let shader_set = material.and_then(|m| components.shader_sets.get(m.shader_set_id));
+        if let (Some(material_key), Some(shader_set)) = (links.material_key, shader_set) {
+            let transform_key = components.inherited_transform_key(scene, *node_key);
+            let model = transform_key.map_or_else(glm::one, |key| components.transforms[key].world_transform);
+            let normal = match transform_key.and_then(|key| components.transform_derived.get(key)) {
+                Some(d) => d.normal_matrix,
+                None => glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&model)))
+            };
+            let builtins = RSGMaterialBuiltinMatrices { model, view, projection, normal };
+            for property in shader_set.properties.iter() {
+                let value = match components.material_property_value(material_key, property.name()) {
+                    Some(RSGMaterialPropertyValue::Builtin(b)) => convert_builtin_value(builtins.value(*b), property),
+                    Some(RSGMaterialPropertyValue::Custom(v)) if property.is_same_type(v) => *v,
+                    _ => property.default_value()
+                };
+                table.values.push(value);
+            }
+        }
+        table.draws.push(RSGDrawValueRange {
+            node_key: *node_key,
+            shader_set_id: material.map(|m| m.shader_set_id),
+            first,
+            count: table.values.len() - first
+        });
+    }
 }
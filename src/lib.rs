@@ -1,2 +1,8 @@
 pub mod scene;
 pub mod components;
+pub mod uniforms;
+pub mod pipeline;
+pub mod materials;
+pub mod shaders;
+#[cfg(feature = "shader-translation")]
+pub mod translation;
@@ -0,0 +1,438 @@
+use crate::components::*;
+use nalgebra_glm as glm;
+
+// The built-in shader sets are registered in every RSGMaterialShaderSetRegistry
+// with their fixed ids, so materials can be made without access to it. The ids
+// are at the top of the range, the ones handed out by register start at 0.
+//
+// The sources are Vulkan-style GLSL: one std140 uniform block at binding 0 with
+// the non-resource properties in declaration order, then textures and samplers
+// in declaration order from binding 1. Vertex inputs use the locations
+// position 0, normal 1, tangent 2, color 3, texcoord 4. Colors are premultiplied,
+// and textures without an id are expected to be bound as opaque white.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGBuiltinShaderSet {
+    FlatColor,
+    VertexColor,
+    Textured,
+    Sprite2D,
+    PbrMetallicRoughness
+}
+
+impl RSGBuiltinShaderSet {
+    pub const ALL: [RSGBuiltinShaderSet; 5] = [
+        RSGBuiltinShaderSet::FlatColor,
+        RSGBuiltinShaderSet::VertexColor,
+        RSGBuiltinShaderSet::Textured,
+        RSGBuiltinShaderSet::Sprite2D,
+        RSGBuiltinShaderSet::PbrMetallicRoughness
+    ];
+
+    // the first reserved id; register never hands out ids from here on
+    pub const FIRST_ID: u32 = 0xffff_ff00;
+
+    pub fn id(&self) -> u32 {
+        RSGBuiltinShaderSet::FIRST_ID + *self as u32
+    }
+
+    pub fn from_id(shader_set_id: u32) -> Option<Self> {
+        let index = shader_set_id.checked_sub(RSGBuiltinShaderSet::FIRST_ID)?;
+        RSGBuiltinShaderSet::ALL.get(index as usize).copied()
+    }
+
+    pub fn shader_set(&self) -> RSGMaterialShaderSet {
+        let (vertex_shader, fragment_shader) = match self {
+            RSGBuiltinShaderSet::FlatColor => (FLAT_COLOR_VERT, FLAT_COLOR_FRAG),
+            RSGBuiltinShaderSet::VertexColor => (VERTEX_COLOR_VERT, VERTEX_COLOR_FRAG),
+            RSGBuiltinShaderSet::Textured => (TEXTURED_VERT, TEXTURED_FRAG),
+            RSGBuiltinShaderSet::Sprite2D => (SPRITE_2D_VERT, SPRITE_2D_FRAG),
+            RSGBuiltinShaderSet::PbrMetallicRoughness => (PBR_VERT, PBR_FRAG)
+        };
+        RSGMaterialShaderSet {
+            vertex_shader: vertex_shader.to_owned(),
+            fragment_shader: fragment_shader.to_owned(),
+            properties: self.properties(),
+            vertex_inputs: self.vertex_inputs(),
+            alpha_cutoff: None
+        }
+    }
+
+    fn vertex_inputs(&self) -> Vec<RSGShaderVertexInput> {
+        let input = RSGShaderVertexInput::new;
+        let position = input(RSGMeshVertexSemantic::Position, RSGMeshVertexInputType::Vec3, 0);
+        let texcoord = input(RSGMeshVertexSemantic::TexCoord(0), RSGMeshVertexInputType::Vec2, 4);
+        match self {
+            RSGBuiltinShaderSet::FlatColor => vec![position],
+            RSGBuiltinShaderSet::VertexColor => vec![position, input(RSGMeshVertexSemantic::Color(0), RSGMeshVertexInputType::Vec4, 3)],
+            RSGBuiltinShaderSet::Textured => vec![position, texcoord],
+            RSGBuiltinShaderSet::Sprite2D => vec![input(RSGMeshVertexSemantic::Position, RSGMeshVertexInputType::Vec2, 0), texcoord],
+            RSGBuiltinShaderSet::PbrMetallicRoughness => vec![
+                position,
+                input(RSGMeshVertexSemantic::Normal, RSGMeshVertexInputType::Vec3, 1),
+                texcoord
+            ]
+        }
+    }
+
+    fn properties(&self) -> Vec<RSGMaterialProperty> {
+        let mat4 = |name: &str| RSGMaterialProperty::Mat4(name.to_owned(), glm::one());
+        let white = |name: &str| RSGMaterialProperty::Vec4(name.to_owned(), glm::vec4(1.0, 1.0, 1.0, 1.0));
+        let texture = |name: &str| RSGMaterialProperty::Texture(name.to_owned(), RSGTextureType::Texture2D, None);
+        let sampler = |name: &str| RSGMaterialProperty::Sampler(name.to_owned(), Default::default());
+        match self {
+            RSGBuiltinShaderSet::FlatColor => vec![mat4("mvp"), white("color")],
+            RSGBuiltinShaderSet::VertexColor => vec![mat4("mvp")],
+            RSGBuiltinShaderSet::Textured => vec![
+                mat4("mvp"),
+                white("color"),
+                texture("base_color"),
+                sampler("base_color_sampler")
+            ],
+            RSGBuiltinShaderSet::Sprite2D => vec![
+                mat4("mvp"),
+                white("color"),
+                // offset and scale of the texture coordinates, for atlases
+                RSGMaterialProperty::Vec4("uv_rect".to_owned(), glm::vec4(0.0, 0.0, 1.0, 1.0)),
+                texture("sprite"),
+                sampler("sprite_sampler")
+            ],
+            RSGBuiltinShaderSet::PbrMetallicRoughness => vec![
+                mat4("model"),
+                mat4("view"),
+                mat4("view_projection"),
+                RSGMaterialProperty::Mat3("normal_matrix".to_owned(), glm::one()),
+                white("base_color_factor"),
+                RSGMaterialProperty::Vec3("emissive_factor".to_owned(), glm::zero()),
+                RSGMaterialProperty::Float("metallic_factor".to_owned(), 1.0),
+                RSGMaterialProperty::Float("roughness_factor".to_owned(), 1.0),
+                // the direction the light travels in, in world space
+                RSGMaterialProperty::Vec3("light_direction".to_owned(), glm::vec3(0.0, -1.0, 0.0)),
+                RSGMaterialProperty::Vec3("light_color".to_owned(), glm::vec3(1.0, 1.0, 1.0)),
+                texture("base_color_texture"),
+                // roughness in green, metalness in blue, as in glTF
+                texture("metallic_roughness_texture"),
+                sampler("material_sampler")
+            ]
+        }
+    }
+}
+
+fn builtin(value: RSGMaterialBuiltinValue) -> RSGMaterialPropertyValue {
+    RSGMaterialPropertyValue::Builtin(value)
+}
+
+fn custom(value: RSGMaterialCustomValue) -> RSGMaterialPropertyValue {
+    RSGMaterialPropertyValue::Custom(value)
+}
+
+impl RSGMaterial {
+    // all properties get a value, the defaults of the shader set for the ones not given here
+    fn with_builtin_shader_set(builtin_shader_set: RSGBuiltinShaderSet, values: Vec<(&str, RSGMaterialPropertyValue)>) -> Self {
+        let mut property_values: std::collections::HashMap<String, RSGMaterialPropertyValue> =
+            values.into_iter().map(|(name, value)| (name.to_owned(), value)).collect();
+        for property in builtin_shader_set.properties() {
+            if !property_values.contains_key(property.name()) {
+                property_values.insert(property.name().to_owned(), custom(property.default_value()));
+            }
+        }
+        RSGMaterial {
+            shader_set_id: builtin_shader_set.id(),
+            property_values,
+            graphics_state: Default::default()
+        }
+    }
+
+    pub fn flat_color(rgba: glm::Vec4) -> Self {
+        RSGMaterial::with_builtin_shader_set(RSGBuiltinShaderSet::FlatColor, vec![
+            ("mvp", builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix)),
+            ("color", custom(RSGMaterialCustomValue::Vec4(rgba)))
+        ])
+    }
+
+    pub fn vertex_color() -> Self {
+        RSGMaterial::with_builtin_shader_set(RSGBuiltinShaderSet::VertexColor, vec![
+            ("mvp", builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix))
+        ])
+    }
+
+    pub fn textured(texture_id: u32, tint: glm::Vec4) -> Self {
+        RSGMaterial::with_builtin_shader_set(RSGBuiltinShaderSet::Textured, vec![
+            ("mvp", builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix)),
+            ("color", custom(RSGMaterialCustomValue::Vec4(tint))),
+            ("base_color", custom(RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(texture_id))))
+        ])
+    }
+
+    // blended, without depth testing, and visible from both sides
+    pub fn sprite_2d(texture_id: u32) -> Self {
+        let mut material = RSGMaterial::with_builtin_shader_set(RSGBuiltinShaderSet::Sprite2D, vec![
+            ("mvp", builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix)),
+            ("sprite", custom(RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(texture_id))))
+        ]);
+        let state = &mut material.graphics_state;
+        state.depth_test = false;
+        state.depth_write = false;
+        state.cull_mode = RSGMaterialCullMode::None;
+        state.blend = RSGMaterialBlend::with_preset(RSGMaterialBlendPreset::PremultipliedAlpha);
+        material
+    }
+
+    pub fn pbr_metallic_roughness(base_color: glm::Vec4, metallic: f32, roughness: f32) -> Self {
+        RSGMaterial::with_builtin_shader_set(RSGBuiltinShaderSet::PbrMetallicRoughness, vec![
+            ("model", builtin(RSGMaterialBuiltinValue::ModelMatrix)),
+            ("view", builtin(RSGMaterialBuiltinValue::ViewMatrix)),
+            ("view_projection", builtin(RSGMaterialBuiltinValue::ViewProjectionMatrix)),
+            ("normal_matrix", builtin(RSGMaterialBuiltinValue::NormalMatrix)),
+            ("base_color_factor", custom(RSGMaterialCustomValue::Vec4(base_color))),
+            ("metallic_factor", custom(RSGMaterialCustomValue::Float(metallic))),
+            ("roughness_factor", custom(RSGMaterialCustomValue::Float(roughness)))
+        ])
+    }
+}
+
+const FLAT_COLOR_VERT: &str = r#"#version 450
+
+layout(location = 0) in vec3 position;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 mvp;
+    vec4 color;
+};
+
+void main()
+{
+    gl_Position = mvp * vec4(position, 1.0);
+}
+"#;
+
+const FLAT_COLOR_FRAG: &str = r#"#version 450
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 mvp;
+    vec4 color;
+};
+
+layout(location = 0) out vec4 frag_color;
+
+void main()
+{
+    frag_color = color;
+}
+"#;
+
+const VERTEX_COLOR_VERT: &str = r#"#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 3) in vec4 color;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 mvp;
+};
+
+layout(location = 0) out vec4 v_color;
+
+void main()
+{
+    v_color = color;
+    gl_Position = mvp * vec4(position, 1.0);
+}
+"#;
+
+const VERTEX_COLOR_FRAG: &str = r#"#version 450
+
+layout(location = 0) in vec4 v_color;
+
+layout(location = 0) out vec4 frag_color;
+
+void main()
+{
+    frag_color = v_color;
+}
+"#;
+
+const TEXTURED_VERT: &str = r#"#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 4) in vec2 texcoord;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 mvp;
+    vec4 color;
+};
+
+layout(location = 0) out vec2 v_texcoord;
+
+void main()
+{
+    v_texcoord = texcoord;
+    gl_Position = mvp * vec4(position, 1.0);
+}
+"#;
+
+const TEXTURED_FRAG: &str = r#"#version 450
+
+layout(location = 0) in vec2 v_texcoord;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 mvp;
+    vec4 color;
+};
+
+layout(binding = 1) uniform texture2D base_color;
+layout(binding = 2) uniform sampler base_color_sampler;
+
+layout(location = 0) out vec4 frag_color;
+
+void main()
+{
+    frag_color = texture(sampler2D(base_color, base_color_sampler), v_texcoord) * color;
+}
+"#;
+
+const SPRITE_2D_VERT: &str = r#"#version 450
+
+layout(location = 0) in vec2 position;
+layout(location = 4) in vec2 texcoord;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 mvp;
+    vec4 color;
+    vec4 uv_rect;
+};
+
+layout(location = 0) out vec2 v_texcoord;
+
+void main()
+{
+    v_texcoord = uv_rect.xy + texcoord * uv_rect.zw;
+    gl_Position = mvp * vec4(position, 0.0, 1.0);
+}
+"#;
+
+const SPRITE_2D_FRAG: &str = r#"#version 450
+
+layout(location = 0) in vec2 v_texcoord;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 mvp;
+    vec4 color;
+    vec4 uv_rect;
+};
+
+layout(binding = 1) uniform texture2D sprite;
+layout(binding = 2) uniform sampler sprite_sampler;
+
+layout(location = 0) out vec4 frag_color;
+
+void main()
+{
+    frag_color = texture(sampler2D(sprite, sprite_sampler), v_texcoord) * color;
+}
+"#;
+
+const PBR_VERT: &str = r#"#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 4) in vec2 texcoord;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 model;
+    mat4 view;
+    mat4 view_projection;
+    mat3 normal_matrix;
+    vec4 base_color_factor;
+    vec3 emissive_factor;
+    float metallic_factor;
+    float roughness_factor;
+    vec3 light_direction;
+    vec3 light_color;
+};
+
+layout(location = 0) out vec3 v_world_position;
+layout(location = 1) out vec3 v_normal;
+layout(location = 2) out vec2 v_texcoord;
+
+void main()
+{
+    vec4 world_position = model * vec4(position, 1.0);
+    v_world_position = world_position.xyz;
+    v_normal = normal_matrix * normal;
+    v_texcoord = texcoord;
+    gl_Position = view_projection * world_position;
+}
+"#;
+
+const PBR_FRAG: &str = r#"#version 450
+
+layout(location = 0) in vec3 v_world_position;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) in vec2 v_texcoord;
+
+layout(std140, binding = 0) uniform MaterialBlock {
+    mat4 model;
+    mat4 view;
+    mat4 view_projection;
+    mat3 normal_matrix;
+    vec4 base_color_factor;
+    vec3 emissive_factor;
+    float metallic_factor;
+    float roughness_factor;
+    vec3 light_direction;
+    vec3 light_color;
+};
+
+layout(binding = 1) uniform texture2D base_color_texture;
+layout(binding = 2) uniform texture2D metallic_roughness_texture;
+layout(binding = 3) uniform sampler material_sampler;
+
+layout(location = 0) out vec4 frag_color;
+
+const float PI = 3.14159265;
+
+float distribution_ggx(float n_dot_h, float roughness)
+{
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness)
+{
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0)
+{
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+void main()
+{
+    vec4 base_color = base_color_factor * texture(sampler2D(base_color_texture, material_sampler), v_texcoord);
+    vec4 metallic_roughness = texture(sampler2D(metallic_roughness_texture, material_sampler), v_texcoord);
+    float roughness = clamp(roughness_factor * metallic_roughness.g, 0.04, 1.0);
+    float metallic = clamp(metallic_factor * metallic_roughness.b, 0.0, 1.0);
+
+    // the view matrix is a rigid transform
+    vec3 camera_position = -(transpose(mat3(view)) * view[3].xyz);
+    vec3 n = normalize(v_normal);
+    vec3 v = normalize(camera_position - v_world_position);
+    vec3 l = normalize(-light_direction);
+    vec3 h = normalize(v + l);
+    float n_dot_v = max(dot(n, v), 0.0001);
+    float n_dot_l = max(dot(n, l), 0.0);
+    float n_dot_h = max(dot(n, h), 0.0);
+
+    vec3 f0 = mix(vec3(0.04), base_color.rgb, metallic);
+    vec3 fresnel = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    vec3 specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+        / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+    vec3 diffuse = (vec3(1.0) - fresnel) * (1.0 - metallic) * base_color.rgb / PI;
+    vec3 color = (diffuse + specular) * light_color * n_dot_l + emissive_factor;
+    frag_color = vec4(color * base_color.a, base_color.a);
+}
+"#;
@@ -0,0 +1,308 @@
+use crate::scene::*;
+use crate::components::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGPipelineIndexFormat {
+    U16,
+    U32
+}
+
+// Everything a backend needs to create a graphics pipeline for drawing a submesh
+// with a material. Buffer ids, offsets of the views and sizes are not part of
+// it, and neither are the mesh inputs the shader set does not consume.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RSGPipelineDescriptor {
+    pub shader_set_id: u32,
+    pub graphics_state: RSGMaterialGraphicsState,
+    pub topology: RSGMeshTopology,
+    pub vertex_layout: RSGVertexLayout,
+    pub index_format: Option<RSGPipelineIndexFormat>
+}
+
+impl RSGPipelineDescriptor {
+    // vertex_layout is what RSGVertexLayout::new resolves for the shader set and the submesh
+    pub fn new(shader_set_id: u32, graphics_state: RSGMaterialGraphicsState, submesh: &RSGSubMesh, vertex_layout: RSGVertexLayout) -> Self {
+        RSGPipelineDescriptor {
+            shader_set_id,
+            graphics_state,
+            topology: submesh.topology,
+            vertex_layout,
+            index_format: submesh.index_view.map(|v| match v {
+                RSGMeshIndexBufferView::U16(_) => RSGPipelineIndexFormat::U16,
+                RSGMeshIndexBufferView::U32(_) => RSGPipelineIndexFormat::U32
+            })
+        }
+    }
+
+    // With the effective graphics state, so the inherited opacity is taken into
+    // account. None also when the submesh does not bind to the shader set, use
+    // RSGVertexLayout::new to get the errors.
+    pub fn for_node<ObserverT>(components: &RSGComponentContainer, scene: &RSGScene<RSGComponentLinks, ObserverT>,
+        node_key: RSGNodeKey, submesh_index: usize) -> Option<Self>
+        where ObserverT: RSGObserver
+    {
+        let links = scene.get_component_links(node_key);
+        let material = components.material(links.material_key?)?;
+        let shader_set = components.shader_sets.get(material.shader_set_id)?;
+        let mesh = components.mesh_data.get(links.mesh_key?)?;
+        let vertex_layout = RSGVertexLayout::new(shader_set, mesh, submesh_index).ok()?;
+        Some(RSGPipelineDescriptor::new(material.shader_set_id, components.effective_graphics_state(links)?,
+            &mesh.submeshes[submesh_index], vertex_layout))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RSGPipelineCacheStats {
+    pub hits: u32,
+    pub created: u32,
+    pub evicted: u32
+}
+
+struct RSGPipelineCacheEntry<PipelineT> {
+    pipeline: PipelineT,
+    last_used_frame: u64
+}
+
+// Maps descriptors to backend pipeline handles. Call begin_frame() once per
+// frame, and evict() at some point to get back the pipelines that were not
+// used for a while so the backend can release them.
+pub struct RSGPipelineCache<PipelineT> {
+    entries: std::collections::HashMap<RSGPipelineDescriptor, RSGPipelineCacheEntry<PipelineT>>,
+    frame: u64,
+    stats: RSGPipelineCacheStats
+}
+
+impl<PipelineT> Default for RSGPipelineCache<PipelineT> {
+    fn default() -> Self {
+        RSGPipelineCache {
+            entries: Default::default(),
+            frame: 0,
+            stats: Default::default()
+        }
+    }
+}
+
+impl<PipelineT> RSGPipelineCache<PipelineT> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+        self.stats = Default::default();
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // for the current frame
+    pub fn stats(&self) -> RSGPipelineCacheStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, descriptor: &RSGPipelineDescriptor) -> bool {
+        self.entries.contains_key(descriptor)
+    }
+
+    pub fn used_this_frame(&self) -> usize {
+        self.entries.values().filter(|e| e.last_used_frame == self.frame).count()
+    }
+
+    // marks the pipeline as used
+    pub fn get(&mut self, descriptor: &RSGPipelineDescriptor) -> Option<&PipelineT> {
+        let frame = self.frame;
+        let entry = self.entries.get_mut(descriptor)?;
+        entry.last_used_frame = frame;
+        self.stats.hits += 1;
+        Some(&entry.pipeline)
+    }
+
+    pub fn get_or_create<F>(&mut self, descriptor: &RSGPipelineDescriptor, create: F) -> &PipelineT
+        where F: FnOnce(&RSGPipelineDescriptor) -> PipelineT
+    {
+        match self.try_get_or_create(descriptor, |d| Ok::<_, std::convert::Infallible>(create(d))) {
+            Ok(pipeline) => pipeline,
+            Err(e) => match e {}
+        }
+    }
+
+    // a failed creation is not cached, the next call tries again
+    pub fn try_get_or_create<F, E>(&mut self, descriptor: &RSGPipelineDescriptor, create: F) -> Result<&PipelineT, E>
+        where F: FnOnce(&RSGPipelineDescriptor) -> Result<PipelineT, E>
+    {
+        let frame = self.frame;
+        if self.entries.contains_key(descriptor) {
+            self.stats.hits += 1;
+        } else {
+            let pipeline = create(descriptor)?;
+            self.stats.created += 1;
+            self.entries.insert(descriptor.clone(), RSGPipelineCacheEntry { pipeline, last_used_frame: frame });
+        }
+        let entry = self.entries.get_mut(descriptor).unwrap();
+        entry.last_used_frame = frame;
+        Ok(&entry.pipeline)
+    }
+
+    // Removes the pipelines not used in the last max_unused_frames frames,
+    // 0 meaning everything not used in the current frame.
+    pub fn evict(&mut self, max_unused_frames: u64) -> Vec<(RSGPipelineDescriptor, PipelineT)> {
+        let frame = self.frame;
+        let stale: Vec<RSGPipelineDescriptor> = self.entries.iter()
+            .filter(|(_, e)| frame - e.last_used_frame > max_unused_frames)
+            .map(|(d, _)| d.clone())
+            .collect();
+        self.stats.evicted += stale.len() as u32;
+        stale.into_iter().map(|d| {
+            let entry = self.entries.remove(&d).unwrap();
+            (d, entry.pipeline)
+        }).collect()
+    }
+
+    pub fn clear(&mut self) -> Vec<(RSGPipelineDescriptor, PipelineT)> {
+        self.entries.drain().map(|(d, e)| (d, e.pipeline)).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RSGVertexBufferLayout {
+    // into the vertex views of the mesh
+    pub view_index: u32,
+    pub stride: usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RSGVertexAttribute {
+    pub location: u32,
+    // never a matrix, those are split into one attribute per column
+    pub format: RSGMeshVertexInputType,
+    // into the buffers of the layout
+    pub buffer_index: usize,
+    // relative to the start of a vertex
+    pub offset: usize
+}
+
+// The vertex input state of a pipeline: the buffers to bind, in binding order,
+// and the attributes sorted by location.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RSGVertexLayout {
+    pub buffers: smallvec::SmallVec<[RSGVertexBufferLayout; 4]>,
+    pub attributes: smallvec::SmallVec<[RSGVertexAttribute; 8]>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RSGVertexInputError {
+    Missing { semantic: RSGMeshVertexSemantic, location: u32 },
+    TypeMismatch { semantic: RSGMeshVertexSemantic, location: u32, expected: RSGMeshVertexInputType, found: RSGMeshVertexInputType },
+    InvalidView { semantic: RSGMeshVertexSemantic, view_index: u32, view_count: usize },
+    InvalidSubmesh { submesh_index: usize, submesh_count: usize },
+    // two shader inputs claim a location, matrices counting one location per column
+    LocationOverlap { semantic: RSGMeshVertexSemantic, other: RSGMeshVertexSemantic, location: u32 }
+}
+
+impl std::fmt::Display for RSGVertexInputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RSGVertexInputError::Missing { semantic, location } =>
+                write!(f, "the mesh has no {:?} input for location {}", semantic, location),
+            RSGVertexInputError::TypeMismatch { semantic, location, expected, found } =>
+                write!(f, "the {:?} input for location {} is a {:?}, but the shader expects a {:?}", semantic, location, found, expected),
+            RSGVertexInputError::InvalidView { semantic, view_index, view_count } =>
+                write!(f, "the {:?} input uses vertex view {}, but the mesh has {}", semantic, view_index, view_count),
+            RSGVertexInputError::InvalidSubmesh { submesh_index, submesh_count } =>
+                write!(f, "there is no submesh {}, the mesh has {}", submesh_index, submesh_count),
+            RSGVertexInputError::LocationOverlap { semantic, other, location } =>
+                write!(f, "the {:?} and {:?} inputs both use location {}", other, semantic, location)
+        }
+    }
+}
+
+impl std::error::Error for RSGVertexInputError {}
+
+impl RSGVertexLayout {
+    // Binds the inputs of a submesh to the locations the shader set consumes.
+    // Inputs the shaders do not use are left out. All problems are reported.
+    pub fn new(shader_set: &RSGMaterialShaderSet, mesh: &RSGMesh, submesh_index: usize) -> Result<Self, Vec<RSGVertexInputError>> {
+        let submesh = match mesh.submeshes.get(submesh_index) {
+            Some(s) => s,
+            None => return Err(vec![RSGVertexInputError::InvalidSubmesh { submesh_index, submesh_count: mesh.submeshes.len() }])
+        };
+        let mut layout = RSGVertexLayout::default();
+        let mut errors = Vec::new();
+        let locations = |input: &RSGShaderVertexInput| {
+            let start = input.location as u64;
+            start..start + input.input_type.columns().1 as u64
+        };
+        for (index, shader_input) in shader_set.vertex_inputs.iter().enumerate() {
+            let range = locations(shader_input);
+            let overlap = shader_set.vertex_inputs[..index].iter()
+                .find(|other| range.start < locations(other).end && locations(other).start < range.end);
+            if let Some(other) = overlap {
+                errors.push(RSGVertexInputError::LocationOverlap {
+                    semantic: shader_input.semantic,
+                    other: other.semantic,
+                    location: range.start.max(locations(other).start) as u32
+                });
+            }
+        }
+        for shader_input in shader_set.vertex_inputs.iter() {
+            let semantic = shader_input.semantic;
+            let location = shader_input.location;
+            let mesh_input = match submesh.inputs.iter().find(|i| i.semantic() == semantic) {
+                Some(i) => i,
+                None => {
+                    if !shader_input.optional {
+                        errors.push(RSGVertexInputError::Missing { semantic, location });
+                    }
+                    continue;
+                }
+            };
+            if !shader_input.accepts(mesh_input.input_type()) {
+                errors.push(RSGVertexInputError::TypeMismatch {
+                    semantic,
+                    location,
+                    expected: shader_input.input_type,
+                    found: mesh_input.input_type()
+                });
+                continue;
+            }
+            let view_index = mesh_input.view_index();
+            let view = match mesh.vertex_views.get(view_index as usize) {
+                Some(v) => v,
+                None => {
+                    errors.push(RSGVertexInputError::InvalidView { semantic, view_index, view_count: mesh.vertex_views.len() });
+                    continue;
+                }
+            };
+            let buffer_index = match layout.buffers.iter().position(|b| b.view_index == view_index) {
+                Some(index) => index,
+                None => {
+                    layout.buffers.push(RSGVertexBufferLayout { view_index, stride: view.stride });
+                    layout.buffers.len() - 1
+                }
+            };
+            let (format, columns) = mesh_input.input_type().columns();
+            for column in 0..columns {
+                layout.attributes.push(RSGVertexAttribute {
+                    location: location + column,
+                    format,
+                    buffer_index,
+                    offset: mesh_input.offset() + column as usize * format.size()
+                });
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        layout.attributes.sort_by_key(|a| a.location);
+        Ok(layout)
+    }
+}
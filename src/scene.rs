@@ -90,6 +90,24 @@ impl<'a, CompLinksT, ObserverT> Iterator for RSGAncestorIter<'a, CompLinksT, Obs
     }
 }
 
+pub struct RSGChildIter<'a, CompLinksT, ObserverT> where CompLinksT: Copy {
+    scene: &'a RSGScene<CompLinksT, ObserverT>,
+    next: Option<RSGNodeKey>
+}
+
+impl<'a, CompLinksT, ObserverT> Iterator for RSGChildIter<'a, CompLinksT, ObserverT> where CompLinksT: Default + Copy, ObserverT: RSGObserver {
+    type Item = RSGNodeKey;
+    fn next(&mut self) -> Option<RSGNodeKey> {
+        match self.next.take() {
+            Some(key) => {
+                self.next = self.scene[key].next_sibling_key;
+                Some(key)
+            }
+            None => None
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct RSGNode<CompLinksT> where CompLinksT: Copy {
     pub key: Option<RSGNodeKey>,
@@ -592,6 +610,14 @@ impl<CompLinksT, ObserverT> RSGScene<CompLinksT, ObserverT> where CompLinksT: De
         }
     }
 
+    pub fn children(&self, node_key: RSGNodeKey) -> RSGChildIter<'_, CompLinksT, ObserverT> {
+        // direct children only
+        RSGChildIter {
+            scene: self,
+            next: self[node_key].first_child_key
+        }
+    }
+
     pub fn ancestors_with_node(&self, node_key: RSGNodeKey) -> RSGAncestorIter<CompLinksT, ObserverT> {
         // node and its ancestors
         RSGAncestorIter {
@@ -0,0 +1,754 @@
+use crate::scene::*;
+use crate::components::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGShaderStage {
+    Vertex,
+    Fragment
+}
+
+impl RSGShaderStage {
+    // the name of the source in locations and errors
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            RSGShaderStage::Vertex => "vertex_shader",
+            RSGShaderStage::Fragment => "fragment_shader"
+        }
+    }
+}
+
+bitflags::bitflags! {
+    pub struct RSGShaderPermutationKey: u32 {
+        // a texture property has a texture id
+        const HAS_TEXTURE = 0x01;
+        // the submesh has a color vertex input
+        const VERTEX_COLOR = 0x02;
+        // the alpha cutoff property of the shader set is above zero
+        const ALPHA_TEST = 0x04;
+    }
+}
+
+impl RSGShaderPermutationKey {
+    pub fn defines(&self) -> Vec<(String, String)> {
+        let mut defines = Vec::new();
+        if self.contains(RSGShaderPermutationKey::HAS_TEXTURE) {
+            defines.push(("RSG_HAS_TEXTURE".to_owned(), "1".to_owned()));
+        }
+        if self.contains(RSGShaderPermutationKey::VERTEX_COLOR) {
+            defines.push(("RSG_VERTEX_COLOR".to_owned(), "1".to_owned()));
+        }
+        if self.contains(RSGShaderPermutationKey::ALPHA_TEST) {
+            defines.push(("RSG_ALPHA_TEST".to_owned(), "1".to_owned()));
+        }
+        defines
+    }
+
+    // with the node's material overrides taken into account
+    pub fn for_node<ObserverT>(components: &RSGComponentContainer, scene: &RSGScene<RSGComponentLinks, ObserverT>,
+        node_key: RSGNodeKey, submesh_index: usize) -> Self
+        where ObserverT: RSGObserver
+    {
+        let links = scene.get_component_links(node_key);
+        let mut key = RSGShaderPermutationKey::empty();
+        if let Some(submesh) = links.mesh_key.and_then(|k| components.mesh_data.get(k)).and_then(|m| m.submeshes.get(submesh_index)) {
+            if submesh.inputs.iter().any(|i| matches!(i, RSGMeshVertexInput::Color(..))) {
+                key |= RSGShaderPermutationKey::VERTEX_COLOR;
+            }
+        }
+        let material_key = match links.material_key {
+            Some(k) => k,
+            None => return key
+        };
+        let shader_set = match components.material(material_key).and_then(|m| components.shader_sets.get(m.shader_set_id)) {
+            Some(shader_set) => shader_set,
+            None => return key
+        };
+        let value = |name: &str| match components.material_property_value(material_key, name) {
+            Some(RSGMaterialPropertyValue::Custom(v)) => Some(*v),
+            _ => None
+        };
+        if shader_set.properties.iter().any(|p| matches!(value(p.name()), Some(RSGMaterialCustomValue::Texture(_, Some(_))))) {
+            key |= RSGShaderPermutationKey::HAS_TEXTURE;
+        }
+        if let Some(RSGMaterialCustomValue::Float(cutoff)) = shader_set.alpha_cutoff.as_deref().and_then(value) {
+            if cutoff > 0.0 {
+                key |= RSGShaderPermutationKey::ALPHA_TEST;
+            }
+        }
+        key
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RSGShaderSourceLocation {
+    pub file: String,
+    // 1-based
+    pub line: usize
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RSGPreprocessedShader {
+    pub source: String,
+    // where each line of the source comes from, None for injected defines
+    pub line_map: Vec<Option<RSGShaderSourceLocation>>
+}
+
+impl RSGPreprocessedShader {
+    // line is 1-based, as in compiler messages
+    pub fn location(&self, line: usize) -> Option<&RSGShaderSourceLocation> {
+        self.line_map.get(line.checked_sub(1)?)?.as_ref()
+    }
+
+    fn push_line(&mut self, line: &str, location: Option<RSGShaderSourceLocation>) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.line_map.push(location);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RSGShaderVariant {
+    pub vertex: RSGPreprocessedShader,
+    pub fragment: RSGPreprocessedShader
+}
+
+impl RSGShaderVariant {
+    pub fn stage(&self, stage: RSGShaderStage) -> &RSGPreprocessedShader {
+        match stage {
+            RSGShaderStage::Vertex => &self.vertex,
+            RSGShaderStage::Fragment => &self.fragment
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RSGShaderPreprocessError {
+    UnknownShaderSet(u32),
+    MissingInclude { file: String, line: usize, name: String },
+    RecursiveInclude { file: String, line: usize, name: String },
+    MalformedInclude { file: String, line: usize }
+}
+
+impl std::fmt::Display for RSGShaderPreprocessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RSGShaderPreprocessError::UnknownShaderSet(id) => write!(f, "shader set {} is not registered", id),
+            RSGShaderPreprocessError::MissingInclude { file, line, name } =>
+                write!(f, "{}:{}: cannot find include '{}'", file, line, name),
+            RSGShaderPreprocessError::RecursiveInclude { file, line, name } =>
+                write!(f, "{}:{}: '{}' is already being included", file, line, name),
+            RSGShaderPreprocessError::MalformedInclude { file, line } =>
+                write!(f, "{}:{}: malformed #include directive", file, line)
+        }
+    }
+}
+
+impl std::error::Error for RSGShaderPreprocessError {}
+
+// the name of an include directive, Some(Err) if it is one but cannot be parsed
+fn parse_include(line: &str) -> Option<Result<&str, ()>> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?;
+    if !rest.starts_with(|c: char| c.is_whitespace() || c == '"' || c == '<') {
+        return None;
+    }
+    let rest = rest.trim();
+    let close = match rest.chars().next() {
+        Some('"') => '"',
+        Some('<') => '>',
+        _ => return Some(Err(()))
+    };
+    let name = &rest[1..];
+    match name.find(close) {
+        Some(end) if end > 0 && name[end + 1..].trim().is_empty() => Some(Ok(&name[..end])),
+        _ => Some(Err(()))
+    }
+}
+
+fn is_directive(line: &str, directive: &str) -> bool {
+    line.trim_start().strip_prefix('#')
+        .map(|rest| rest.split_whitespace().collect::<Vec<_>>() == directive.split_whitespace().collect::<Vec<_>>())
+        .unwrap_or(false)
+}
+
+// the index of the #version line, when only blank lines and comments come before it
+fn version_line(source: &str) -> Option<usize> {
+    let mut in_comment = false;
+    for (index, line) in source.lines().enumerate() {
+        let mut rest = line.trim();
+        loop {
+            if in_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        rest = rest[end + 2..].trim_start();
+                        in_comment = false;
+                    },
+                    None => {
+                        rest = "";
+                        break;
+                    }
+                }
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                rest = comment;
+                in_comment = true;
+            } else {
+                break;
+            }
+        }
+        if rest.is_empty() || rest.starts_with("//") {
+            continue;
+        }
+        return match rest.strip_prefix('#') {
+            Some(directive) if directive.trim_start().starts_with("version") => Some(index),
+            _ => None
+        };
+    }
+    None
+}
+
+#[derive(Clone, Debug)]
+struct RSGShaderVariantCacheEntry {
+    // the sources the variant was made from
+    vertex_source: String,
+    fragment_source: String,
+    variant: RSGShaderVariant
+}
+
+// Resolves #include "name" (or <name>) against a table of virtual files, and
+// injects defines after the #version line. Conditionals are left to the GLSL
+// compiler. Included files with #pragma once are only included the first time.
+// The variants of the registered shader sets are cached per permutation key,
+// and made again when the sources of the set differ from the cached ones.
+#[derive(Clone, Debug, Default)]
+pub struct RSGShaderPreprocessor {
+    includes: std::collections::HashMap<String, String>,
+    defines: std::collections::BTreeMap<String, String>,
+    variants: std::collections::HashMap<(u32, RSGShaderPermutationKey), RSGShaderVariantCacheEntry>
+}
+
+impl RSGShaderPreprocessor {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // changing includes or defines drops the cached variants
+    pub fn add_include(&mut self, name: &str, source: &str) {
+        self.includes.insert(name.to_owned(), source.to_owned());
+        self.variants.clear();
+    }
+
+    pub fn remove_include(&mut self, name: &str) -> Option<String> {
+        self.variants.clear();
+        self.includes.remove(name)
+    }
+
+    pub fn include(&self, name: &str) -> Option<&str> {
+        self.includes.get(name).map(|s| s.as_str())
+    }
+
+    // injected into every variant, before the permutation defines
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.defines.insert(name.to_owned(), value.to_owned());
+        self.variants.clear();
+    }
+
+    pub fn undefine(&mut self, name: &str) {
+        self.defines.remove(name);
+        self.variants.clear();
+    }
+
+    pub fn preprocess(&self, source: &str, file: &str, defines: &[(String, String)]) -> Result<RSGPreprocessedShader, RSGShaderPreprocessError> {
+        let mut output = RSGPreprocessedShader {
+            source: String::with_capacity(source.len()),
+            line_map: Vec::new()
+        };
+        let mut lines = source.lines().enumerate();
+        // #version has to come first, the defines go after it
+        let header_len = version_line(source).map_or(0, |index| index + 1);
+        for (index, line) in lines.by_ref().take(header_len) {
+            output.push_line(line, Some(RSGShaderSourceLocation { file: file.to_owned(), line: index + 1 }));
+        }
+        for (name, value) in self.defines.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+            .chain(defines.iter().map(|(n, v)| (n.as_str(), v.as_str())))
+        {
+            output.push_line(format!("#define {} {}", name, value).trim_end(), None);
+        }
+        let mut stack = vec![file.to_owned()];
+        let mut included_once = std::collections::HashSet::new();
+        for (index, line) in lines {
+            self.process_line(line, file, index + 1, &mut stack, &mut included_once, &mut output)?;
+        }
+        Ok(output)
+    }
+
+    fn process_file(&self, name: &str, stack: &mut Vec<String>, included_once: &mut std::collections::HashSet<String>,
+        output: &mut RSGPreprocessedShader) -> Result<(), RSGShaderPreprocessError>
+    {
+        let source = &self.includes[name];
+        if source.lines().any(|l| is_directive(l, "pragma once")) && !included_once.insert(name.to_owned()) {
+            return Ok(());
+        }
+        stack.push(name.to_owned());
+        for (index, line) in source.lines().enumerate() {
+            if is_directive(line, "pragma once") {
+                continue;
+            }
+            self.process_line(line, name, index + 1, stack, included_once, output)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    fn process_line(&self, line: &str, file: &str, line_number: usize, stack: &mut Vec<String>,
+        included_once: &mut std::collections::HashSet<String>, output: &mut RSGPreprocessedShader) -> Result<(), RSGShaderPreprocessError>
+    {
+        let name = match parse_include(line) {
+            None => {
+                output.push_line(line, Some(RSGShaderSourceLocation { file: file.to_owned(), line: line_number }));
+                return Ok(());
+            },
+            Some(Err(())) => return Err(RSGShaderPreprocessError::MalformedInclude { file: file.to_owned(), line: line_number }),
+            Some(Ok(name)) => name
+        };
+        if !self.includes.contains_key(name) {
+            return Err(RSGShaderPreprocessError::MissingInclude { file: file.to_owned(), line: line_number, name: name.to_owned() });
+        }
+        if stack.iter().any(|f| f == name) {
+            return Err(RSGShaderPreprocessError::RecursiveInclude { file: file.to_owned(), line: line_number, name: name.to_owned() });
+        }
+        self.process_file(name, stack, included_once, output)
+    }
+
+    pub fn variant(&mut self, shader_sets: &RSGMaterialShaderSetRegistry, shader_set_id: u32,
+        key: RSGShaderPermutationKey) -> Result<&RSGShaderVariant, RSGShaderPreprocessError>
+    {
+        let shader_set = shader_sets.get(shader_set_id).ok_or(RSGShaderPreprocessError::UnknownShaderSet(shader_set_id))?;
+        // e.g. another registry, or a set registered again under the same id
+        let up_to_date = self.variants.get(&(shader_set_id, key))
+            .is_some_and(|e| e.vertex_source == shader_set.vertex_shader && e.fragment_source == shader_set.fragment_shader);
+        if !up_to_date {
+            let defines = key.defines();
+            let variant = RSGShaderVariant {
+                vertex: self.preprocess(&shader_set.vertex_shader, RSGShaderStage::Vertex.file_name(), &defines)?,
+                fragment: self.preprocess(&shader_set.fragment_shader, RSGShaderStage::Fragment.file_name(), &defines)?
+            };
+            self.variants.insert((shader_set_id, key), RSGShaderVariantCacheEntry {
+                vertex_source: shader_set.vertex_shader.clone(),
+                fragment_source: shader_set.fragment_shader.clone(),
+                variant
+            });
+        }
+        Ok(&self.variants[&(shader_set_id, key)].variant)
+    }
+
+    pub fn cached_variant(&self, shader_set_id: u32, key: RSGShaderPermutationKey) -> Option<&RSGShaderVariant> {
+        self.variants.get(&(shader_set_id, key)).map(|e| &e.variant)
+    }
+
+    pub fn variant_count(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn invalidate(&mut self, shader_set_id: u32) {
+        self.variants.retain(|(id, _), _| *id != shader_set_id);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RSGReflectedUniform {
+    pub name: String,
+    // as written in the source
+    pub type_name: String,
+    pub array_size: Option<usize>,
+    // None for uniforms outside of blocks, textures and samplers
+    pub block: Option<String>,
+    pub stages: smallvec::SmallVec<[RSGShaderStage; 2]>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RSGShaderReflectionError {
+    Syntax { stage: RSGShaderStage, line: usize, message: String },
+    UnsupportedType { name: String, type_name: String },
+    // only arrays with vec4 aligned elements (or of textures and samplers) map to properties
+    UnsupportedArray { name: String, type_name: String },
+    StageConflict { name: String, vertex: String, fragment: String }
+}
+
+impl std::fmt::Display for RSGShaderReflectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RSGShaderReflectionError::Syntax { stage, line, message } =>
+                write!(f, "{}:{}: {}", stage.file_name(), line, message),
+            RSGShaderReflectionError::UnsupportedType { name, type_name } =>
+                write!(f, "uniform '{}' has the unsupported type {}", name, type_name),
+            RSGShaderReflectionError::UnsupportedArray { name, type_name } =>
+                write!(f, "uniform '{}' is an array of {}, which has no property representation", name, type_name),
+            RSGShaderReflectionError::StageConflict { name, vertex, fragment } =>
+                write!(f, "uniform '{}' is a {} in the vertex shader, but a {} in the fragment shader", name, vertex, fragment)
+        }
+    }
+}
+
+impl std::error::Error for RSGShaderReflectionError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RSGShaderPropertyMismatch {
+    // used by the shaders, but not declared
+    Undeclared { name: String, type_name: &'static str },
+    // declared, but not used by the shaders
+    Unused { name: String },
+    TypeMismatch { name: String, declared: &'static str, reflected: &'static str },
+    // uniforms have to be declared in block order, as that determines the layout
+    OrderMismatch { name: String, declared_index: usize, reflected_index: usize }
+}
+
+impl std::fmt::Display for RSGShaderPropertyMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RSGShaderPropertyMismatch::Undeclared { name, type_name } =>
+                write!(f, "the shaders use the {} '{}', which is not declared", type_name, name),
+            RSGShaderPropertyMismatch::Unused { name } =>
+                write!(f, "property '{}' is not used by the shaders", name),
+            RSGShaderPropertyMismatch::TypeMismatch { name, declared, reflected } =>
+                write!(f, "property '{}' is declared as a {}, but the shaders use a {}", name, declared, reflected),
+            RSGShaderPropertyMismatch::OrderMismatch { name, declared_index, reflected_index } =>
+                write!(f, "uniform '{}' is declared at position {}, but is at position {} in the shaders", name, declared_index, reflected_index)
+        }
+    }
+}
+
+struct RSGGlslToken<'a> {
+    text: &'a str,
+    line: usize
+}
+
+// identifiers, numbers and single punctuation characters; comments and
+// preprocessor lines are dropped
+fn tokenize(source: &str) -> Vec<RSGGlslToken<'_>> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut line_start = true;
+    let is_word = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'.';
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\n' {
+            line += 1;
+            line_start = true;
+            i += 1;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+        } else if (c == b'#' && line_start) || bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if bytes[i..].starts_with(b"/*") {
+            i += 2;
+            while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+                if bytes[i] == b'\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i = (i + 2).min(bytes.len());
+        } else {
+            line_start = false;
+            let start = i;
+            if is_word(c) {
+                while i < bytes.len() && is_word(bytes[i]) {
+                    i += 1;
+                }
+            } else {
+                i += source[i..].chars().next().unwrap().len_utf8();
+            }
+            tokens.push(RSGGlslToken { text: &source[start..i], line });
+        }
+    }
+    tokens
+}
+
+const GLSL_QUALIFIERS: &[&str] = &[
+    "uniform", "const", "highp", "mediump", "lowp", "flat", "smooth", "noperspective",
+    "invariant", "precise", "readonly", "writeonly", "coherent", "volatile", "restrict"
+];
+
+// the tokens not inside parentheses or brackets
+fn at_depth_zero<'a, 'b>(tokens: &'b [RSGGlslToken<'a>]) -> impl Iterator<Item = (usize, &'b RSGGlslToken<'a>)> {
+    let mut depth = 0i32;
+    tokens.iter().enumerate().filter(move |(_, t)| {
+        let top = depth == 0;
+        match t.text {
+            "(" | "[" => depth += 1,
+            ")" | "]" => depth -= 1,
+            _ => {}
+        }
+        top && depth == 0 && t.text != ")" && t.text != "]"
+    })
+}
+
+struct RSGGlslParser {
+    stage: RSGShaderStage,
+    uniforms: Vec<RSGReflectedUniform>
+}
+
+impl RSGGlslParser {
+    fn error<T>(&self, line: usize, message: &str) -> Result<T, RSGShaderReflectionError> {
+        Err(RSGShaderReflectionError::Syntax { stage: self.stage, line, message: message.to_owned() })
+    }
+
+    fn parse(&mut self, source: &str) -> Result<(), RSGShaderReflectionError> {
+        let tokens = tokenize(source);
+        let mut statement_start = 0;
+        let mut i = 0;
+        while i < tokens.len() {
+            match tokens[i].text {
+                ";" => {
+                    let statement = &tokens[statement_start..i];
+                    if at_depth_zero(statement).any(|(_, t)| t.text == "uniform") {
+                        self.parse_declaration(statement, None)?;
+                    }
+                    i += 1;
+                    statement_start = i;
+                },
+                "{" => {
+                    let end = self.matching_brace(&tokens, i)?;
+                    let head = &tokens[statement_start..i];
+                    if at_depth_zero(head).any(|(_, t)| t.text == "uniform") {
+                        i = self.parse_block(&tokens, head, i, end)?;
+                    } else {
+                        // a function, or a struct whose declarators end up in an ignored statement
+                        i = end + 1;
+                    }
+                    statement_start = i;
+                },
+                _ => i += 1
+            }
+        }
+        Ok(())
+    }
+
+    fn matching_brace(&self, tokens: &[RSGGlslToken], open: usize) -> Result<usize, RSGShaderReflectionError> {
+        let mut depth = 0;
+        for (i, token) in tokens.iter().enumerate().skip(open) {
+            match token.text {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(i);
+                    }
+                },
+                _ => {}
+            }
+        }
+        self.error(tokens[open].line, "unbalanced braces")
+    }
+
+    // returns the index after the block declaration
+    fn parse_block(&mut self, tokens: &[RSGGlslToken], head: &[RSGGlslToken], open: usize, close: usize)
+        -> Result<usize, RSGShaderReflectionError>
+    {
+        let block_name = match head.last() {
+            Some(t) if t.text != "uniform" && t.text != ")" => t.text.to_owned(),
+            _ => return self.error(tokens[open].line, "uniform block without a name")
+        };
+        let mut member_start = open + 1;
+        for i in open + 1..close {
+            if tokens[i].text == ";" {
+                self.parse_declaration(&tokens[member_start..i], Some(&block_name))?;
+                member_start = i + 1;
+            }
+        }
+        // the optional instance name
+        let semicolon = match tokens[close + 1..].iter().position(|t| t.text == ";") {
+            Some(p) => close + 1 + p,
+            None => return self.error(tokens[close].line, "missing ';' after uniform block")
+        };
+        if tokens[close + 1..semicolon].iter().any(|t| t.text == "[") {
+            return Err(RSGShaderReflectionError::UnsupportedArray { name: block_name, type_name: "uniform block".to_owned() });
+        }
+        Ok(semicolon + 1)
+    }
+
+    fn parse_declaration(&mut self, statement: &[RSGGlslToken], block: Option<&str>) -> Result<(), RSGShaderReflectionError> {
+        // drop layout(...) and the qualifiers, leaving the type and the declarators
+        let words: Vec<usize> = at_depth_zero(statement)
+            .filter(|(_, t)| t.text != "layout" && !GLSL_QUALIFIERS.contains(&t.text))
+            .map(|(i, _)| i)
+            .collect();
+        let line = statement.first().map(|t| t.line).unwrap_or(0);
+        let type_index = match words.first() {
+            Some(i) if statement[*i].text != "(" => *i,
+            _ => return self.error(line, "declaration without a type")
+        };
+        let mut rest = &statement[type_index + 1..];
+        let type_name = statement[type_index].text;
+        while !rest.is_empty() {
+            let name = rest[0].text;
+            if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                return self.error(rest[0].line, &format!("unexpected '{}'", name));
+            }
+            let mut array_size = None;
+            let mut next = 1;
+            if rest.get(1).map(|t| t.text) == Some("[") {
+                let size = rest.get(2).and_then(|t| t.text.trim_end_matches(['u', 'U']).parse::<usize>().ok());
+                if size.is_none() || rest.get(3).map(|t| t.text) != Some("]") {
+                    return self.error(rest[0].line, &format!("the size of array '{}' is not an integer literal", name));
+                }
+                array_size = size;
+                next = 4;
+            }
+            self.add(name, type_name, array_size, block);
+            // an initializer, up to the next declarator
+            rest = match at_depth_zero(&rest[next..]).find(|(_, t)| t.text == ",") {
+                Some((comma, _)) => &rest[next + comma + 1..],
+                None => &[]
+            };
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, name: &str, type_name: &str, array_size: Option<usize>, block: Option<&str>) {
+        self.uniforms.push(RSGReflectedUniform {
+            name: name.to_owned(),
+            type_name: type_name.to_owned(),
+            array_size,
+            block: block.map(|b| b.to_owned()),
+            stages: smallvec::smallvec![self.stage]
+        });
+    }
+}
+
+fn property_of(name: String, type_name: &str) -> Option<RSGMaterialProperty> {
+    Some(match type_name {
+        "float" => RSGMaterialProperty::Float(name, 0.0),
+        "vec2" => RSGMaterialProperty::Vec2(name, nalgebra_glm::zero()),
+        "vec3" => RSGMaterialProperty::Vec3(name, nalgebra_glm::zero()),
+        "vec4" => RSGMaterialProperty::Vec4(name, nalgebra_glm::zero()),
+        "int" => RSGMaterialProperty::Int(name, 0),
+        "ivec2" => RSGMaterialProperty::Int2(name, nalgebra_glm::zero()),
+        "ivec3" => RSGMaterialProperty::Int3(name, nalgebra_glm::zero()),
+        "ivec4" => RSGMaterialProperty::Int4(name, nalgebra_glm::zero()),
+        "mat2" | "mat2x2" => RSGMaterialProperty::Mat2(name, nalgebra_glm::one()),
+        "mat3" | "mat3x3" => RSGMaterialProperty::Mat3(name, nalgebra_glm::one()),
+        "mat4" | "mat4x4" => RSGMaterialProperty::Mat4(name, nalgebra_glm::one()),
+        // combined image samplers become textures, the sampler state comes with the image
+        "texture2D" | "sampler2D" => RSGMaterialProperty::Texture(name, RSGTextureType::Texture2D, None),
+        "textureCube" | "samplerCube" => RSGMaterialProperty::Texture(name, RSGTextureType::Cube, None),
+        "texture2DArray" | "sampler2DArray" => RSGMaterialProperty::Texture(name, RSGTextureType::Array2D, None),
+        "sampler" => RSGMaterialProperty::Sampler(name, Default::default()),
+        _ => return None
+    })
+}
+
+// Uniforms of both stages, in the order the vertex and then the fragment shader
+// declare them, and the material properties they map to. Array elements become
+// properties of their own, named like "lights[0]". Preprocessor lines are
+// ignored, so conditional declarations should be reflected on a preprocessed variant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RSGShaderReflection {
+    pub uniforms: Vec<RSGReflectedUniform>,
+    // with zero, identity or unset defaults
+    pub properties: Vec<RSGMaterialProperty>
+}
+
+impl RSGShaderReflection {
+    pub fn new(vertex_shader: &str, fragment_shader: &str) -> Result<Self, RSGShaderReflectionError> {
+        let mut uniforms: Vec<RSGReflectedUniform> = Vec::new();
+        for (stage, source) in [(RSGShaderStage::Vertex, vertex_shader), (RSGShaderStage::Fragment, fragment_shader)].iter() {
+            let mut parser = RSGGlslParser { stage: *stage, uniforms: Vec::new() };
+            parser.parse(source)?;
+            for uniform in parser.uniforms {
+                match uniforms.iter_mut().find(|u| u.name == uniform.name) {
+                    Some(existing) if existing.type_name == uniform.type_name && existing.array_size == uniform.array_size => {
+                        if !existing.stages.contains(stage) {
+                            existing.stages.push(*stage);
+                        }
+                    },
+                    Some(existing) => {
+                        let describe = |u: &RSGReflectedUniform| match u.array_size {
+                            Some(n) => format!("{}[{}]", u.type_name, n),
+                            None => u.type_name.clone()
+                        };
+                        return Err(RSGShaderReflectionError::StageConflict {
+                            name: uniform.name.clone(),
+                            vertex: describe(existing),
+                            fragment: describe(&uniform)
+                        });
+                    },
+                    None => uniforms.push(uniform)
+                }
+            }
+        }
+        let mut properties = Vec::with_capacity(uniforms.len());
+        for uniform in uniforms.iter() {
+            let unsupported = || RSGShaderReflectionError::UnsupportedType { name: uniform.name.clone(), type_name: uniform.type_name.clone() };
+            let property = property_of(uniform.name.clone(), &uniform.type_name).ok_or_else(unsupported)?;
+            let count = match uniform.array_size {
+                None => {
+                    properties.push(property);
+                    continue;
+                },
+                Some(count) => count
+            };
+            // the std140 array stride is a multiple of 16, which only matches consecutive properties for these
+            let element = property.default_value();
+            let vec4_aligned = matches!(element, RSGMaterialCustomValue::Vec4(_) | RSGMaterialCustomValue::Int4(_)
+                | RSGMaterialCustomValue::Mat2(_) | RSGMaterialCustomValue::Mat3(_) | RSGMaterialCustomValue::Mat4(_));
+            if !vec4_aligned && !element.is_resource() {
+                return Err(RSGShaderReflectionError::UnsupportedArray { name: uniform.name.clone(), type_name: uniform.type_name.clone() });
+            }
+            for index in 0..count {
+                properties.push(property_of(format!("{}[{}]", uniform.name, index), &uniform.type_name).unwrap());
+            }
+        }
+        Ok(RSGShaderReflection { uniforms, properties })
+    }
+
+    pub fn for_shader_set(shader_set: &RSGMaterialShaderSet) -> Result<Self, RSGShaderReflectionError> {
+        RSGShaderReflection::new(&shader_set.vertex_shader, &shader_set.fragment_shader)
+    }
+
+    pub fn compare(&self, declared: &[RSGMaterialProperty]) -> Vec<RSGShaderPropertyMismatch> {
+        let mut mismatches = Vec::new();
+        for property in self.properties.iter() {
+            let type_name = property.default_value().type_name();
+            match declared.iter().find(|p| p.name() == property.name()) {
+                None => mismatches.push(RSGShaderPropertyMismatch::Undeclared { name: property.name().to_owned(), type_name }),
+                Some(d) if !d.is_same_type(&property.default_value()) => mismatches.push(RSGShaderPropertyMismatch::TypeMismatch {
+                    name: property.name().to_owned(),
+                    declared: d.default_value().type_name(),
+                    reflected: type_name
+                }),
+                Some(_) => {}
+            }
+        }
+        for property in declared.iter() {
+            if !self.properties.iter().any(|p| p.name() == property.name()) {
+                mismatches.push(RSGShaderPropertyMismatch::Unused { name: property.name().to_owned() });
+            }
+        }
+        // the relative order of the names that are uniforms (not resources) on both sides;
+        // a uniform on one side and a resource on the other is a type mismatch already
+        let is_uniform = |list: &[RSGMaterialProperty], name: &str| list.iter()
+            .any(|p| p.name() == name && !p.default_value().is_resource());
+        let uniform_names = |list: &[RSGMaterialProperty], other: &[RSGMaterialProperty]| -> Vec<String> {
+            list.iter()
+                .filter(|p| is_uniform(list, p.name()) && is_uniform(other, p.name()))
+                .map(|p| p.name().to_owned())
+                .collect()
+        };
+        let declared_order = uniform_names(declared, &self.properties);
+        let reflected_order = uniform_names(&self.properties, declared);
+        for (declared_index, name) in declared_order.iter().enumerate() {
+            let reflected_index = match reflected_order.iter().position(|n| n == name) {
+                Some(index) => index,
+                None => continue
+            };
+            if reflected_index != declared_index {
+                mismatches.push(RSGShaderPropertyMismatch::OrderMismatch { name: name.clone(), declared_index, reflected_index });
+            }
+        }
+        mismatches
+    }
+}
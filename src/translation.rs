@@ -0,0 +1,223 @@
+use crate::components::*;
+use crate::shaders::*;
+
+// Translation of the Vulkan-style GLSL of the shader sets to the languages of
+// the other backends, with naga. Everything runs on the CPU, so this can be
+// done offline, e.g. when packaging assets.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RSGShaderTarget {
+    // e.g. 300 or 310
+    GlslEs(u16),
+    // core profile, e.g. 330 or 450
+    Glsl(u16),
+    Wgsl,
+    // shader model 5.1
+    Hlsl,
+    // version 2.1
+    Msl
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RSGTranslatedShader {
+    pub source: String,
+    // may differ from main, e.g. MSL does not allow it
+    pub entry_point: String
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RSGTranslatedShaderSet {
+    pub target: RSGShaderTarget,
+    pub vertex: RSGTranslatedShader,
+    pub fragment: RSGTranslatedShader
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RSGShaderDiagnostic {
+    // the file and line before preprocessing, when known
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl std::fmt::Display for RSGShaderDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RSGShaderTranslationError {
+    Preprocess(RSGShaderPreprocessError),
+    // parsing or validation failed
+    Compile { stage: RSGShaderStage, diagnostics: Vec<RSGShaderDiagnostic> },
+    // the shader is valid, but cannot be expressed in the target language
+    Translate { stage: RSGShaderStage, target: RSGShaderTarget, message: String }
+}
+
+impl std::fmt::Display for RSGShaderTranslationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RSGShaderTranslationError::Preprocess(e) => e.fmt(f),
+            RSGShaderTranslationError::Compile { diagnostics, .. } => {
+                let lines: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            },
+            RSGShaderTranslationError::Translate { stage, target, message } =>
+                write!(f, "cannot translate the {} to {:?}: {}", stage.file_name(), target, message)
+        }
+    }
+}
+
+impl std::error::Error for RSGShaderTranslationError {}
+
+impl From<RSGShaderPreprocessError> for RSGShaderTranslationError {
+    fn from(e: RSGShaderPreprocessError) -> Self {
+        RSGShaderTranslationError::Preprocess(e)
+    }
+}
+
+fn naga_stage(stage: RSGShaderStage) -> naga::ShaderStage {
+    match stage {
+        RSGShaderStage::Vertex => naga::ShaderStage::Vertex,
+        RSGShaderStage::Fragment => naga::ShaderStage::Fragment
+    }
+}
+
+// the message of an error and the errors it wraps
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(s) = source {
+        message.push_str(": ");
+        message.push_str(&s.to_string());
+        source = s.source();
+    }
+    message
+}
+
+fn diagnostic(shader: &RSGPreprocessedShader, stage: RSGShaderStage, location: Option<naga::SourceLocation>, message: String) -> RSGShaderDiagnostic {
+    let (line, column) = location.map(|l| (l.line_number as usize, l.line_position as usize)).unwrap_or((0, 0));
+    match shader.location(line) {
+        Some(original) => RSGShaderDiagnostic { file: original.file.clone(), line: original.line, column, message },
+        None => RSGShaderDiagnostic { file: stage.file_name().to_owned(), line, column, message }
+    }
+}
+
+pub fn translate_shader(shader: &RSGPreprocessedShader, stage: RSGShaderStage,
+    target: RSGShaderTarget) -> Result<RSGTranslatedShader, RSGShaderTranslationError>
+{
+    let source = &shader.source;
+    let compile_error = |diagnostics| RSGShaderTranslationError::Compile { stage, diagnostics };
+    let module = naga::front::glsl::Frontend::default()
+        .parse(&naga::front::glsl::Options::from(naga_stage(stage)), source)
+        .map_err(|e| compile_error(e.errors.iter()
+            .map(|e| diagnostic(shader, stage, e.location(source), e.kind.to_string()))
+            .collect()))?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::default())
+        .validate(&module)
+        .map_err(|e| compile_error(vec![diagnostic(shader, stage, e.location(source), error_chain(e.as_inner()))]))?;
+
+    let translate_error = |message: String| RSGShaderTranslationError::Translate { stage, target, message };
+    let mut output = String::new();
+    let entry_point = match target {
+        RSGShaderTarget::GlslEs(version) | RSGShaderTarget::Glsl(version) => {
+            let options = naga::back::glsl::Options {
+                version: match target {
+                    RSGShaderTarget::GlslEs(_) => naga::back::glsl::Version::new_gles(version),
+                    _ => naga::back::glsl::Version::Desktop(version)
+                },
+                ..Default::default()
+            };
+            let pipeline_options = naga::back::glsl::PipelineOptions {
+                shader_stage: naga_stage(stage),
+                entry_point: "main".to_owned(),
+                multiview: None
+            };
+            naga::back::glsl::Writer::new(&mut output, &module, &info, &options, &pipeline_options, Default::default())
+                .and_then(|mut writer| writer.write())
+                .map_err(|e| translate_error(error_chain(&e)))?;
+            "main".to_owned()
+        },
+        RSGShaderTarget::Wgsl => {
+            output = naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())
+                .map_err(|e| translate_error(error_chain(&e)))?;
+            "main".to_owned()
+        },
+        RSGShaderTarget::Hlsl => {
+            let options = naga::back::hlsl::Options::default();
+            let reflection = naga::back::hlsl::Writer::new(&mut output, &options)
+                .write(&module, &info, None)
+                .map_err(|e| translate_error(error_chain(&e)))?;
+            reflection.entry_point_names.into_iter().next()
+                .ok_or_else(|| translate_error("no entry point".to_owned()))?
+                .map_err(|e| translate_error(error_chain(&e)))?
+        },
+        RSGShaderTarget::Msl => {
+            let options = naga::back::msl::Options { lang_version: (2, 1), ..Default::default() };
+            let (source, translation) = naga::back::msl::write_string(&module, &info, &options, &Default::default())
+                .map_err(|e| translate_error(error_chain(&e)))?;
+            output = source;
+            translation.entry_point_names.into_iter().next()
+                .ok_or_else(|| translate_error("no entry point".to_owned()))?
+                .map_err(|e| translate_error(error_chain(&e)))?
+        }
+    };
+    Ok(RSGTranslatedShader { source: output, entry_point })
+}
+
+struct RSGTranslationCacheEntry {
+    // the preprocessed sources the translation was made from
+    vertex_source: String,
+    fragment_source: String,
+    translated: RSGTranslatedShaderSet
+}
+
+// Caches the translations of the variants of registered shader sets. A cached
+// translation is redone when the preprocessed sources change, e.g. after
+// changing an include. Failures are not cached.
+#[derive(Default)]
+pub struct RSGShaderTranslator {
+    cache: std::collections::HashMap<(u32, RSGShaderPermutationKey, RSGShaderTarget), RSGTranslationCacheEntry>
+}
+
+impl RSGShaderTranslator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    pub fn translate(&mut self, preprocessor: &mut RSGShaderPreprocessor, shader_sets: &RSGMaterialShaderSetRegistry,
+        shader_set_id: u32, key: RSGShaderPermutationKey, target: RSGShaderTarget) -> Result<&RSGTranslatedShaderSet, RSGShaderTranslationError>
+    {
+        let variant = preprocessor.variant(shader_sets, shader_set_id, key)?;
+        let cache_key = (shader_set_id, key, target);
+        let up_to_date = self.cache.get(&cache_key)
+            .is_some_and(|e| e.vertex_source == variant.vertex.source && e.fragment_source == variant.fragment.source);
+        if !up_to_date {
+            let translated = RSGTranslatedShaderSet {
+                target,
+                vertex: translate_shader(&variant.vertex, RSGShaderStage::Vertex, target)?,
+                fragment: translate_shader(&variant.fragment, RSGShaderStage::Fragment, target)?
+            };
+            self.cache.insert(cache_key, RSGTranslationCacheEntry {
+                vertex_source: variant.vertex.source.clone(),
+                fragment_source: variant.fragment.source.clone(),
+                translated
+            });
+        }
+        Ok(&self.cache[&cache_key].translated)
+    }
+}
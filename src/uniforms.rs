@@ -0,0 +1,214 @@
+use crate::components::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RSGUniformLayoutRules {
+    // uniform blocks
+    Std140,
+    // storage blocks (and push constants)
+    Std430
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RSGUniformType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Int,
+    Int2,
+    Int3,
+    Int4,
+    Mat2,
+    Mat3,
+    Mat4
+}
+
+impl RSGUniformType {
+    // None for textures and samplers, they are not part of uniform blocks
+    pub fn of_property(property: &RSGMaterialProperty) -> Option<Self> {
+        RSGUniformType::of_value(&property.default_value())
+    }
+
+    pub fn of_value(value: &RSGMaterialCustomValue) -> Option<Self> {
+        Some(match value {
+            RSGMaterialCustomValue::Float(_) => RSGUniformType::Float,
+            RSGMaterialCustomValue::Vec2(_) => RSGUniformType::Vec2,
+            RSGMaterialCustomValue::Vec3(_) => RSGUniformType::Vec3,
+            RSGMaterialCustomValue::Vec4(_) => RSGUniformType::Vec4,
+            RSGMaterialCustomValue::Int(_) => RSGUniformType::Int,
+            RSGMaterialCustomValue::Int2(_) => RSGUniformType::Int2,
+            RSGMaterialCustomValue::Int3(_) => RSGUniformType::Int3,
+            RSGMaterialCustomValue::Int4(_) => RSGUniformType::Int4,
+            RSGMaterialCustomValue::Mat2(_) => RSGUniformType::Mat2,
+            RSGMaterialCustomValue::Mat3(_) => RSGUniformType::Mat3,
+            RSGMaterialCustomValue::Mat4(_) => RSGUniformType::Mat4,
+            RSGMaterialCustomValue::Texture(_, _) | RSGMaterialCustomValue::Sampler(_) => return None
+        })
+    }
+
+    fn component_count(&self) -> usize {
+        match self {
+            RSGUniformType::Float | RSGUniformType::Int => 1,
+            RSGUniformType::Vec2 | RSGUniformType::Int2 => 2,
+            RSGUniformType::Vec3 | RSGUniformType::Int3 => 3,
+            RSGUniformType::Vec4 | RSGUniformType::Int4 => 4,
+            RSGUniformType::Mat2 => 2,
+            RSGUniformType::Mat3 => 3,
+            RSGUniformType::Mat4 => 4
+        }
+    }
+
+    fn is_matrix(&self) -> bool {
+        matches!(self, RSGUniformType::Mat2 | RSGUniformType::Mat3 | RSGUniformType::Mat4)
+    }
+
+    // (alignment, size, matrix column stride) in bytes
+    pub fn layout(&self, rules: RSGUniformLayoutRules) -> (usize, usize, usize) {
+        let vec_align = |n: usize| if n == 1 { 4 } else if n == 2 { 8 } else { 16 };
+        let n = self.component_count();
+        if self.is_matrix() {
+            // an array of n column vectors; std140 rounds the array stride up to a vec4
+            let column_stride = match rules {
+                RSGUniformLayoutRules::Std140 => 16,
+                RSGUniformLayoutRules::Std430 => vec_align(n)
+            };
+            (column_stride, column_stride * n, column_stride)
+        } else {
+            (vec_align(n), 4 * n, 0)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RSGUniformMember {
+    pub name: String,
+    // into the properties of the shader set, and so into the resolved values of a draw
+    pub property_index: usize,
+    pub uniform_type: RSGUniformType,
+    pub offset: usize,
+    pub size: usize,
+    pub matrix_column_stride: usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RSGUniformLayout {
+    pub rules: RSGUniformLayoutRules,
+    pub members: Vec<RSGUniformMember>,
+    // including the padding at the end
+    pub size: usize
+}
+
+#[inline]
+fn align_to(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+impl RSGUniformLayout {
+    pub fn new(properties: &[RSGMaterialProperty], rules: RSGUniformLayoutRules) -> Self {
+        let mut members = Vec::with_capacity(properties.len());
+        let mut offset = 0;
+        let mut max_align = 4;
+        for (property_index, property) in properties.iter().enumerate() {
+            let uniform_type = match RSGUniformType::of_property(property) {
+                Some(t) => t,
+                None => continue
+            };
+            let (align, size, matrix_column_stride) = uniform_type.layout(rules);
+            offset = align_to(offset, align);
+            members.push(RSGUniformMember {
+                name: property.name().to_owned(),
+                property_index,
+                uniform_type,
+                offset,
+                size,
+                matrix_column_stride
+            });
+            offset += size;
+            max_align = max_align.max(align);
+        }
+        // a block is laid out like a struct, and std140 struct alignment is rounded up to vec4
+        if rules == RSGUniformLayoutRules::Std140 {
+            max_align = max_align.max(16);
+        }
+        RSGUniformLayout {
+            rules,
+            members,
+            size: align_to(offset, max_align)
+        }
+    }
+
+    pub fn for_shader_set(shader_set: &RSGMaterialShaderSet, rules: RSGUniformLayoutRules) -> Self {
+        RSGUniformLayout::new(&shader_set.properties, rules)
+    }
+
+    pub fn member(&self, name: &str) -> Option<&RSGUniformMember> {
+        self.members.iter().find(|m| m.name == name)
+    }
+
+    pub fn write(&self, values: &[RSGMaterialCustomValue], dst: &mut [u8]) {
+        // Values in property order, as produced by resolve_render_list_values, written
+        // in native byte order (what a GPU on the same machine expects). Padding and
+        // members without a value, e.g. for a draw without material, are left untouched,
+        // so are members given a value of another type (values not from this layout's
+        // properties).
+        assert!(dst.len() >= self.size);
+        for member in self.members.iter() {
+            let value = match values.get(member.property_index) {
+                Some(value) if RSGUniformType::of_value(value) == Some(member.uniform_type) => value,
+                _ => continue
+            };
+            write_value(value, member, &mut dst[member.offset..member.offset + member.size]);
+        }
+    }
+
+    pub fn append(&self, values: &[RSGMaterialCustomValue], buf: &mut Vec<u8>, alignment: usize) -> usize {
+        // e.g. for dynamic uniform buffer offsets; returns the offset of the block
+        let offset = align_to(buf.len(), alignment.max(1));
+        buf.resize(offset + self.size, 0);
+        self.write(values, &mut buf[offset..]);
+        offset
+    }
+}
+
+fn write_floats(src: &[f32], dst: &mut [u8]) {
+    for (i, v) in src.iter().enumerate() {
+        dst[i * 4..i * 4 + 4].copy_from_slice(&v.to_ne_bytes());
+    }
+}
+
+fn write_ints(src: &[i32], dst: &mut [u8]) {
+    for (i, v) in src.iter().enumerate() {
+        dst[i * 4..i * 4 + 4].copy_from_slice(&v.to_ne_bytes());
+    }
+}
+
+fn write_columns(src: &[f32], rows: usize, column_stride: usize, dst: &mut [u8]) {
+    // nalgebra storage is column-major already
+    for (c, column) in src.chunks(rows).enumerate() {
+        write_floats(column, &mut dst[c * column_stride..]);
+    }
+}
+
+fn write_value(value: &RSGMaterialCustomValue, member: &RSGUniformMember, dst: &mut [u8]) {
+    match value {
+        RSGMaterialCustomValue::Float(v) => write_floats(&[*v], dst),
+        RSGMaterialCustomValue::Vec2(v) => write_floats(v.as_slice(), dst),
+        RSGMaterialCustomValue::Vec3(v) => write_floats(v.as_slice(), dst),
+        RSGMaterialCustomValue::Vec4(v) => write_floats(v.as_slice(), dst),
+        RSGMaterialCustomValue::Int(v) => write_ints(&[*v], dst),
+        RSGMaterialCustomValue::Int2(v) => write_ints(v.as_slice(), dst),
+        RSGMaterialCustomValue::Int3(v) => write_ints(v.as_slice(), dst),
+        RSGMaterialCustomValue::Int4(v) => write_ints(v.as_slice(), dst),
+        RSGMaterialCustomValue::Mat2(m) => write_columns(m.as_slice(), 2, member.matrix_column_stride, dst),
+        RSGMaterialCustomValue::Mat3(m) => write_columns(m.as_slice(), 3, member.matrix_column_stride, dst),
+        RSGMaterialCustomValue::Mat4(m) => write_columns(m.as_slice(), 4, member.matrix_column_stride, dst),
+        RSGMaterialCustomValue::Texture(_, _) | RSGMaterialCustomValue::Sampler(_) => {}
+    }
+}
+
+pub fn pack_draw_values(layout: &RSGUniformLayout, table: &RSGDrawValueTable, draw_indices: &[usize],
+    alignment: usize, buf: &mut Vec<u8>) -> Vec<usize>
+{
+    // all given draws are expected to use the shader set the layout was built for
+    draw_indices.iter().map(|i| layout.append(table.draw_values(*i), buf, alignment)).collect()
+}
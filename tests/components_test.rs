@@ -1,36 +1,35 @@
 use rsg::scene::*;
 use rsg::components::*;
+use rsg::materials::*;
 use nalgebra_glm as glm;
 use smallvec::*;
 
 type Scene = RSGScene::<RSGComponentLinks, RSGSceneObserver>;
 type MeshBuffers = std::collections::HashMap<u32, RSGMeshBuffer>;
-type ShaderSets = std::collections::HashMap<u32, RSGMaterialShaderSet>;
 
 #[test]
 fn scene_with_2d_first_plus_one_3d_layer() {
     static TRIANGLE2D_BUF_ID: u32 = 1;
     static TRIANGLE3D_BUF_ID: u32 = 2;
-    static COLOR_SH_ID: u32 = 1;
 
-    fn make_color_material(shader_sets: &mut ShaderSets) -> RSGMaterial {
+    fn make_color_material(components: &mut RSGComponentContainer) -> RSGMaterial {
         let mvp_name = "mvp".to_owned();
         let color_name = "color".to_owned();
 
-        if !shader_sets.contains_key(&COLOR_SH_ID) {
-            let shader_set = RSGMaterialShaderSet {
-                vertex_shader: "".to_owned(),
-                fragment_shader: "".to_owned(),
-                properties: vec![
-                    RSGMaterialProperty::Mat4(mvp_name.clone(), glm::one()),
-                    RSGMaterialProperty::Vec3(color_name.clone(), glm::zero())
-                ]
-            };
-            shader_sets.insert(COLOR_SH_ID, shader_set);
-        }
+        // registering the same set again gives the same id
+        let shader_set_id = components.shader_sets.register(RSGMaterialShaderSet {
+            vertex_shader: "".to_owned(),
+            fragment_shader: "".to_owned(),
+            properties: vec![
+                RSGMaterialProperty::Mat4(mvp_name.clone(), glm::one()),
+                RSGMaterialProperty::Vec3(color_name.clone(), glm::zero())
+            ],
+            vertex_inputs: vec![],
+            alpha_cutoff: None
+        }).unwrap();
 
         let mut material = RSGMaterial {
-            shader_set_id: COLOR_SH_ID,
+            shader_set_id,
             property_values: Default::default(),
             graphics_state: Default::default()
         };
@@ -39,7 +38,7 @@ fn scene_with_2d_first_plus_one_3d_layer() {
         material
     }
 
-    fn make_2d_triangle(components: &mut RSGComponentContainer, buffers: &mut MeshBuffers, shader_sets: &mut ShaderSets,
+    fn make_2d_triangle(components: &mut RSGComponentContainer, buffers: &mut MeshBuffers,
         local_transform: glm::Mat4, opacity: f32) -> RSGNode<RSGComponentLinks>
     {
         if !buffers.contains_key(&TRIANGLE2D_BUF_ID) {
@@ -68,10 +67,11 @@ fn scene_with_2d_first_plus_one_3d_layer() {
                 index_count: None,
                 index_view: None
             }],
-            bounds_3d: None
+            bounds_3d: None,
+            bounding_sphere: None
         };
 
-        let material = make_color_material(shader_sets);
+        let material = make_color_material(components);
 
         RSGNode::with_component_links(
             RSGComponentBuilder::new(components)
@@ -82,7 +82,7 @@ fn scene_with_2d_first_plus_one_3d_layer() {
             .links())
     }
 
-    fn make_3d_triangle(components: &mut RSGComponentContainer, buffers: &mut MeshBuffers, shader_sets: &mut ShaderSets,
+    fn make_3d_triangle(components: &mut RSGComponentContainer, buffers: &mut MeshBuffers,
         local_transform: glm::Mat4, opacity: f32) -> RSGNode<RSGComponentLinks>
     {
         if !buffers.contains_key(&TRIANGLE3D_BUF_ID) {
@@ -115,9 +115,10 @@ fn scene_with_2d_first_plus_one_3d_layer() {
                 minimum: glm::vec3(-1.0, -1.0, 0.0),
                 maximum: glm::vec3(1.0, 1.0, 0.0)
             }),
+            bounding_sphere: None
         };
 
-        let material = make_color_material(shader_sets);
+        let material = make_color_material(components);
 
         RSGNode::with_component_links(
             RSGComponentBuilder::new(components)
@@ -132,13 +133,10 @@ fn scene_with_2d_first_plus_one_3d_layer() {
     struct Data {
         components: RSGComponentContainer,
         mesh_buffers: MeshBuffers,
-        shader_sets: ShaderSets,
         opaque_list_2d: RSGRenderList,
         alpha_list_2d: RSGRenderList,
         opaque_list_3d: RSGRenderList,
         alpha_list_3d: RSGRenderList,
-        camera_3d: RSGCamera,
-        camera_3d_properties: RSGCameraWorldTransformDerivedProperties,
         root_key: RSGNodeKey,
         layer2d_key: RSGNodeKey,
         layer3d_key: RSGNodeKey,
@@ -154,6 +152,7 @@ fn scene_with_2d_first_plus_one_3d_layer() {
         tri_3d2_key: RSGNodeKey,
         tri_3d_alpha1_key: RSGNodeKey,
         tri_3d_alpha2_key: RSGNodeKey,
+        camera_3d_key: RSGNodeKey,
     }
 
     fn sync(d: &mut Data, scene: &mut Scene) {
@@ -163,20 +162,29 @@ fn scene_with_2d_first_plus_one_3d_layer() {
             let subtree_keys = RSGSubtreeBuilder::new(scene, d.root_key)
             .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut d.components).layer().links()))
             // 2D, opaque
-            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(50.0, 100.0, 0.0)), 1.0))
-            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(10.0, 20.0, 0.0)), 1.0))
-            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(-5.0, 0.0, 0.0)), 1.0))
+            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(50.0, 100.0, 0.0)), 1.0))
+            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(10.0, 20.0, 0.0)), 1.0))
+            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(-5.0, 0.0, 0.0)), 1.0))
             // 2D, alpha
-            .append_to(1, make_2d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(25.0, 32.0, 0.0)), 0.8))
-            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(50.0, 100.0, 0.0)), 1.0))
-            // ... tri_alpha1(tri_alpha2, layer(tri_3d1(tri_3d2, tri_3d_alpha1(tri_3d_alpha2))))
+            .append_to(1, make_2d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(25.0, 32.0, 0.0)), 0.8))
+            .append(make_2d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(50.0, 100.0, 0.0)), 1.0))
+            // ... tri_alpha1(tri_alpha2, layer(tri_3d1(tri_3d2, tri_3d_alpha1(tri_3d_alpha2)), camera))
             // 3D, opaque
             .append_to(4, RSGNode::with_component_links(RSGComponentBuilder::new(&mut d.components).layer().links()))
-            .append(make_3d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(0.0, 0.0, -1.0)), 1.0))
-            .append(make_3d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(0.5, 0.5, -5.0)), 1.0))
+            .append(make_3d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(0.0, 0.0, -1.0)), 1.0))
+            .append(make_3d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(0.5, 0.5, -5.0)), 1.0))
             // 3D, alpha
-            .append_to(7, make_3d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(-1.5, 0.0, -2.0)), 0.5))
-            .append(make_3d_triangle(&mut d.components, &mut d.mesh_buffers, &mut d.shader_sets, glm::translation(&glm::vec3(0.0, 1.0, 1.0)), 0.2))
+            .append_to(7, make_3d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(-1.5, 0.0, -2.0)), 0.5))
+            .append(make_3d_triangle(&mut d.components, &mut d.mesh_buffers, glm::translation(&glm::vec3(0.0, 1.0, 1.0)), 0.2))
+            .append_to(6, RSGNode::with_component_links(RSGComponentBuilder::new(&mut d.components)
+                .transform(glm::translation(&glm::vec3(0.0, 0.0, 600.0)))
+                .camera(RSGCamera::Perspective(RSGPerspectiveProjection {
+                    aspect_ratio: 1.777,
+                    fov: 45.0,
+                    near: 0.01,
+                    far: 1000.0
+                }))
+                .links()))
             .commit();
             d.layer2d_key = subtree_keys[0];
             d.tri1_key = subtree_keys[1];
@@ -189,35 +197,31 @@ fn scene_with_2d_first_plus_one_3d_layer() {
             d.tri_3d2_key = subtree_keys[8];
             d.tri_3d_alpha1_key = subtree_keys[9];
             d.tri_3d_alpha2_key = subtree_keys[10];
+            d.camera_3d_key = subtree_keys[11];
 
-            d.camera_3d = RSGCamera::Perspective(RSGPerspectiveProjection {
-                aspect_ratio: 1.777,
-                fov: 45.0,
-                near: 0.01,
-                far: 1000.0
-            });
-            d.camera_3d_properties = RSGCameraWorldTransformDerivedProperties::new(&glm::translation(&glm::vec3(0.0, 0.0, 600.0)));
+            d.components.set_active_camera(scene, d.layer3d_key, Some(d.camera_3d_key));
         }
     }
 
     fn update(components: &mut RSGComponentContainer, scene: &Scene, observer: &RSGSceneObserver,
         layer_2d_key: RSGNodeKey, opaque_list_2d: &mut RSGRenderList, alpha_list_2d: &mut RSGRenderList,
-        layer_3d_key: RSGNodeKey, camera_3d_properties: RSGCameraWorldTransformDerivedProperties, opaque_list_3d: &mut RSGRenderList, alpha_list_3d: &mut RSGRenderList,
+        layer_3d_key: RSGNodeKey, opaque_list_3d: &mut RSGRenderList, alpha_list_3d: &mut RSGRenderList,
         pool: &scoped_pool::Pool)
     {
         println!("Update scene, changes={:?}", observer);
         if observer.changed {
             update_inherited_properties(components, scene, &observer.dirty_world_roots, &observer.dirty_opacity_roots, &pool);
+            let camera_3d_properties = components.active_camera(scene, layer_3d_key).unwrap().world_properties;
             pool.scoped(|scope| {
                 let components_ref = &components;
                 let (two2d_tx, two2d_rx) = std::sync::mpsc::channel();
                 scope.execute(move || {
-                    build_layer_render_lists(components_ref, scene, layer_2d_key, None, opaque_list_2d, alpha_list_2d);
+                    build_layer_render_lists(components_ref, scene, layer_2d_key, None, None, opaque_list_2d, alpha_list_2d);
                     two2d_tx.send(()).unwrap();
                 });
                 let (three3d_tx, three3d_rx) = std::sync::mpsc::channel();
                 scope.execute(move || {
-                    build_layer_render_lists(components_ref, scene, layer_3d_key, Some(camera_3d_properties), opaque_list_3d, alpha_list_3d);
+                    build_layer_render_lists(components_ref, scene, layer_3d_key, Some(camera_3d_properties), None, opaque_list_3d, alpha_list_3d);
                     three3d_tx.send(()).unwrap();
                 });
                 two2d_rx.recv().unwrap();
@@ -231,7 +235,9 @@ fn scene_with_2d_first_plus_one_3d_layer() {
         d.components.print_scene(scene, d.root_key, Some(10));
         println!("  2D opaque list={:?}", d.opaque_list_2d);
         println!("  2D alpha list={:?}", d.alpha_list_2d);
-        println!("  3D camera={:?} {:?}", d.camera_3d, d.camera_3d_properties);
+        let camera_3d = d.components.active_camera(scene, d.layer3d_key).unwrap();
+        println!("  3D camera={:?} {:?}", camera_3d.camera, camera_3d.world_properties);
+        assert!(camera_3d.world_properties.position == glm::vec3(0.0, 0.0, 600.0));
         println!("  3D opaque list={:?}", d.opaque_list_3d);
         println!("  3D alpha list={:?}", d.alpha_list_3d);
 
@@ -252,6 +258,15 @@ fn scene_with_2d_first_plus_one_3d_layer() {
             (d.tri_3d_alpha1_key, 603.0),
             (d.tri_3d_alpha2_key, 602.0),
         ]);
+
+        let mut values = RSGDrawValueTable::default();
+        resolve_render_list_values(&d.components, scene, &d.opaque_list_3d, Some(camera_3d), &Default::default(), &mut values);
+        assert!(values.draws.len() == 2);
+        let tri_3d1_world = d.components.transforms[scene.get_component_links(d.tri_3d1_key).transform_key.unwrap()].world_transform;
+        assert!(values.draw_values(0) == [
+            RSGMaterialCustomValue::Mat4(camera_3d.view_projection_matrix(&Default::default()) * tri_3d1_world),
+            RSGMaterialCustomValue::Vec3(glm::vec3(1.0, 0.0, 0.0))
+        ]);
     }
 
     fn frame(d: &mut Data, scene: &mut Scene, pool: &scoped_pool::Pool) {
@@ -261,7 +276,7 @@ fn scene_with_2d_first_plus_one_3d_layer() {
         observer = scene.take_observer().unwrap();
         update(&mut d.components, scene, &observer,
             d.layer2d_key, &mut d.opaque_list_2d, &mut d.alpha_list_2d,
-            d.layer3d_key, d.camera_3d_properties, &mut d.opaque_list_3d, &mut d.alpha_list_3d,
+            d.layer3d_key, &mut d.opaque_list_3d, &mut d.alpha_list_3d,
             pool);
         render(d, scene);
         d.frame_count += 1;
@@ -276,3 +291,1759 @@ fn scene_with_2d_first_plus_one_3d_layer() {
 
     pool.shutdown();
 }
+
+fn translation_of(m: &glm::Mat4) -> glm::Vec3 {
+    glm::vec3(m[12], m[13], m[14])
+}
+
+fn update_transforms(components: &mut RSGComponentContainer, scene: &Scene, dirty_world_roots: &[RSGNodeKey], pool: &scoped_pool::Pool) {
+    update_inherited_properties(components, scene, dirty_world_roots, &[], pool);
+}
+
+#[test]
+fn world_transforms_deep_chain() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    let mut builder = RSGSubtreeBuilder::new(&mut scene, root_key);
+    for _ in 0..1000 {
+        builder.append(RSGNode::with_component_links(
+            RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(1.0, 0.0, 0.0))).links()));
+    }
+    let keys = builder.commit();
+    update_transforms(&mut components, &scene, &[keys[0]], &pool);
+
+    for (i, key) in keys.iter().enumerate() {
+        let t = components.transforms[scene.get_component_links(*key).transform_key.unwrap()];
+        assert!(translation_of(&t.world_transform) == glm::vec3((i + 1) as f32, 0.0, 0.0));
+    }
+
+    // change a node in the middle, only its subtree is affected
+    let mid_transform_key = scene.get_component_links(keys[499]).transform_key.unwrap();
+    components.transforms[mid_transform_key].local_transform = glm::translation(&glm::vec3(101.0, 0.0, 0.0));
+    update_transforms(&mut components, &scene, &[keys[499], keys[700], keys[499]], &pool);
+    let t = components.transforms[scene.get_component_links(keys[498]).transform_key.unwrap()];
+    assert!(translation_of(&t.world_transform) == glm::vec3(499.0, 0.0, 0.0));
+    let t = components.transforms[mid_transform_key];
+    assert!(translation_of(&t.world_transform) == glm::vec3(600.0, 0.0, 0.0));
+    let t = components.transforms[scene.get_component_links(keys[999]).transform_key.unwrap()];
+    assert!(translation_of(&t.world_transform) == glm::vec3(1100.0, 0.0, 0.0));
+
+    pool.shutdown();
+}
+
+#[test]
+fn world_transforms_wide_and_layers() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+    let root_transform_key = scene.get_component_links(root_key).transform_key.unwrap();
+    components.transforms[root_transform_key].local_transform = glm::translation(&glm::vec3(0.0, 10.0, 0.0));
+
+    // root(group(a, b, layer(c, group(d))))
+    let group_key = scene.append(root_key, RSGNode::new());
+    let a_key = scene.append(group_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(1.0, 0.0, 0.0))).links()));
+    let b_key = scene.append(group_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(2.0, 0.0, 0.0))).links()));
+    let layer_key = scene.append(group_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).layer().links()));
+    let c_key = scene.append(layer_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(3.0, 0.0, 0.0))).links()));
+    let group2_key = scene.append(layer_key, RSGNode::new());
+    let d_key = scene.append(group2_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(4.0, 0.0, 0.0))).links()));
+
+    update_transforms(&mut components, &scene, &[root_key], &pool);
+
+    fn world(components: &RSGComponentContainer, scene: &Scene, key: RSGNodeKey) -> glm::Vec3 {
+        translation_of(&components.transforms[scene.get_component_links(key).transform_key.unwrap()].world_transform)
+    }
+    assert!(world(&components, &scene, a_key) == glm::vec3(1.0, 10.0, 0.0));
+    assert!(world(&components, &scene, b_key) == glm::vec3(2.0, 10.0, 0.0));
+    // layer boundary, nothing inherited from above
+    assert!(world(&components, &scene, c_key) == glm::vec3(3.0, 0.0, 0.0));
+    assert!(world(&components, &scene, d_key) == glm::vec3(4.0, 0.0, 0.0));
+
+    // dirty root inside the group, ancestors' world transform picked up
+    components.transforms[scene.get_component_links(b_key).transform_key.unwrap()].local_transform = glm::translation(&glm::vec3(5.0, 0.0, 0.0));
+    update_transforms(&mut components, &scene, &[b_key, d_key], &pool);
+    assert!(world(&components, &scene, b_key) == glm::vec3(5.0, 10.0, 0.0));
+    assert!(world(&components, &scene, d_key) == glm::vec3(4.0, 0.0, 0.0));
+
+    // many siblings
+    let wide_keys: Vec<RSGNodeKey> = (0..10000).map(|i| scene.append(group_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(i as f32, 0.0, 0.0))).links()))).collect();
+    update_transforms(&mut components, &scene, &[group_key], &pool);
+    for (i, key) in wide_keys.iter().enumerate() {
+        assert!(world(&components, &scene, *key) == glm::vec3(i as f32, 10.0, 0.0));
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn world_transforms_compose_parent_then_local() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    // the child is moved along the parent's rotated x axis, not the world's
+    let rotation = glm::rotation(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 0.0, 1.0));
+    let parent_key = scene.append(root_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(10.0, 0.0, 0.0)) * rotation).links()));
+    let child_key = scene.append(parent_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(1.0, 0.0, 0.0))).links()));
+    update_transforms(&mut components, &scene, &[root_key], &pool);
+
+    let child = components.transforms[scene.get_component_links(child_key).transform_key.unwrap()];
+    assert!(glm::distance(&translation_of(&child.world_transform), &glm::vec3(10.0, 1.0, 0.0)) < 0.0001);
+
+    pool.shutdown();
+}
+
+fn approx_eq_vec3(a: &glm::Vec3, b: &glm::Vec3) -> bool {
+    glm::distance(a, b) < 0.0001
+}
+
+fn approx_eq_mat4(a: &glm::Mat4, b: &glm::Mat4) -> bool {
+    (0..16).all(|i| (a[i] - b[i]).abs() < 0.0001)
+}
+
+fn transform_point(m: &glm::Mat4, p: &glm::Vec3) -> glm::Vec3 {
+    glm::vec4_to_vec3(&(m * glm::vec4(p.x, p.y, p.z, 1.0)))
+}
+
+#[test]
+fn trs_compose_and_decompose() {
+    let rotation = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 0.0, 1.0));
+    let trs = RSGTransformTRS::new(glm::vec3(10.0, 0.0, 0.0), rotation, glm::vec3(2.0, 2.0, 2.0));
+    let m = trs.to_matrix();
+    assert!(approx_eq_vec3(&transform_point(&m, &glm::vec3(1.0, 0.0, 0.0)), &glm::vec3(10.0, 2.0, 0.0)));
+
+    let d = RSGTransformTRS::from_matrix(&m);
+    assert!(approx_eq_vec3(&d.translation, &trs.translation));
+    assert!(approx_eq_vec3(&d.scale, &trs.scale));
+    assert!(approx_eq_mat4(&d.to_matrix(), &m));
+
+    // pivot at (1, 0, 0): that point stays in place (apart from the translation)
+    let mut trs = trs;
+    trs.pivot = glm::vec3(1.0, 0.0, 0.0);
+    let m = trs.to_matrix();
+    assert!(approx_eq_vec3(&transform_point(&m, &trs.pivot), &glm::vec3(11.0, 0.0, 0.0)));
+    let d = RSGTransformTRS::from_matrix_with_pivot(&m, trs.pivot);
+    assert!(approx_eq_vec3(&d.translation, &trs.translation));
+    assert!(approx_eq_mat4(&d.to_matrix(), &m));
+}
+
+#[test]
+fn trs_look_at_and_rotate_around() {
+    let mut trs = RSGTransformTRS::new(glm::vec3(0.0, 0.0, 10.0), glm::quat_identity(), glm::vec3(1.0, 1.0, 1.0));
+    trs.look_at(&glm::vec3(10.0, 0.0, 10.0), &glm::vec3(0.0, 1.0, 0.0));
+    let m = trs.to_matrix();
+    // -Z now points towards +X
+    let dir = glm::vec4_to_vec3(&(m * glm::vec4(0.0, 0.0, -1.0, 0.0)));
+    assert!(approx_eq_vec3(&dir, &glm::vec3(1.0, 0.0, 0.0)));
+    assert!(approx_eq_vec3(&trs.origin(), &glm::vec3(0.0, 0.0, 10.0)));
+
+    // quarter turn around the Y axis through the world origin
+    trs.rotate_around(&glm::zero(), &glm::vec3(0.0, 1.0, 0.0), std::f32::consts::FRAC_PI_2);
+    assert!(approx_eq_vec3(&trs.origin(), &glm::vec3(10.0, 0.0, 0.0)));
+    let m = trs.to_matrix();
+    let dir = glm::vec4_to_vec3(&(m * glm::vec4(0.0, 0.0, -1.0, 0.0)));
+    assert!(approx_eq_vec3(&dir, &glm::vec3(0.0, 0.0, -1.0)));
+
+    // looking straight up or down still gives a valid rotation
+    let mut up = RSGTransformTRS::default();
+    up.look_at(&glm::vec3(0.0, 5.0, 0.0), &glm::vec3(0.0, 1.0, 0.0));
+    let dir = glm::vec4_to_vec3(&(up.to_matrix() * glm::vec4(0.0, 0.0, -1.0, 0.0)));
+    assert!(approx_eq_vec3(&dir, &glm::vec3(0.0, 1.0, 0.0)));
+    let mut down = RSGTransformTRS::default();
+    down.look_at(&glm::vec3(0.0, -5.0, 0.0), &glm::zero());
+    let dir = glm::vec4_to_vec3(&(down.to_matrix() * glm::vec4(0.0, 0.0, -1.0, 0.0)));
+    assert!(approx_eq_vec3(&dir, &glm::vec3(0.0, -1.0, 0.0)));
+
+    // looking at the own origin keeps the rotation
+    let before = trs;
+    trs.look_at(&trs.origin(), &glm::vec3(0.0, 1.0, 0.0));
+    assert!(trs == before);
+}
+
+#[test]
+fn trs_decompose_zero_scale() {
+    let rotation = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 0.0, 1.0));
+    let trs = RSGTransformTRS::new(glm::vec3(1.0, 2.0, 3.0), rotation, glm::vec3(2.0, 0.0, 1.0));
+    let d = RSGTransformTRS::from_matrix(&trs.to_matrix());
+    assert!(approx_eq_vec3(&d.scale, &trs.scale));
+    assert!(glm::quat_dot(&d.rotation, &rotation).abs() > 0.9999);
+    assert!(approx_eq_mat4(&d.to_matrix(), &trs.to_matrix()));
+
+    let d = RSGTransformTRS::from_matrix(&glm::scaling(&glm::zero()));
+    assert!(d.rotation == glm::quat_identity() && d.scale == glm::zero());
+}
+
+#[test]
+fn trs_world_space_setters() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    // root(parent(child)), parent is rotated and translated
+    let parent_rotation = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 0.0, 1.0));
+    let keys = RSGSubtreeBuilder::new(&mut scene, root_key)
+        .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+            .trs(RSGTransformTRS::new(glm::vec3(5.0, 0.0, 0.0), parent_rotation, glm::vec3(1.0, 1.0, 1.0))).links()))
+        .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+            .trs(RSGTransformTRS::new(glm::vec3(1.0, 0.0, 0.0), glm::quat_identity(), glm::vec3(1.0, 1.0, 1.0))).links()))
+        .commit();
+    let (parent_key, child_key) = (keys[0], keys[1]);
+    let parent_transform_key = scene.get_component_links(parent_key).transform_key.unwrap();
+    let child_transform_key = scene.get_component_links(child_key).transform_key.unwrap();
+    update_transforms(&mut components, &scene, &[parent_key], &pool);
+
+    // parent's rotation applies to the child's translation
+    assert!(approx_eq_vec3(&translation_of(&components.transforms[child_transform_key].world_transform), &glm::vec3(5.0, 1.0, 0.0)));
+
+    // changing the TRS is picked up lazily by the next update
+    components.transforms[parent_transform_key].trs.as_mut().unwrap().translation = glm::vec3(6.0, 0.0, 0.0);
+    assert!(translation_of(&components.transforms[parent_transform_key].local_transform) == glm::vec3(5.0, 0.0, 0.0));
+    update_transforms(&mut components, &scene, &[parent_key], &pool);
+    assert!(approx_eq_vec3(&translation_of(&components.transforms[child_transform_key].world_transform), &glm::vec3(6.0, 1.0, 0.0)));
+
+    let parent_world = components.parent_world_transform(&scene, child_key);
+    assert!(approx_eq_mat4(&parent_world, &components.transforms[parent_transform_key].world_transform));
+
+    components.transforms[child_transform_key].set_world_translation(&parent_world, &glm::vec3(0.0, 0.0, 3.0));
+    update_transforms(&mut components, &scene, &[child_key], &pool);
+    assert!(approx_eq_vec3(&translation_of(&components.transforms[child_transform_key].world_transform), &glm::vec3(0.0, 0.0, 3.0)));
+
+    components.transforms[child_transform_key].set_world_rotation(&parent_world, &glm::quat_identity());
+    update_transforms(&mut components, &scene, &[child_key], &pool);
+    let world = components.transforms[child_transform_key].world_transform;
+    assert!(approx_eq_mat4(&world, &glm::translation(&glm::vec3(0.0, 0.0, 3.0))));
+
+    let target = glm::translation(&glm::vec3(1.0, 2.0, 3.0)) * glm::scaling(&glm::vec3(2.0, 2.0, 2.0));
+    components.transforms[child_transform_key].set_world_transform(&parent_world, &target);
+    update_transforms(&mut components, &scene, &[child_key], &pool);
+    assert!(approx_eq_mat4(&components.transforms[child_transform_key].world_transform, &target));
+
+    pool.shutdown();
+}
+
+#[test]
+fn transform_inheritance_modes() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    let parent_rotation = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 0.0, 1.0));
+    let parent_trs = RSGTransformTRS::new(glm::vec3(10.0, 20.0, 30.0), parent_rotation, glm::vec3(2.0, 2.0, 2.0));
+    let local = glm::translation(&glm::vec3(1.0, 0.0, 0.0));
+    let modes = [
+        RSGTransformInheritance::Full,
+        RSGTransformInheritance::TranslationOnly,
+        RSGTransformInheritance::NoScale,
+        RSGTransformInheritance::NoRotation,
+        RSGTransformInheritance::Absolute
+    ];
+    let expected_origins = [
+        glm::vec3(10.0, 22.0, 30.0),
+        glm::vec3(11.0, 20.0, 30.0),
+        glm::vec3(10.0, 21.0, 30.0),
+        glm::vec3(12.0, 20.0, 30.0),
+        glm::vec3(1.0, 0.0, 0.0)
+    ];
+
+    // parent(mode_node(grandchild), ..., layer(mode_node, ...), transformed_layer(mode_node, ...))
+    let parent_key = scene.append(root_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).trs(parent_trs).links()));
+    let layer_key = scene.append(parent_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).layer().links()));
+    let layer_offset = glm::translation(&glm::vec3(0.0, 0.0, 5.0));
+    let transformed_layer_key = scene.append(parent_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).layer().transform(layer_offset).links()));
+    let mut keys = vec![];
+    let mut grandchild_keys = vec![];
+    let mut layer_keys = vec![];
+    let mut transformed_layer_keys = vec![];
+    for mode in modes.iter() {
+        let key = scene.append(parent_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).transform(local).inheritance(*mode).links()));
+        keys.push(key);
+        grandchild_keys.push(scene.append(key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).transform(local).links())));
+        layer_keys.push(scene.append(layer_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).transform(local).inheritance(*mode).links())));
+        transformed_layer_keys.push(scene.append(transformed_layer_key, RSGNode::with_component_links(
+            RSGComponentBuilder::new(&mut components).transform(local).inheritance(*mode).links())));
+    }
+    update_transforms(&mut components, &scene, &[parent_key], &pool);
+
+    let world = |components: &RSGComponentContainer, key: RSGNodeKey| {
+        components.transforms[scene.get_component_links(key).transform_key.unwrap()].world_transform
+    };
+    for i in 0..modes.len() {
+        let w = world(&components, keys[i]);
+        assert!(approx_eq_vec3(&translation_of(&w), &expected_origins[i]));
+        // the grandchild inherits its parent's effective world transform in full
+        assert!(approx_eq_mat4(&world(&components, grandchild_keys[i]), &(w * local)));
+        // nothing is inherited across a layer boundary without a transform, whatever the mode
+        assert!(approx_eq_mat4(&world(&components, layer_keys[i]), &local));
+        // a layer's own transform is inherited according to the mode; the layer is
+        // moved by (0, 0, 10) in world space, as the parent scales by 2
+        let expected = match modes[i] {
+            RSGTransformInheritance::Absolute => local,
+            _ => glm::translation(&glm::vec3(0.0, 0.0, 10.0)) * w
+        };
+        assert!(approx_eq_mat4(&world(&components, transformed_layer_keys[i]), &expected));
+    }
+    assert!(approx_eq_mat4(&world(&components, transformed_layer_key), &(world(&components, parent_key) * layer_offset)));
+
+    // without a transform there is nothing to apply the mode to
+    let without_transform = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        RSGComponentBuilder::new(&mut components).inheritance(RSGTransformInheritance::Absolute);
+    }));
+    assert!(without_transform.is_err());
+
+    let parent_world = world(&components, parent_key);
+    let full = world(&components, keys[0]);
+    assert!(approx_eq_mat4(&full, &(parent_world * local)));
+    let translation_only = world(&components, keys[1]);
+    assert!(approx_eq_mat4(&translation_only, &glm::translation(&translation_of(&translation_only))));
+    let no_scale = RSGTransformTRS::from_matrix(&world(&components, keys[2]));
+    assert!(approx_eq_vec3(&no_scale.scale, &glm::vec3(1.0, 1.0, 1.0)));
+    let no_rotation = RSGTransformTRS::from_matrix(&world(&components, keys[3]));
+    assert!(approx_eq_vec3(&no_rotation.scale, &glm::vec3(2.0, 2.0, 2.0)));
+    assert!(approx_eq_mat4(&glm::quat_to_mat4(&no_rotation.rotation), &glm::one()));
+    assert!(approx_eq_mat4(&world(&components, keys[4]), &local));
+
+    // world-space setters account for the inheritance mode
+    for key in keys.iter() {
+        let parent_world = components.parent_world_transform(&scene, *key);
+        let transform_key = scene.get_component_links(*key).transform_key.unwrap();
+        components.transforms[transform_key].set_world_translation(&parent_world, &glm::vec3(-3.0, 4.0, 5.0));
+    }
+    update_transforms(&mut components, &scene, &keys, &pool);
+    for key in keys.iter() {
+        assert!(approx_eq_vec3(&translation_of(&world(&components, *key)), &glm::vec3(-3.0, 4.0, 5.0)));
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn derived_matrices() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    let rotation = glm::quat_angle_axis(0.5, &glm::vec3(0.0, 1.0, 0.0));
+    let keys = RSGSubtreeBuilder::new(&mut scene, root_key)
+        .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+            .trs(RSGTransformTRS::new(glm::vec3(1.0, 2.0, 3.0), rotation, glm::vec3(1.0, 2.0, 4.0))).links()))
+        .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+            .transform(glm::translation(&glm::vec3(0.0, 0.0, 5.0))).derived_matrices().links()))
+        .commit();
+    let parent_transform_key = scene.get_component_links(keys[0]).transform_key.unwrap();
+    let child_transform_key = scene.get_component_links(keys[1]).transform_key.unwrap();
+    update_transforms(&mut components, &scene, &[keys[0]], &pool);
+
+    // only where requested
+    assert!(!components.transform_derived.contains_key(parent_transform_key));
+    let check = |components: &RSGComponentContainer| {
+        let world = components.transforms[child_transform_key].world_transform;
+        let d = components.transform_derived[child_transform_key];
+        assert!(approx_eq_mat4(&(world * d.inverse_world_transform), &glm::one()));
+        let expected_normal = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&world)));
+        assert!((0..9).all(|i| (d.normal_matrix[i] - expected_normal[i]).abs() < 0.0001));
+        let camera = RSGCameraWorldTransformDerivedProperties::with_derived_matrices(&world, &d);
+        let expected_camera = RSGCameraWorldTransformDerivedProperties::new(&world);
+        assert!(approx_eq_vec3(&camera.position, &expected_camera.position));
+        assert!(approx_eq_vec3(&camera.direction, &expected_camera.direction));
+    };
+    check(&components);
+
+    // invalidated together with the world transform
+    components.transforms[parent_transform_key].trs.as_mut().unwrap().scale = glm::vec3(3.0, 1.0, 1.0);
+    update_transforms(&mut components, &scene, &[keys[0]], &pool);
+    check(&components);
+
+    components.disable_derived_matrices(child_transform_key);
+    update_transforms(&mut components, &scene, &[keys[0]], &pool);
+    assert!(!components.transform_derived.contains_key(child_transform_key));
+
+    // without a transform there is nothing to derive from
+    let derived_before = components.transform_derived.len();
+    let without_transform = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        RSGComponentBuilder::new(&mut components).derived_matrices();
+    }));
+    assert!(without_transform.is_err());
+    assert!(components.transform_derived.len() == derived_before);
+
+    pool.shutdown();
+}
+
+fn mesh_with_bounds(minimum: glm::Vec3, maximum: glm::Vec3) -> RSGMesh {
+    RSGMesh {
+        vertex_views: smallvec::smallvec![],
+        submeshes: smallvec::smallvec![],
+        bounds_3d: Some(RSGAabb { minimum, maximum }),
+        bounding_sphere: None
+    }
+}
+
+fn mesh_node(components: &mut RSGComponentContainer, local_transform: glm::Mat4) -> RSGNode<RSGComponentLinks> {
+    RSGNode::with_component_links(RSGComponentBuilder::new(components)
+        .transform(local_transform)
+        .mesh(mesh_with_bounds(glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)))
+        .links())
+}
+
+#[test]
+fn world_bounds() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    // root(group(a, b(c), layer(d)))
+    let group_key = scene.append(root_key, RSGNode::new());
+    let a_key = scene.append(group_key, mesh_node(&mut components, glm::translation(&glm::vec3(10.0, 0.0, 0.0))));
+    let b_key = scene.append(group_key, mesh_node(&mut components, glm::translation(&glm::vec3(-10.0, 0.0, 0.0))));
+    let c_key = scene.append(b_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 5.0, 0.0)) * glm::scaling(&glm::vec3(2.0, 2.0, 2.0))));
+    let layer_key = scene.append(group_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).layer().links()));
+    let d_key = scene.append(layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, 100.0))));
+
+    let update = |components: &mut RSGComponentContainer, scene: &Scene, world_roots: &[RSGNodeKey], mesh_nodes: &[RSGNodeKey]| {
+        update_inherited_properties(components, scene, world_roots, &[], &pool);
+        update_world_bounds(components, scene, world_roots, mesh_nodes);
+    };
+    update(&mut components, &scene, &[root_key], &[root_key]);
+
+    let b = components.node_bounds[a_key];
+    assert!(b.world_aabb == Some(RSGAabb { minimum: glm::vec3(9.0, -1.0, -1.0), maximum: glm::vec3(11.0, 1.0, 1.0) }));
+    let sphere = b.world_sphere.unwrap();
+    assert!(approx_eq_vec3(&sphere.center, &glm::vec3(10.0, 0.0, 0.0)));
+    assert!((sphere.radius - 3.0f32.sqrt()).abs() < 0.0001);
+    assert!(b.subtree_aabb == b.world_aabb);
+
+    // c is scaled and its world transform includes b's
+    let c_aabb = components.node_bounds[c_key].world_aabb.unwrap();
+    assert!(c_aabb == RSGAabb { minimum: glm::vec3(-12.0, 3.0, -2.0), maximum: glm::vec3(-8.0, 7.0, 2.0) });
+    assert!((components.node_bounds[c_key].world_sphere.unwrap().radius - 2.0 * 3.0f32.sqrt()).abs() < 0.0001);
+    assert!(components.node_bounds[b_key].subtree_aabb == Some(RSGAabb { minimum: glm::vec3(-12.0, -1.0, -2.0), maximum: glm::vec3(-8.0, 7.0, 2.0) }));
+
+    // the layer keeps its own aggregate, which does not make it into the group's
+    let group_aabb = RSGAabb { minimum: glm::vec3(-12.0, -1.0, -2.0), maximum: glm::vec3(11.0, 7.0, 2.0) };
+    assert!(components.node_bounds[group_key].world_aabb.is_none());
+    assert!(components.node_bounds[group_key].subtree_aabb == Some(group_aabb));
+    assert!(components.node_bounds[root_key].subtree_aabb == Some(group_aabb));
+    assert!(components.node_bounds[layer_key].subtree_aabb == components.node_bounds[d_key].world_aabb);
+
+    // transform change, ancestors are updated incrementally
+    let a_transform_key = scene.get_component_links(a_key).transform_key.unwrap();
+    components.transforms[a_transform_key].local_transform = glm::translation(&glm::vec3(20.0, 0.0, 0.0));
+    update(&mut components, &scene, &[a_key], &[]);
+    assert!(components.node_bounds[group_key].subtree_aabb.unwrap().maximum == glm::vec3(21.0, 7.0, 2.0));
+    assert!(components.node_bounds[root_key].subtree_aabb.unwrap().maximum == glm::vec3(21.0, 7.0, 2.0));
+
+    // mesh change
+    let c_mesh_key = scene.get_component_links(c_key).mesh_key.unwrap();
+    components.mesh_data[c_mesh_key].bounds_3d = Some(RSGAabb { minimum: glm::vec3(0.0, 0.0, 0.0), maximum: glm::vec3(1.0, 10.0, 1.0) });
+    update(&mut components, &scene, &[], &[c_key]);
+    assert!(components.node_bounds[c_key].world_aabb == Some(RSGAabb { minimum: glm::vec3(-10.0, 5.0, 0.0), maximum: glm::vec3(-8.0, 25.0, 2.0) }));
+    assert!(components.node_bounds[root_key].subtree_aabb == Some(RSGAabb { minimum: glm::vec3(-11.0, -1.0, -1.0), maximum: glm::vec3(21.0, 25.0, 2.0) }));
+
+    // the entries of removed nodes are dropped with the next update
+    let links = scene.remove(b_key);
+    components.remove(links);
+    update(&mut components, &scene, &[], &[a_key]);
+    assert!(components.node_bounds.len() == scene.node_count());
+    assert!(!components.node_bounds.contains_key(b_key) && !components.node_bounds.contains_key(c_key));
+    // and updating a tightens the ancestors again
+    assert!(components.node_bounds[root_key].subtree_aabb == components.node_bounds[a_key].world_aabb);
+
+    // a mesh without a transform of its own is placed by the nearest transformed ancestor
+    let mesh_only_key = scene.append(a_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+        .mesh(mesh_with_bounds(glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0))).links()));
+    update(&mut components, &scene, &[mesh_only_key], &[]);
+    assert!(components.node_bounds[mesh_only_key].world_aabb == components.node_bounds[a_key].world_aabb);
+    assert!(approx_eq_vec3(&components.node_bounds[mesh_only_key].world_sphere.unwrap().center, &glm::vec3(20.0, 0.0, 0.0)));
+    assert!(approx_eq_mat4(&components.world_transform(&scene, mesh_only_key), &components.world_transform(&scene, a_key)));
+    components.transforms[a_transform_key].local_transform = glm::translation(&glm::vec3(30.0, 0.0, 0.0));
+    update(&mut components, &scene, &[a_key], &[]);
+    assert!(components.node_bounds[mesh_only_key].world_aabb == Some(RSGAabb { minimum: glm::vec3(29.0, -1.0, -1.0), maximum: glm::vec3(31.0, 1.0, 1.0) }));
+
+    pool.shutdown();
+}
+
+#[test]
+fn frustum_tests() {
+    let camera = RSGCamera::Perspective(RSGPerspectiveProjection { aspect_ratio: 1.0, fov: 90.0, near: 1.0, far: 100.0 });
+    let frustum = RSGFrustum::new(&camera, &glm::one());
+    assert!(frustum.test_point(&glm::vec3(0.0, 0.0, -10.0)));
+    assert!(frustum.test_point(&glm::vec3(9.0, -9.0, -10.0)));
+    assert!(!frustum.test_point(&glm::vec3(11.0, 0.0, -10.0)));
+    assert!(!frustum.test_point(&glm::vec3(0.0, 0.0, -0.5)));
+    assert!(!frustum.test_point(&glm::vec3(0.0, 0.0, -101.0)));
+    assert!(!frustum.test_point(&glm::vec3(0.0, 0.0, 10.0)));
+
+    let aabb = |c: glm::Vec3, e: f32| RSGAabb { minimum: c - glm::vec3(e, e, e), maximum: c + glm::vec3(e, e, e) };
+    assert!(frustum.test_aabb(&aabb(glm::vec3(0.0, 0.0, -10.0), 1.0)) == RSGFrustumTest::Inside);
+    assert!(frustum.test_aabb(&aabb(glm::vec3(10.0, 0.0, -10.0), 1.0)) == RSGFrustumTest::Intersecting);
+    assert!(frustum.test_aabb(&aabb(glm::vec3(20.0, 0.0, -10.0), 1.0)) == RSGFrustumTest::Outside);
+    assert!(frustum.test_sphere(&RSGSphere { center: glm::vec3(0.0, 0.0, -10.0), radius: 1.0 }) == RSGFrustumTest::Inside);
+    assert!(frustum.test_sphere(&RSGSphere { center: glm::vec3(0.0, 0.0, -100.5), radius: 1.0 }) == RSGFrustumTest::Intersecting);
+    assert!(frustum.test_sphere(&RSGSphere { center: glm::vec3(0.0, 0.0, 5.0), radius: 1.0 }) == RSGFrustumTest::Outside);
+
+    // the camera's world transform moves the frustum
+    let rotated = glm::translation(&glm::vec3(0.0, 0.0, 50.0)) * glm::rotation(std::f32::consts::PI, &glm::vec3(0.0, 1.0, 0.0));
+    let frustum = RSGFrustum::new(&camera, &rotated);
+    assert!(frustum.test_point(&glm::vec3(0.0, 0.0, 60.0)));
+    assert!(!frustum.test_point(&glm::vec3(0.0, 0.0, 40.0)));
+
+    let camera = RSGCamera::Orthographic(RSGOrthographicProjection { xmag: 2.0, ymag: 1.0, near: 0.0, far: 10.0 });
+    let frustum = RSGFrustum::new(&camera, &glm::one());
+    assert!(frustum.test_point(&glm::vec3(1.9, -0.9, -5.0)));
+    assert!(!frustum.test_point(&glm::vec3(2.1, 0.0, -5.0)));
+    assert!(!frustum.test_point(&glm::vec3(0.0, 1.1, -5.0)));
+}
+
+#[test]
+fn frustum_culling() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    // root(layer(visible, behind, far_away, group(left1, left2), partial(off_screen_child)))
+    let layer_key = scene.append(root_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).layer().links()));
+    let visible_key = scene.append(layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, -10.0))));
+    scene.append(layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, 10.0))));
+    scene.append(layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, -500.0))));
+    let group_key = scene.append(layer_key, RSGNode::new());
+    scene.append(group_key, mesh_node(&mut components, glm::translation(&glm::vec3(-100.0, 0.0, -10.0))));
+    scene.append(group_key, mesh_node(&mut components, glm::translation(&glm::vec3(-110.0, 0.0, -10.0))));
+    let partial_key = scene.append(layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 5.0, -12.0))));
+    scene.append(partial_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 100.0, 0.0))));
+
+    let camera = RSGCamera::Perspective(RSGPerspectiveProjection { aspect_ratio: 1.0, fov: 90.0, near: 1.0, far: 100.0 });
+    let camera_world = glm::one();
+    let frustum = RSGFrustum::new(&camera, &camera_world);
+    let camera_properties = RSGCameraWorldTransformDerivedProperties::new(&camera_world);
+    let mut opaque_list = RSGRenderList::new();
+    let mut alpha_list = RSGRenderList::new();
+
+    // without aggregated bounds every node is tested on its own
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    let stats = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list == vec![(visible_key, 10.0), (partial_key, 12.0)]);
+    assert!(stats == RSGCullingStats { culled_nodes: 5, culled_subtrees: 0 });
+
+    // with aggregated bounds whole subtrees (here all of them, including leaves) are rejected
+    update_world_bounds(&mut components, &scene, &[root_key], &[]);
+    let stats = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list == vec![(visible_key, 10.0), (partial_key, 12.0)]);
+    assert!(stats == RSGCullingStats { culled_nodes: 5, culled_subtrees: 4 });
+
+    // no frustum, no culling
+    let stats = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), None, &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list.len() == 7);
+    assert!(stats == RSGCullingStats::default());
+
+    // stale aggregated bounds: a visible node added to the rejected group is not culled
+    // with it, the group is not rejected as a whole until update_world_bounds runs again
+    let added_key = scene.append(group_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, -8.0))));
+    update_inherited_properties(&mut components, &scene, &[added_key], &[], &pool);
+    assert!(components.stale_bounds_roots.as_slice() == [added_key]);
+    let stats = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list == vec![(added_key, 8.0), (visible_key, 10.0), (partial_key, 12.0)]);
+    assert!(stats == RSGCullingStats { culled_nodes: 5, culled_subtrees: 5 });
+    // the same for a node moved into view, its cached bounds are not used
+    let left_key = scene.children(group_key).next().unwrap();
+    let left_transform_key = scene.get_component_links(left_key).transform_key.unwrap();
+    components.transforms[left_transform_key].local_transform = glm::translation(&glm::vec3(0.0, 0.0, -9.0));
+    update_inherited_properties(&mut components, &scene, &[left_key], &[], &pool);
+    build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list == vec![(added_key, 8.0), (left_key, 9.0), (visible_key, 10.0), (partial_key, 12.0)]);
+    update_world_bounds(&mut components, &scene, &[added_key, left_key], &[]);
+    assert!(components.stale_bounds_roots.is_empty());
+    build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list == vec![(added_key, 8.0), (left_key, 9.0), (visible_key, 10.0), (partial_key, 12.0)]);
+    components.transforms[left_transform_key].local_transform = glm::translation(&glm::vec3(-100.0, 0.0, -10.0));
+    update_inherited_properties(&mut components, &scene, &[left_key], &[], &pool);
+    update_world_bounds(&mut components, &scene, &[left_key], &[]);
+
+    // a mesh without a transform of its own is sorted by the world transform it inherits
+    let moved_group_key = scene.append(layer_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(0.0, 0.0, -40.0))).links()));
+    let inheriting_key = scene.append(moved_group_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+        .mesh(mesh_with_bounds(glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0))).links()));
+    update_inherited_properties(&mut components, &scene, &[moved_group_key], &[], &pool);
+    update_world_bounds(&mut components, &scene, &[moved_group_key], &[]);
+    build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list.last() == Some(&(inheriting_key, 40.0)));
+    let links = scene.remove(moved_group_key);
+    components.remove(links);
+
+    // collecting stops at a nested layer, after the mesh of the layer node itself
+    let mut builder = RSGComponentBuilder::new(&mut components);
+    builder.layer().transform(glm::translation(&glm::vec3(0.0, 0.0, -20.0))).mesh(mesh_with_bounds(glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)));
+    let nested_layer_key = scene.append(layer_key, RSGNode::with_component_links(builder.links()));
+    let nested_key = scene.append(nested_layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, 1.0))));
+    let after_key = scene.append(layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, -30.0))));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), None, &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list.len() == 9 && opaque_list.contains(&(nested_layer_key, 20.0)));
+    assert!(opaque_list.iter().all(|(key, _)| *key != nested_key && *key != after_key));
+
+    // the same when the nested layer is in a rejected subtree, only the mesh of the
+    // layer node itself is counted
+    let links = scene.remove(nested_layer_key);
+    components.remove(links);
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    update_world_bounds(&mut components, &scene, &[root_key], &[]);
+    let stats_before = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list.contains(&(after_key, 30.0)));
+    let culled_group_key = scene.insert_before(after_key, mesh_node(&mut components, glm::translation(&glm::vec3(-200.0, 0.0, -10.0))));
+    let mut builder = RSGComponentBuilder::new(&mut components);
+    builder.layer().transform(glm::translation(&glm::vec3(-200.0, 0.0, -10.0))).mesh(mesh_with_bounds(glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)));
+    let culled_layer_key = scene.append(culled_group_key, RSGNode::with_component_links(builder.links()));
+    scene.append(culled_layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, 1.0))));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    update_world_bounds(&mut components, &scene, &[root_key], &[]);
+    let stats = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(!opaque_list.contains(&(after_key, 30.0)));
+    assert!(stats.culled_subtrees == stats_before.culled_subtrees + 1 && stats.culled_nodes == stats_before.culled_nodes + 2);
+    // and when the rejected subtree is the nested layer
+    let links = scene.remove(culled_group_key);
+    components.remove(links);
+    let mut builder = RSGComponentBuilder::new(&mut components);
+    builder.layer().transform(glm::translation(&glm::vec3(-200.0, 0.0, -10.0))).mesh(mesh_with_bounds(glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)));
+    let culled_layer_key = scene.insert_before(after_key, RSGNode::with_component_links(builder.links()));
+    scene.append(culled_layer_key, mesh_node(&mut components, glm::translation(&glm::vec3(0.0, 0.0, 1.0))));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    update_world_bounds(&mut components, &scene, &[root_key], &[]);
+    let stats = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    assert!(!opaque_list.contains(&(after_key, 30.0)));
+    assert!(stats.culled_subtrees == stats_before.culled_subtrees + 1 && stats.culled_nodes == stats_before.culled_nodes + 1);
+    let links = scene.remove(culled_layer_key);
+    components.remove(links);
+
+    // without camera properties and frustum, those of the active camera are used
+    let camera_key = scene.append(layer_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(camera_world).camera(camera).links()));
+    components.set_active_camera(&scene, layer_key, Some(camera_key));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    let expected_stats = build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), Some(&frustum), &mut opaque_list, &mut alpha_list);
+    let expected_list = opaque_list.clone();
+    assert!(expected_stats.culled_nodes > 0);
+    let stats = build_layer_render_lists(&components, &scene, layer_key, None, None, &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list == expected_list && stats == expected_stats);
+
+    pool.shutdown();
+}
+
+fn project(m: &glm::Mat4, p: &glm::Vec3) -> glm::Vec3 {
+    let c = m * glm::vec4(p.x, p.y, p.z, 1.0);
+    glm::vec3(c.x / c.w, c.y / c.w, c.z / c.w)
+}
+
+#[test]
+fn camera_matrices() {
+    let perspective = RSGCamera::Perspective(RSGPerspectiveProjection { aspect_ratio: 2.0, fov: 90.0, near: 1.0, far: 100.0 });
+    let orthographic = RSGCamera::Orthographic(RSGOrthographicProjection { xmag: 4.0, ymag: 2.0, near: 1.0, far: 100.0 });
+    let near_far = |camera: &RSGCamera, conventions: &RSGProjectionConventions| {
+        let m = camera.projection_matrix(conventions);
+        (project(&m, &glm::vec3(0.0, 0.0, -1.0)).z, project(&m, &glm::vec3(0.0, 0.0, -100.0)).z)
+    };
+
+    for camera in [perspective, orthographic].iter() {
+        let (n, f) = near_far(camera, &RSGProjectionConventions::default());
+        assert!((n + 1.0).abs() < 0.0001 && (f - 1.0).abs() < 0.0001);
+
+        let zo = RSGProjectionConventions { depth_range: RSGDepthRange::ZeroToOne, ..Default::default() };
+        let (n, f) = near_far(camera, &zo);
+        assert!(n.abs() < 0.0001 && (f - 1.0).abs() < 0.0001);
+
+        let zo_reversed = RSGProjectionConventions { depth_range: RSGDepthRange::ZeroToOne, reversed_z: true, ..Default::default() };
+        let (n, f) = near_far(camera, &zo_reversed);
+        assert!((n - 1.0).abs() < 0.0001 && f.abs() < 0.0001);
+
+        let no_reversed = RSGProjectionConventions { reversed_z: true, ..Default::default() };
+        let (n, f) = near_far(camera, &no_reversed);
+        assert!((n - 1.0).abs() < 0.0001 && (f + 1.0).abs() < 0.0001);
+    }
+
+    // top edge of the view volume, 10 units away
+    let top = glm::vec3(0.0, 10.0, -10.0);
+    let m = perspective.projection_matrix(&Default::default());
+    assert!((project(&m, &top).y - 1.0).abs() < 0.0001);
+    assert!((project(&m, &glm::vec3(20.0, 0.0, -10.0)).x - 1.0).abs() < 0.0001);
+    let flipped = RSGProjectionConventions { flip_y: true, ..Default::default() };
+    let m = perspective.projection_matrix(&flipped);
+    assert!((project(&m, &top).y + 1.0).abs() < 0.0001);
+    let m = orthographic.projection_matrix(&Default::default());
+    assert!(approx_eq_vec3(&project(&m, &glm::vec3(4.0, 2.0, -1.0)), &glm::vec3(1.0, 1.0, -1.0)));
+
+    // the view matrix undoes the camera's world transform
+    let camera_world = glm::translation(&glm::vec3(0.0, 0.0, 10.0)) * glm::rotation(0.3, &glm::vec3(0.0, 1.0, 0.0));
+    let view = RSGCamera::view_matrix(&camera_world);
+    assert!(approx_eq_vec3(&transform_point(&view, &glm::vec3(0.0, 0.0, 10.0)), &glm::zero()));
+    let vp = perspective.view_projection_matrix(&camera_world, &Default::default());
+    assert!(approx_eq_mat4(&vp, &(perspective.projection_matrix(&Default::default()) * view)));
+    let in_front = transform_point(&camera_world, &glm::vec3(0.0, 0.0, -50.0));
+    let ndc = project(&vp, &in_front);
+    assert!(ndc.x.abs() < 0.0001 && ndc.y.abs() < 0.0001 && ndc.z > -1.0 && ndc.z < 1.0);
+}
+
+#[test]
+fn camera_components() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    // root(layer(rig(camera), other_camera))
+    let rig_rotation = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 1.0, 0.0));
+    let keys = RSGSubtreeBuilder::new(&mut scene, root_key)
+        .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).layer().links()))
+        .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+            .trs(RSGTransformTRS::new(glm::vec3(0.0, 0.0, 10.0), rig_rotation, glm::vec3(1.0, 1.0, 1.0))).links()))
+        .append(RSGNode::with_component_links(RSGComponentBuilder::new(&mut components)
+            .transform(glm::translation(&glm::vec3(0.0, 0.0, 5.0))).camera(Default::default()).links()))
+        .append_to(0, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).camera(Default::default()).links()))
+        .commit();
+    let (layer_key, rig_key, camera_key, other_camera_key) = (keys[0], keys[1], keys[2], keys[3]);
+
+    assert!(components.active_camera(&scene, layer_key).is_none());
+    components.set_active_camera(&scene, layer_key, Some(camera_key));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+
+    // inherits the rig's transform: rotated to look towards -X, moved 5 units that way
+    let camera = components.active_camera(&scene, layer_key).unwrap();
+    assert!(approx_eq_vec3(&camera.world_properties.position, &glm::vec3(5.0, 0.0, 10.0)));
+    assert!(approx_eq_vec3(&camera.world_properties.direction, &glm::vec3(-1.0, 0.0, 0.0)));
+    assert!(camera.frustum().test_point(&glm::vec3(-10.0, 0.0, 10.0)));
+    assert!(approx_eq_vec3(&transform_point(&camera.view_matrix(), &glm::vec3(5.0, 0.0, 10.0)), &glm::zero()));
+
+    // moving the rig updates the camera
+    let rig_transform_key = scene.get_component_links(rig_key).transform_key.unwrap();
+    components.transforms[rig_transform_key].trs.as_mut().unwrap().translation = glm::vec3(0.0, 3.0, 10.0);
+    update_inherited_properties(&mut components, &scene, &[rig_key], &[], &pool);
+    let camera = components.active_camera(&scene, layer_key).unwrap();
+    assert!(approx_eq_vec3(&camera.world_properties.position, &glm::vec3(5.0, 3.0, 10.0)));
+
+    // a camera without a transform component gets the inherited one (none here, as it is directly under the layer)
+    components.set_active_camera(&scene, layer_key, Some(other_camera_key));
+    let camera = components.active_camera(&scene, layer_key).unwrap();
+    assert!(camera.world_properties == RSGCameraWorldTransformDerivedProperties::default());
+
+    // removing the camera node deactivates it
+    let links = scene.remove(other_camera_key);
+    components.remove(links);
+    assert!(components.active_camera(&scene, layer_key).is_none());
+
+    pool.shutdown();
+}
+
+#[test]
+fn resolve_material_values() {
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    let shader_set_id = components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "".to_owned(),
+        properties: vec![
+            RSGMaterialProperty::Mat4("model".to_owned(), glm::one()),
+            RSGMaterialProperty::Mat4("normal_as_mat4".to_owned(), glm::one()),
+            RSGMaterialProperty::Mat3("normal".to_owned(), glm::one()),
+            RSGMaterialProperty::Mat4("mvp".to_owned(), glm::one()),
+            RSGMaterialProperty::Float("missing".to_owned(), 0.25),
+            RSGMaterialProperty::Vec4("wrong_type".to_owned(), glm::vec4(1.0, 2.0, 3.0, 4.0)),
+            RSGMaterialProperty::Int("custom".to_owned(), 0)
+        ],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let mut material = RSGMaterial {
+        shader_set_id,
+        property_values: Default::default(),
+        graphics_state: Default::default()
+    };
+    let builtin = |b| RSGMaterialPropertyValue::Builtin(b);
+    material.property_values.insert("model".to_owned(), builtin(RSGMaterialBuiltinValue::ModelMatrix));
+    material.property_values.insert("normal_as_mat4".to_owned(), builtin(RSGMaterialBuiltinValue::NormalMatrix));
+    material.property_values.insert("normal".to_owned(), builtin(RSGMaterialBuiltinValue::NormalMatrix));
+    material.property_values.insert("mvp".to_owned(), builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix));
+    material.property_values.insert("custom".to_owned(), RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Int(42)));
+
+    let model = glm::translation(&glm::vec3(1.0, 2.0, 3.0)) * glm::scaling(&glm::vec3(2.0, 2.0, 2.0));
+    let links = RSGComponentBuilder::new(&mut components).transform(model).material(material).links();
+    let node_key = scene.append(root_key, RSGNode::with_component_links(links));
+    // validation filled in "missing" and "wrong_type"; resolving still copes with values edited afterwards
+    let material = &mut components.material_data[links.material_key.unwrap()];
+    material.property_values.remove("missing");
+    material.property_values.insert("wrong_type".to_owned(), RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(5.0)));
+    let plain_key = scene.append(root_key, RSGNode::new());
+    let render_list: RSGRenderList = vec![(node_key, 0.0), (plain_key, 1.0)];
+
+    let mut camera = RSGCameraComponent::new(Default::default());
+    camera.world_transform = glm::translation(&glm::vec3(0.0, 0.0, 10.0));
+    let conventions = RSGProjectionConventions { depth_range: RSGDepthRange::ZeroToOne, ..Default::default() };
+    let mut table = RSGDrawValueTable::default();
+    resolve_render_list_values(&components, &scene, &render_list, Some(&camera), &conventions, &mut table);
+
+    let normal = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&model)));
+    assert!(table.draws.len() == 2);
+    assert!(table.draws[0].shader_set_id == Some(shader_set_id));
+    assert!(table.draw_values(0) == [
+        RSGMaterialCustomValue::Mat4(model),
+        RSGMaterialCustomValue::Mat4(glm::mat3_to_mat4(&normal)),
+        RSGMaterialCustomValue::Mat3(normal),
+        RSGMaterialCustomValue::Mat4(camera.projection_matrix(&conventions) * camera.view_matrix() * model),
+        RSGMaterialCustomValue::Float(0.25),
+        RSGMaterialCustomValue::Vec4(glm::vec4(1.0, 2.0, 3.0, 4.0)),
+        RSGMaterialCustomValue::Int(42)
+    ]);
+    // no material, nothing to resolve, but the entry is still there
+    assert!(table.draws[1] == RSGDrawValueRange { node_key: plain_key, shader_set_id: None, first: 7, count: 0 });
+
+    // without a camera view and projection are identity
+    resolve_render_list_values(&components, &scene, &render_list, None, &conventions, &mut table);
+    assert!(table.draw_values(0)[3] == RSGMaterialCustomValue::Mat4(model));
+
+    // a node without a transform of its own gets the matrices of the nearest transformed ancestor
+    let mut child_material = RSGMaterial { shader_set_id, property_values: Default::default(), graphics_state: Default::default() };
+    child_material.property_values.insert("model".to_owned(), builtin(RSGMaterialBuiltinValue::ModelMatrix));
+    child_material.property_values.insert("normal".to_owned(), builtin(RSGMaterialBuiltinValue::NormalMatrix));
+    let child_key = scene.append(node_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).material(child_material).links()));
+    resolve_render_list_values(&components, &scene, &vec![(child_key, 0.0)], None, &conventions, &mut table);
+    assert!(table.draw_values(0)[0] == RSGMaterialCustomValue::Mat4(model));
+    assert!(table.draw_values(0)[2] == RSGMaterialCustomValue::Mat3(normal));
+}
+
+#[test]
+fn shader_set_registry() {
+    let mut components = RSGComponentContainer::default();
+    let make_set = |fragment_shader: &str| RSGMaterialShaderSet {
+        vertex_shader: "void main() {}".to_owned(),
+        fragment_shader: fragment_shader.to_owned(),
+        properties: vec![
+            RSGMaterialProperty::Mat4("mvp".to_owned(), glm::one()),
+            RSGMaterialProperty::Vec4("color".to_owned(), glm::vec4(1.0, 1.0, 1.0, 1.0)),
+            RSGMaterialProperty::Float("intensity".to_owned(), 0.5)
+        ],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    };
+    let id1 = components.shader_sets.register(make_set("void main() {}")).unwrap();
+    let id2 = components.shader_sets.register(make_set("void main() { discard; }")).unwrap();
+    // the built-in sets do not take any of the ids handed out
+    assert!(id1 == 0 && id2 == 1);
+    // identical sets are shared
+    assert!(components.shader_sets.register(make_set("void main() {}")).unwrap() == id1);
+    assert!(components.shader_sets.len() == RSGBuiltinShaderSet::ALL.len() + 2);
+    assert!(components.shader_sets.get(id2).unwrap().fragment_shader == "void main() { discard; }");
+
+    let material = |shader_set_id, values: Vec<(&str, RSGMaterialPropertyValue)>| RSGMaterial {
+        shader_set_id,
+        property_values: values.into_iter().map(|(n, v)| (n.to_owned(), v)).collect(),
+        graphics_state: Default::default()
+    };
+    let custom = RSGMaterialPropertyValue::Custom;
+    let builtin = RSGMaterialPropertyValue::Builtin;
+
+    // missing values get the declared defaults
+    let links = RSGComponentBuilder::new(&mut components)
+        .material(material(id1, vec![("mvp", builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix))]))
+        .links();
+    let values = &components.material_data[links.material_key.unwrap()].property_values;
+    assert!(values.len() == 3);
+    assert!(values["mvp"] == builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix));
+    assert!(values["color"] == custom(RSGMaterialCustomValue::Vec4(glm::vec4(1.0, 1.0, 1.0, 1.0))));
+    assert!(values["intensity"] == custom(RSGMaterialCustomValue::Float(0.5)));
+
+    let materials_before = components.materials.len();
+    let mut builder = RSGComponentBuilder::new(&mut components);
+    let e = builder.try_material(material(7, vec![])).err().unwrap();
+    assert!(e == RSGMaterialError::UnknownShaderSet(7));
+    assert!(e.to_string() == "shader set 7 is not registered");
+
+    let e = builder.try_material(material(id1, vec![("colour", custom(RSGMaterialCustomValue::Float(1.0)))])).err().unwrap();
+    assert!(e == RSGMaterialError::UnknownProperty { shader_set_id: id1, name: "colour".to_owned() });
+    assert!(e.to_string() == format!("shader set {} has no property named 'colour'", id1));
+
+    let e = builder.try_material(material(id1, vec![("color", custom(RSGMaterialCustomValue::Vec3(glm::zero())))])).err().unwrap();
+    assert!(e.to_string() == format!("property 'color' of shader set {} is a vec4, but the material provides a vec3", id1));
+
+    let e = builder.try_material(material(id1, vec![("intensity", builtin(RSGMaterialBuiltinValue::ModelMatrix))])).err().unwrap();
+    assert!(e == RSGMaterialError::TypeMismatch {
+        shader_set_id: id1,
+        name: "intensity".to_owned(),
+        expected: "float",
+        found: "builtin ModelMatrix".to_owned()
+    });
+    // with several problems the first by name is reported
+    let e = builder.try_material(material(id1, vec![
+        ("zzz", custom(RSGMaterialCustomValue::Float(1.0))),
+        ("intensity", custom(RSGMaterialCustomValue::Int(1))),
+        ("aaa", custom(RSGMaterialCustomValue::Float(1.0)))
+    ])).err().unwrap();
+    assert!(e == RSGMaterialError::UnknownProperty { shader_set_id: id1, name: "aaa".to_owned() });
+    let e = builder.try_material_override("color", custom(RSGMaterialCustomValue::Float(1.0))).err().unwrap();
+    assert!(e == RSGMaterialError::NoMaterial);
+    assert!(builder.links().material_key.is_none());
+    assert!(components.materials.len() == materials_before);
+
+    // the non-failing variants do not accept invalid materials and overrides either, they panic
+    let invalid_material = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        RSGComponentBuilder::new(&mut components).material(material(7, vec![("color", custom(RSGMaterialCustomValue::Float(1.0)))]));
+    }));
+    assert!(invalid_material.is_err());
+    assert!(components.materials.len() == materials_before);
+    let override_without_material = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        RSGComponentBuilder::new(&mut components).material_override("color", custom(RSGMaterialCustomValue::Float(1.0)));
+    }));
+    assert!(override_without_material.is_err());
+    let mut material_key = None;
+    let invalid_override = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut builder = RSGComponentBuilder::new(&mut components);
+        builder.material(material(id1, vec![]));
+        material_key = builder.links().material_key;
+        builder.material_override("color", custom(RSGMaterialCustomValue::Float(1.0)));
+    }));
+    assert!(invalid_override.is_err());
+    assert!(components.material_data[material_key.unwrap()].property_values["color"] == custom(RSGMaterialCustomValue::Vec4(glm::vec4(1.0, 1.0, 1.0, 1.0))));
+
+    // the alpha cutoff of a set has to name one of its float properties
+    let mut cutout_set = make_set("void main() {}");
+    cutout_set.alpha_cutoff = Some("color".to_owned());
+    let e = components.shader_sets.register(cutout_set.clone()).err().unwrap();
+    assert!(e == RSGMaterialError::InvalidAlphaCutoff { name: "color".to_owned() });
+    assert!(e.to_string() == "the alpha cutoff 'color' is not a float property of the shader set");
+    cutout_set.alpha_cutoff = Some("cutoff".to_owned());
+    assert!(components.shader_sets.register(cutout_set.clone()).is_err());
+    cutout_set.alpha_cutoff = Some("intensity".to_owned());
+    assert!(components.shader_sets.register(cutout_set).unwrap() == 2);
+}
+
+#[test]
+fn shared_materials() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    let shader_set_id = components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "".to_owned(),
+        properties: vec![
+            RSGMaterialProperty::Vec4("color".to_owned(), glm::vec4(1.0, 1.0, 1.0, 1.0)),
+            RSGMaterialProperty::Float("roughness".to_owned(), 0.5)
+        ],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let color = |r| RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Vec4(glm::vec4(r, 0.0, 0.0, 1.0)));
+    let mut material = RSGMaterial {
+        shader_set_id,
+        property_values: Default::default(),
+        graphics_state: Default::default()
+    };
+    material.property_values.insert("color".to_owned(), color(0.1));
+    let shared_key = components.add_shared_material(material).unwrap();
+
+    let plain_links = RSGComponentBuilder::new(&mut components).shared_material(shared_key).links();
+    let plain_key = scene.append(root_key, RSGNode::with_component_links(plain_links));
+    let override_links = RSGComponentBuilder::new(&mut components)
+        .shared_material(shared_key)
+        .material_override("color", color(0.9))
+        .links();
+    let override_key = scene.append(root_key, RSGNode::with_component_links(override_links));
+    let other_key = scene.append(root_key, RSGNode::new());
+    let plain_material_key = plain_links.material_key.unwrap();
+    let override_material_key = override_links.material_key.unwrap();
+
+    // no copies are made, and the defaults were filled in once for the shared material
+    assert!(components.material_data.is_empty());
+    assert!(components.material(plain_material_key) == components.material(override_material_key));
+    assert!(components.material_property_value(plain_material_key, "color") == Some(&color(0.1)));
+    assert!(components.material_property_value(override_material_key, "color") == Some(&color(0.9)));
+    assert!(components.material_property_value(override_material_key, "roughness")
+        == Some(&RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(0.5))));
+    assert!(components.set_material_override(override_material_key, "color",
+        RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(0.0))).is_err());
+
+    let render_list: RSGRenderList = vec![(plain_key, 0.0), (override_key, 1.0)];
+    let mut table = RSGDrawValueTable::default();
+    resolve_render_list_values(&components, &scene, &render_list, None, &Default::default(), &mut table);
+    assert!(table.draw_values(0)[0] == RSGMaterialCustomValue::Vec4(glm::vec4(0.1, 0.0, 0.0, 1.0)));
+    assert!(table.draw_values(1)[0] == RSGMaterialCustomValue::Vec4(glm::vec4(0.9, 0.0, 0.0, 1.0)));
+
+    // editing the shared material marks all its users dirty, their nodes are known after an update
+    assert!(components.shared_material_users(&scene, shared_key).is_empty());
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    let mut users = components.shared_material_users(&scene, shared_key);
+    users.sort();
+    let mut expected_users = vec![plain_key, override_key];
+    expected_users.sort();
+    assert!(users == expected_users);
+    scene.set_observer(RSGSceneObserver::new());
+    components.edit_shared_material(&mut scene, shared_key, |m| {
+        m.property_values.insert("roughness".to_owned(), RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(1.0)));
+    }).unwrap();
+    let observer = scene.take_observer().unwrap();
+    assert!(observer.dirty_material_value_nodes.len() == 2);
+    assert!(!observer.dirty_material_value_nodes.contains(&other_key));
+    assert!(observer.dirty_material_nodes.is_empty());
+
+    scene.set_observer(RSGSceneObserver::new());
+    components.edit_shared_material(&mut scene, shared_key, |m| m.graphics_state.blend.blend_enable = true).unwrap();
+    let observer = scene.take_observer().unwrap();
+    assert!(observer.dirty_material_value_nodes.len() == 2);
+    assert!(observer.dirty_material_nodes.len() == 2);
+    assert!(!components.is_opaque(&plain_links));
+
+    // an invalid edit is discarded
+    scene.set_observer(RSGSceneObserver::new());
+    assert!(components.edit_shared_material(&mut scene, shared_key, |m| {
+        m.property_values.insert("metalness".to_owned(), RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(1.0)));
+    }).is_err());
+    assert!(!scene.take_observer().unwrap().changed);
+    assert!(!components.material(plain_material_key).unwrap().property_values.contains_key("metalness"));
+
+    // writing to the material of a node gives it its own copy, with its overrides
+    components.material_mut(override_material_key).graphics_state.blend.blend_enable = false;
+    assert!(components.materials[override_material_key].shared_material.is_none());
+    assert!(components.material_data.len() == 1);
+    assert!(components.material(override_material_key).unwrap().property_values["color"] == color(0.9));
+    assert!(components.is_opaque(&override_links));
+    assert!(!components.is_opaque(&plain_links));
+    assert!(components.shared_material_users(&scene, shared_key) == vec![plain_key]);
+    assert!(!components.clear_material_override(plain_material_key, "color"));
+
+    // users are found again after being added, and not after being removed
+    let second_links = RSGComponentBuilder::new(&mut components)
+        .shared_material(shared_key)
+        .material_override("color", color(0.5))
+        .links();
+    assert!(components.shared_material_users(&scene, shared_key) == vec![plain_key]);
+    let second_key = scene.append(root_key, RSGNode::with_component_links(second_links));
+    assert!(components.shared_material_users(&scene, shared_key) == vec![plain_key]);
+    update_inherited_properties(&mut components, &scene, &[second_key], &[], &pool);
+    let mut expected_users = vec![plain_key, second_key];
+    expected_users.sort();
+    assert!(components.shared_material_users(&scene, shared_key) == expected_users);
+    // a user not attached to a node by the next update is searched for then, not on every update
+    let unattached_links = RSGComponentBuilder::new(&mut components).shared_material(shared_key).links();
+    assert!(components.shared_material_users_added);
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    assert!(!components.shared_material_users_added);
+    assert!(components.shared_material_users(&scene, shared_key) == expected_users);
+    components.remove(unattached_links);
+
+    // a new shader set has to fit the overrides of the users too
+    let narrow_id = components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "narrow".to_owned(),
+        properties: vec![RSGMaterialProperty::Float("roughness".to_owned(), 0.5)],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let narrow = |m: &mut RSGMaterial| {
+        m.shader_set_id = narrow_id;
+        m.property_values.remove("color");
+    };
+    let e = components.edit_shared_material(&mut scene, shared_key, narrow).err().unwrap();
+    assert!(e == RSGMaterialError::UnknownProperty { shader_set_id: narrow_id, name: "color".to_owned() });
+    assert!(components.material(plain_material_key).unwrap().shader_set_id == shader_set_id);
+
+    let links = scene.remove(second_key);
+    components.remove(links);
+    assert!(components.shared_material_users(&scene, shared_key) == vec![plain_key]);
+    components.edit_shared_material(&mut scene, shared_key, narrow).unwrap();
+    assert!(components.material(plain_material_key).unwrap().shader_set_id == narrow_id);
+
+    // removing the shared material leaves its users without one
+    assert!(components.remove_shared_material(shared_key).is_some());
+    assert!(components.material(plain_material_key).is_none());
+    assert!(components.set_material_override(plain_material_key, "roughness",
+        RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(0.0))) == Err(RSGMaterialError::NoMaterial));
+    assert!(components.shared_material_users(&scene, shared_key).is_empty());
+    let e = components.edit_shared_material(&mut scene, shared_key, |_| {}).err().unwrap();
+    assert!(e == RSGMaterialError::NoSharedMaterial);
+    assert!(e.to_string() == "there is no such shared material");
+
+    pool.shutdown();
+}
+
+#[test]
+fn state_sorted_render_lists() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+    let layer_key = scene.append(root_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).layer().links()));
+
+    let shader_set = |components: &mut RSGComponentContainer, fragment_shader: &str| components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: fragment_shader.to_owned(),
+        properties: vec![],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let a = shader_set(&mut components, "a");
+    let b = shader_set(&mut components, "b");
+    let mut node = |components: &mut RSGComponentContainer, shader_set_id, blend_enable, z| {
+        let mut material = RSGMaterial { shader_set_id, property_values: Default::default(), graphics_state: Default::default() };
+        material.graphics_state.blend.blend_enable = blend_enable;
+        let links = RSGComponentBuilder::new(components)
+            .transform(glm::translation(&glm::vec3(0.0, 0.0, z)))
+            .mesh(mesh_with_bounds(glm::vec3(-0.1, -0.1, -0.1), glm::vec3(0.1, 0.1, 0.1)))
+            .material(material)
+            .links();
+        scene.append(layer_key, RSGNode::with_component_links(links))
+    };
+    let opaque_keys = [
+        node(&mut components, a, false, -2.0),
+        node(&mut components, b, false, -3.0),
+        node(&mut components, a, false, -4.0),
+        node(&mut components, b, false, -5.0),
+        node(&mut components, a, false, -6.0)
+    ];
+    let alpha_keys = [
+        node(&mut components, a, true, -7.0),
+        node(&mut components, b, true, -8.0),
+        node(&mut components, a, true, -9.0)
+    ];
+
+    update_inherited_properties(&mut components, &scene, &[root_key], &[root_key], &pool);
+    let camera_properties = RSGCameraWorldTransformDerivedProperties::new(&glm::one());
+    let mut opaque_list = RSGRenderList::new();
+    let mut alpha_list = RSGRenderList::new();
+    build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), None, &mut opaque_list, &mut alpha_list);
+
+    let keys = |sorted: &RSGSortedRenderList| sorted.entries.iter().map(|e| e.node_key).collect::<Vec<_>>();
+    let batch_sizes = |sorted: &RSGSortedRenderList| sorted.batches.iter().map(|b| b.count).collect::<Vec<_>>();
+
+    // state mode: opaque grouped by shader set, then front to back; alpha still back to front
+    let mut sorted = RSGSortedRenderList::default();
+    sorted.add_render_list(&components, &scene, &alpha_list, 0, RSGRenderPass::Alpha, RSGRenderListSortMode::State);
+    sorted.add_render_list(&components, &scene, &opaque_list, 0, RSGRenderPass::Opaque, RSGRenderListSortMode::State);
+    sorted.sort_and_batch();
+    assert!(keys(&sorted) == vec![
+        opaque_keys[0], opaque_keys[2], opaque_keys[4], opaque_keys[1], opaque_keys[3],
+        alpha_keys[2], alpha_keys[1], alpha_keys[0]
+    ]);
+    assert!(batch_sizes(&sorted) == vec![3, 2, 1, 1, 1]);
+    let batch = sorted.batch_entries(0);
+    assert!(batch.iter().all(|e| e.draw_state.shader_set_id == Some(a) && e.pass == RSGRenderPass::Opaque));
+    assert!(batch[0].draw_state.graphics_state.unwrap().depth_write);
+    assert!(!sorted.batch_entries(2)[0].draw_state.graphics_state.unwrap().depth_write);
+
+    // depth mode keeps the order of the lists
+    sorted.clear();
+    sorted.add_render_list(&components, &scene, &opaque_list, 0, RSGRenderPass::Opaque, RSGRenderListSortMode::Depth);
+    sorted.add_render_list(&components, &scene, &alpha_list, 0, RSGRenderPass::Alpha, RSGRenderListSortMode::Depth);
+    sorted.sort_and_batch();
+    assert!(keys(&sorted)[..5] == opaque_keys[..]);
+    assert!(batch_sizes(&sorted) == vec![1; 8]);
+
+    // layers come before passes, and entries never batch across layers
+    sorted.clear();
+    sorted.add_render_list(&components, &scene, &opaque_list, 1, RSGRenderPass::Opaque, RSGRenderListSortMode::State);
+    sorted.add_render_list(&components, &scene, &alpha_list[..1].to_vec(), 0, RSGRenderPass::Alpha, RSGRenderListSortMode::State);
+    sorted.add_render_list(&components, &scene, &opaque_list[..1].to_vec(), 0, RSGRenderPass::Opaque, RSGRenderListSortMode::State);
+    sorted.sort_and_batch();
+    assert!(keys(&sorted)[..3] == [opaque_keys[0], alpha_keys[2], opaque_keys[0]]);
+    assert!(batch_sizes(&sorted) == vec![1, 1, 3, 2]);
+
+    assert!(RSGSortKey::state_first(0, RSGRenderPass::Opaque, 1, 2, 3, 1.0) < RSGSortKey::depth_first(0, RSGRenderPass::Alpha, 0.0, 0));
+    assert!(RSGSortKey::depth_first(0, RSGRenderPass::Alpha, 0.25, 7) < RSGSortKey::depth_first(0, RSGRenderPass::Alpha, 0.5, 0));
+    assert!(RSGSortKey::state_first(0, RSGRenderPass::Opaque, 1, 2, 3, 1.0) < RSGSortKey::state_first(0, RSGRenderPass::Opaque, 2, 0, 0, 0.0));
+    // every field at its largest value stays within its bits
+    let max = |bits: u32| (1u64 << bits) - 1;
+    let top_layer = max(RSGSortKey::LAYER_BITS) as u32;
+    let largest = RSGSortKey::state_first(top_layer - 1, RSGRenderPass::Alpha,
+        max(RSGSortKey::SHADER_SET_BITS), max(RSGSortKey::STATE_BITS), max(RSGSortKey::MESH_BITS), 1.0);
+    assert!(largest < RSGSortKey::state_first(top_layer, RSGRenderPass::Opaque, 0, 0, 0, 0.0));
+    assert!(RSGSortKey::depth_first(top_layer - 1, RSGRenderPass::Alpha, 1.0, max(RSGSortKey::ORDER_BITS)) < RSGSortKey::depth_first(top_layer, RSGRenderPass::Opaque, 0.0, 0));
+    // larger indices share the last value of their field instead of spilling into the next
+    assert!(RSGSortKey::state_first(0, RSGRenderPass::Opaque, 0, 0, max(RSGSortKey::MESH_BITS) + 1, 0.0)
+        == RSGSortKey::state_first(0, RSGRenderPass::Opaque, 0, 0, max(RSGSortKey::MESH_BITS), 0.0));
+    assert!(RSGSortKey::depth_first(0, RSGRenderPass::Opaque, 0.0, u64::MAX) < RSGSortKey::depth_first(0, RSGRenderPass::Opaque, 1.0, 0));
+    assert!(RSGSortKey::state_first(top_layer + 1, RSGRenderPass::Opaque, 0, 0, 0, 0.0) == RSGSortKey::state_first(top_layer, RSGRenderPass::Opaque, 0, 0, 0, 0.0));
+    assert!(RSGSortKey::depth_first(u32::MAX, RSGRenderPass::Alpha, 0.0, 0) == RSGSortKey::depth_first(top_layer, RSGRenderPass::Alpha, 0.0, 0));
+
+    // alpha entries whose depths fall into the same step keep their order in the list,
+    // whatever their state: here the middle two, with different shader sets
+    let alpha_close_list = vec![(alpha_keys[0], 1000000.0), (alpha_keys[1], 2.0), (alpha_keys[2], 1.0), (alpha_keys[0], 0.0)];
+    sorted.clear();
+    sorted.add_render_list(&components, &scene, &alpha_close_list, 0, RSGRenderPass::Alpha, RSGRenderListSortMode::State);
+    assert!(sorted.entries[1].sort_key.0 >> RSGSortKey::ORDER_BITS == sorted.entries[2].sort_key.0 >> RSGSortKey::ORDER_BITS);
+    sorted.sort_and_batch();
+    assert!(keys(&sorted) == alpha_close_list.iter().map(|e| e.0).collect::<Vec<_>>());
+
+    // depth, not the position in the list: equally far entries get the same depth
+    let far_list = vec![(opaque_keys[0], 1.0), (opaque_keys[2], 3.0), (opaque_keys[4], 3.0), (opaque_keys[1], 5.0)];
+    sorted.clear();
+    sorted.add_render_list(&components, &scene, &far_list, 0, RSGRenderPass::Opaque, RSGRenderListSortMode::Depth);
+    let depth_bits = |e: &RSGSortedRenderEntry| e.sort_key.0 >> RSGSortKey::ORDER_BITS & max(RSGSortKey::DEPTH_BITS);
+    let depths = sorted.entries.iter().map(depth_bits).collect::<Vec<_>>();
+    assert!(depths == vec![0, max(RSGSortKey::DEPTH_BITS) / 2, max(RSGSortKey::DEPTH_BITS) / 2, max(RSGSortKey::DEPTH_BITS)]);
+    // the alpha list runs back to front, so its depth is inverted: the farthest is 0
+    let alpha_depths = |sorted: &mut RSGSortedRenderList| {
+        sorted.clear();
+        sorted.add_render_list(&components, &scene, &alpha_list, 0, RSGRenderPass::Alpha, RSGRenderListSortMode::State);
+        sorted.entries.iter().map(depth_bits).collect::<Vec<_>>()
+    };
+    assert!(alpha_list[0].1 > alpha_list[2].1);
+    assert!(alpha_depths(&mut sorted) == vec![0, max(RSGSortKey::DEPTH_BITS) / 2, max(RSGSortKey::DEPTH_BITS)]);
+
+    // shader sets are numbered in the order they are first seen, whatever their ids
+    sorted.clear();
+    sorted.add_render_list(&components, &scene, &opaque_list, 0, RSGRenderPass::Opaque, RSGRenderListSortMode::State);
+    assert!(sorted.entries.iter().all(|e| e.sort_key.0 >> (RSGSortKey::STATE_BITS + RSGSortKey::MESH_BITS + RSGSortKey::DEPTH_BITS) & max(RSGSortKey::SHADER_SET_BITS) < 2));
+
+    pool.shutdown();
+}
+
+#[test]
+fn instanced_draws() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    let shader_set_id = components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "".to_owned(),
+        properties: vec![
+            RSGMaterialProperty::Vec4("color".to_owned(), glm::vec4(1.0, 1.0, 1.0, 1.0)),
+            RSGMaterialProperty::Int("index".to_owned(), 0),
+            RSGMaterialProperty::Float("roughness".to_owned(), 0.5),
+            RSGMaterialProperty::Mat4("model".to_owned(), glm::one())
+        ],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let mesh = |buffer_id| RSGMesh {
+        vertex_views: smallvec::smallvec![RSGMeshBufferView { buffer_id, offset: 0, size: 36, stride: 12 }],
+        submeshes: smallvec::smallvec![RSGSubMesh {
+            topology: RSGMeshTopology::Triangles,
+            vertex_count: 3,
+            inputs: smallvec::smallvec![RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 0, 0)],
+            index_count: None,
+            index_view: None
+        }],
+        bounds_3d: None,
+        bounding_sphere: None
+    };
+    let material = |color: f32, index: i32, roughness: f32| {
+        let mut m = RSGMaterial { shader_set_id, property_values: Default::default(), graphics_state: Default::default() };
+        m.property_values.insert("color".to_owned(), RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Vec4(glm::vec4(color, 0.0, 0.0, 1.0))));
+        m.property_values.insert("index".to_owned(), RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Int(index)));
+        m.property_values.insert("roughness".to_owned(), RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(roughness)));
+        m
+    };
+    let shared_key = components.add_shared_material(material(0.0, 0, 0.5)).unwrap();
+    let mut add = |components: &mut RSGComponentContainer, x: f32, mesh: RSGMesh, material: Option<RSGMaterial>| {
+        let mut builder = RSGComponentBuilder::new(components);
+        builder.transform(glm::translation(&glm::vec3(x, 0.0, 0.0))).mesh(mesh);
+        match material {
+            Some(m) => builder.material(m),
+            None => builder.shared_material(shared_key).material_override("color",
+                RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Vec4(glm::vec4(0.5, 0.0, 0.0, 1.0))))
+        };
+        let links = builder.links();
+        scene.append(root_key, RSGNode::with_component_links(links))
+    };
+    let a1 = add(&mut components, 1.0, mesh(1), Some(material(0.1, 1, 0.5)));
+    let other_roughness = add(&mut components, 2.0, mesh(1), Some(material(0.2, 2, 0.75)));
+    let a2 = add(&mut components, 3.0, mesh(1), None);
+    let b1 = add(&mut components, 4.0, mesh(2), Some(material(0.4, 4, 0.5)));
+    let a3 = add(&mut components, 5.0, mesh(1), Some(material(0.5, 5, 0.5)));
+    let b2 = add(&mut components, 6.0, mesh(2), Some(material(0.6, 6, 0.5)));
+    let other_mesh = add(&mut components, 7.0, mesh(3), Some(material(0.7, 7, 0.5)));
+    let no_material = scene.append(root_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).mesh(mesh(1)).links()));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[root_key], &pool);
+
+    let opaque_list: RSGRenderList = [a1, other_roughness, a2, b1, no_material, a3, b2, other_mesh].iter()
+        .enumerate().map(|(i, key)| (*key, i as f32)).collect();
+    let options = RSGInstancingOptions {
+        per_instance_properties: vec!["color".to_owned(), "index".to_owned(), "not_declared".to_owned()],
+        ..Default::default()
+    };
+    let mut instanced = RSGInstancedRenderList::default();
+    build_instanced_draws(&components, &scene, &opaque_list, &options, &mut instanced);
+
+    assert!(instanced.draws.len() == 2);
+    assert!(instanced.draws[0].node_key == a1 && instanced.draw_instances(0) == [a1, a2, a3]);
+    assert!(instanced.draws[1].node_key == b1 && instanced.draw_instances(1) == [b1, b2]);
+    assert!(instanced.remaining == vec![(other_roughness, 1.0), (no_material, 4.0), (other_mesh, 7.0)]);
+
+    // matrix, opacity, color, index
+    assert!(instanced.draws.iter().all(|d| d.data_stride == 16 + 1 + 4 + 1));
+    assert!(instanced.instance_data.len() == 5 * 22);
+    let values = instanced.instance_values(0, 1);
+    assert!(values[..16] == *glm::translation(&glm::vec3(3.0, 0.0, 0.0)).as_slice());
+    assert!(values[16] == 1.0);
+    assert!(values[17..21] == [0.5, 0.0, 0.0, 1.0]);
+    assert!(values[21].to_bits() == 0);
+    let values = instanced.instance_values(1, 1);
+    assert!(values[12] == 6.0);
+    assert!(values[17] == 0.6 && values[21].to_bits() == 6);
+
+    // with everything per instance the differing roughness does not matter
+    let options = RSGInstancingOptions {
+        per_instance_properties: vec!["color".to_owned(), "index".to_owned(), "roughness".to_owned()],
+        min_instance_count: 3
+    };
+    build_instanced_draws(&components, &scene, &opaque_list, &options, &mut instanced);
+    assert!(instanced.draws.len() == 1);
+    assert!(instanced.draw_instances(0) == [a1, other_roughness, a2, a3]);
+    assert!(instanced.instance_values(0, 1)[22] == 0.75);
+    assert!(instanced.remaining.iter().map(|e| e.0).collect::<Vec<_>>() == vec![b1, no_material, b2, other_mesh]);
+
+    // instances without a transform of their own get the world transform they inherit
+    let group_key = scene.append(root_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).transform(glm::translation(&glm::vec3(10.0, 0.0, 0.0))).links()));
+    let children: Vec<_> = (0..2).map(|_| scene.append(group_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).mesh(mesh(4)).material(material(0.8, 8, 0.5)).links()))).collect();
+    update_inherited_properties(&mut components, &scene, &[group_key], &[group_key], &pool);
+    let opaque_list: RSGRenderList = children.iter().map(|key| (*key, 0.0)).collect();
+    build_instanced_draws(&components, &scene, &opaque_list, &Default::default(), &mut instanced);
+    assert!(instanced.draws.len() == 1 && instanced.draw_instances(0) == children.as_slice());
+    assert!(instanced.instance_values(0, 1)[..16] == *glm::translation(&glm::vec3(10.0, 0.0, 0.0)).as_slice());
+
+    // a per-instance property with a builtin value has nothing to go in the instance data,
+    // such entries are not instanced
+    let mut builtin_model = material(0.9, 9, 0.5);
+    builtin_model.property_values.insert("model".to_owned(), RSGMaterialPropertyValue::Builtin(RSGMaterialBuiltinValue::ModelMatrix));
+    let mut add_with_material = |m: &RSGMaterial| scene.append(root_key, RSGNode::with_component_links(
+        RSGComponentBuilder::new(&mut components).mesh(mesh(5)).material(m.clone()).links()));
+    let builtins: Vec<_> = (0..2).map(|_| add_with_material(&builtin_model)).collect();
+    let customs: Vec<_> = (0..2).map(|_| add_with_material(&material(0.9, 9, 0.5))).collect();
+    update_inherited_properties(&mut components, &scene, &[root_key], &[root_key], &pool);
+    let opaque_list: RSGRenderList = builtins.iter().chain(customs.iter()).enumerate().map(|(i, key)| (*key, i as f32)).collect();
+    let options = RSGInstancingOptions { per_instance_properties: vec!["model".to_owned()], ..Default::default() };
+    build_instanced_draws(&components, &scene, &opaque_list, &options, &mut instanced);
+    assert!(instanced.draws.len() == 1 && instanced.draw_instances(0) == customs.as_slice());
+    assert!(instanced.instance_values(0, 1)[17..33] == *glm::one::<glm::Mat4>().as_slice());
+    assert!(instanced.remaining == vec![(builtins[0], 0.0), (builtins[1], 1.0)]);
+
+    pool.shutdown();
+}
+
+fn hash_of<T: std::hash::Hash>(value: &T) -> u64 {
+    use std::hash::Hasher;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn extended_graphics_state() {
+    let state = RSGMaterialGraphicsState::default();
+    assert!(!state.stencil.stencil_test && state.depth_bias.is_none() && !state.scissor_test);
+    assert!(state.polygon_mode == RSGMaterialPolygonMode::Fill && state.line_width == 1.0);
+
+    // outline: mark the object in the stencil buffer, then draw a wireframe where it is not marked
+    let mut mark = state;
+    mark.stencil = RSGMaterialStencil::with_ops(RSGMaterialCompareOp::Always, RSGMaterialStencilOp::Replace, 1);
+    let mut outline = state;
+    outline.stencil = RSGMaterialStencil::with_ops(RSGMaterialCompareOp::NotEqual, RSGMaterialStencilOp::Keep, 1);
+    outline.stencil.write_mask = 0;
+    outline.polygon_mode = RSGMaterialPolygonMode::Line;
+    outline.line_width = 3.0;
+    assert!(mark.stencil.back.pass_op == RSGMaterialStencilOp::Replace);
+    assert!(mark != outline && hash_of(&mark) != hash_of(&outline));
+
+    // decal
+    let mut decal = state;
+    decal.depth_bias = Some(RSGMaterialDepthBias { constant_factor: -1.0, slope_factor: -1.0, clamp: 0.0 });
+    let mut same_decal = decal;
+    same_decal.depth_bias.as_mut().unwrap().clamp = -0.0;
+    assert!(decal == same_decal && hash_of(&decal) == hash_of(&same_decal));
+    assert!(decal != state);
+    let mut wider = state;
+    wider.line_width = 2.0;
+    assert!(hash_of(&wider) != hash_of(&state));
+
+    // NaNs do not break Eq and Hash, e.g. for the pipeline cache
+    let mut broken = state;
+    broken.line_width = f32::NAN;
+    broken.depth_bias = Some(RSGMaterialDepthBias { constant_factor: f32::NAN, slope_factor: 0.0, clamp: 0.0 });
+    let same_broken = broken;
+    assert!(broken == same_broken && hash_of(&broken) == hash_of(&same_broken));
+    assert!(broken != state);
+
+    // transparency only touches blending and depth writes
+    let mut material = RSGMaterial { shader_set_id: 0, property_values: Default::default(), graphics_state: outline };
+    material.graphics_state.depth_bias = decal.depth_bias;
+    material.graphics_state.scissor_test = true;
+    let effective = material.effective_graphics_state(0.5, RSGAlphaConvention::Premultiplied);
+    assert!(effective.blend.blend_enable && !effective.depth_write);
+    let mut expected = material.graphics_state;
+    expected.blend = effective.blend;
+    expected.depth_write = false;
+    assert!(effective == expected);
+
+    // a stencil-only pass has no color to blend
+    material.graphics_state = mark;
+    material.graphics_state.blend.color_write = RSGMaterialColorMask::empty();
+    assert!(material.effective_graphics_state(0.5, RSGAlphaConvention::Premultiplied) == material.graphics_state);
+}
+
+#[test]
+fn blend_presets() {
+    use RSGMaterialBlendFactor::*;
+    let factors = |b: &RSGMaterialBlend| (b.src_color, b.dst_color, b.src_alpha, b.dst_alpha);
+    let material = |blend| RSGMaterial {
+        shader_set_id: 0,
+        property_values: Default::default(),
+        graphics_state: RSGMaterialGraphicsState { blend, ..Default::default() }
+    };
+    let premultiplied = RSGAlphaConvention::Premultiplied;
+    let straight = RSGAlphaConvention::Straight;
+
+    // opacity-induced blending follows the convention, and keeps the color mask
+    let blend = RSGMaterialBlend { color_write: RSGMaterialColorMask::R | RSGMaterialColorMask::G, ..Default::default() };
+    let m = material(blend);
+    let e = m.effective_graphics_state(0.5, premultiplied);
+    assert!(e.blend.blend_enable && factors(&e.blend) == (One, OneMinusSrcAlpha, One, OneMinusSrcAlpha));
+    assert!(e.blend.color_write == blend.color_write);
+    let e = m.effective_graphics_state(0.5, straight);
+    assert!(factors(&e.blend) == (SrcAlpha, OneMinusSrcAlpha, One, OneMinusSrcAlpha));
+    assert!(m.effective_graphics_state(1.0, straight) == m.graphics_state);
+
+    // an enabled preset
+    let m = material(RSGMaterialBlend::with_preset(RSGMaterialBlendPreset::Additive));
+    assert!(factors(&m.effective_graphics_state(1.0, premultiplied).blend) == (One, One, One, OneMinusSrcAlpha));
+    assert!(factors(&m.effective_graphics_state(1.0, straight).blend) == (SrcAlpha, One, One, OneMinusSrcAlpha));
+    let m = material(RSGMaterialBlend::with_preset(RSGMaterialBlendPreset::Multiply));
+    assert!(factors(&m.effective_graphics_state(1.0, premultiplied).blend).0 == DstColor);
+    let m = material(RSGMaterialBlend::with_preset(RSGMaterialBlendPreset::Screen));
+    assert!(factors(&m.effective_graphics_state(1.0, straight).blend).1 == OneMinusSrcColor);
+    // explicit alpha presets do not depend on the convention
+    let m = material(RSGMaterialBlend::with_preset(RSGMaterialBlendPreset::StraightAlpha));
+    assert!(factors(&m.effective_graphics_state(1.0, premultiplied).blend).0 == SrcAlpha);
+
+    // a preset that is only used once the opacity requires blending
+    let mut blend = RSGMaterialBlend::with_preset(RSGMaterialBlendPreset::Additive);
+    blend.blend_enable = false;
+    let m = material(blend);
+    let e = m.effective_graphics_state(1.0, premultiplied);
+    assert!(!e.blend.blend_enable && e.depth_write);
+    let e = m.effective_graphics_state(0.5, premultiplied);
+    assert!(e.blend.blend_enable && !e.depth_write && factors(&e.blend) == (One, One, One, OneMinusSrcAlpha));
+
+    // the convention is scene-wide, kept in the container
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+    let shader_set_id = components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "".to_owned(),
+        properties: vec![],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let links = RSGComponentBuilder::new(&mut components).opacity(0.25)
+        .material(RSGMaterial { shader_set_id, ..material(Default::default()) }).links();
+    scene.append(root_key, RSGNode::with_component_links(links));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[root_key], &pool);
+    assert!(components.alpha_convention == RSGAlphaConvention::Premultiplied);
+    assert!(components.effective_graphics_state(&links).unwrap().blend.src_color == One);
+    components.alpha_convention = RSGAlphaConvention::Straight;
+    assert!(components.effective_graphics_state(&links).unwrap().blend.src_color == SrcAlpha);
+    assert!(!components.is_opaque(&links));
+    pool.shutdown();
+}
+
+#[test]
+fn texture_properties() {
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+
+    let opaque_id = components.textures.add(RSGTexture::new_2d(256, 256, false, "wall.png"));
+    let alpha_id = components.textures.add(RSGTexture::new_2d(64, 64, true, "leaves.png"));
+    let sky_id = components.textures.add(RSGTexture {
+        texture_type: RSGTextureType::Cube,
+        layers: 6,
+        ..RSGTexture::new_2d(512, 512, false, "sky.ktx")
+    });
+    assert!(components.textures.len() == 3);
+    assert!(components.textures.get(alpha_id).unwrap().has_alpha);
+
+    let nearest = RSGSampler {
+        min_filter: RSGSamplerFilter::Nearest,
+        mag_filter: RSGSamplerFilter::Nearest,
+        mipmap_mode: RSGSamplerMipmapMode::Nearest,
+        wrap_u: RSGSamplerWrap::ClampToEdge,
+        wrap_v: RSGSamplerWrap::ClampToEdge,
+        wrap_w: RSGSamplerWrap::ClampToEdge
+    };
+    let shader_set_id = components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "".to_owned(),
+        properties: vec![
+            RSGMaterialProperty::Texture("base_color".to_owned(), RSGTextureType::Texture2D, Some(opaque_id)),
+            RSGMaterialProperty::Sampler("base_color_sampler".to_owned(), nearest),
+            RSGMaterialProperty::Texture("environment".to_owned(), RSGTextureType::Cube, None)
+        ],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let material = |values: Vec<(&str, RSGMaterialCustomValue)>| RSGMaterial {
+        shader_set_id,
+        property_values: values.into_iter().map(|(n, v)| (n.to_owned(), RSGMaterialPropertyValue::Custom(v))).collect(),
+        graphics_state: Default::default()
+    };
+
+    // the texture type is part of the property type
+    let e = RSGComponentBuilder::new(&mut components)
+        .try_material(material(vec![("environment", RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(opaque_id)))]))
+        .err().unwrap();
+    assert!(e.to_string() == format!("property 'environment' of shader set {} is a textureCube, but the material provides a texture2D", shader_set_id));
+
+    let default_links = RSGComponentBuilder::new(&mut components).material(material(vec![
+        ("environment", RSGMaterialCustomValue::Texture(RSGTextureType::Cube, Some(sky_id)))
+    ])).links();
+    let alpha_links = RSGComponentBuilder::new(&mut components).material(material(vec![
+        ("base_color", RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(alpha_id)))
+    ])).links();
+    let default_key = scene.append(root_key, RSGNode::with_component_links(default_links));
+    let alpha_key = scene.append(root_key, RSGNode::with_component_links(alpha_links));
+
+    // has-alpha only matters when asked for
+    assert!(components.is_opaque(&default_links) && components.is_opaque(&alpha_links));
+    components.texture_alpha_affects_opacity = true;
+    assert!(components.is_opaque(&default_links) && !components.is_opaque(&alpha_links));
+    components.textures.update(alpha_id, RSGTexture::new_2d(64, 64, false, "leaves.jpg"));
+    assert!(components.is_opaque(&alpha_links));
+
+    let render_list: RSGRenderList = vec![(default_key, 0.0), (alpha_key, 1.0)];
+    let mut table = RSGDrawValueTable::default();
+    resolve_render_list_values(&components, &scene, &render_list, None, &Default::default(), &mut table);
+    assert!(table.draw_values(0) == [
+        RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(opaque_id)),
+        RSGMaterialCustomValue::Sampler(nearest),
+        RSGMaterialCustomValue::Texture(RSGTextureType::Cube, Some(sky_id))
+    ]);
+    assert!(table.draw_values(1)[0] == RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(alpha_id)));
+    assert!(table.draw_values(1)[2] == RSGMaterialCustomValue::Texture(RSGTextureType::Cube, None));
+    assert!(components.textures.remove(sky_id).is_some() && components.textures.get(sky_id).is_none());
+}
+
+fn u16_index_buffer(indices: &[u16]) -> RSGMeshBuffer {
+    let mut bytes: Vec<u8> = indices.iter().flat_map(|i| i.to_ne_bytes()).collect();
+    bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+    RSGMeshBuffer {
+        data: bytes.chunks(4).map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]])).collect(),
+        source: Default::default()
+    }
+}
+
+#[test]
+fn mesh_validation() {
+    let mut buffers = RSGMeshBufferTable::new();
+    // a quad: positions and texture coordinates interleaved
+    buffers.insert(1, RSGMeshBuffer {
+        data: vec![
+            0.0, 0.0, 0.0, 0.0, 0.0,
+            1.0, 0.0, 0.0, 1.0, 0.0,
+            1.0, 1.0, 0.0, 1.0, 1.0,
+            0.0, 1.0, 0.0, 0.0, 1.0
+        ],
+        source: Default::default()
+    });
+    buffers.insert(2, u16_index_buffer(&[0, 1, 2, 0, 2, 3]));
+    assert!(buffers[&2].byte_size() == 12);
+    assert!(buffers[&2].read_u16(10) == Some(3) && buffers[&2].read_u16(12).is_none());
+    assert!(buffers[&1].read_f32(20) == Some(1.0));
+
+    let quad = RSGMesh {
+        vertex_views: smallvec![RSGMeshBufferView { buffer_id: 1, offset: 0, size: 80, stride: 20 }],
+        submeshes: smallvec![RSGSubMesh {
+            topology: RSGMeshTopology::Triangles,
+            vertex_count: 4,
+            inputs: smallvec![
+                RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 0, 0),
+                RSGMeshVertexInput::TexCoord(0, RSGMeshVertexInputType::Vec2, 0, 12)
+            ],
+            index_count: Some(6),
+            index_view: Some(RSGMeshIndexBufferView::U16(RSGMeshBufferView { buffer_id: 2, offset: 0, size: 12, stride: 2 }))
+        }],
+        bounds_3d: None,
+        bounding_sphere: None
+    };
+    assert!(quad.validate(&buffers).is_ok());
+
+    let mut mesh = quad.clone();
+    mesh.vertex_views[0].size = 84;
+    mesh.submeshes[0].inputs.push(RSGMeshVertexInput::Normal(RSGMeshVertexInputType::Vec3, 0, 12));
+    mesh.submeshes[0].inputs.push(RSGMeshVertexInput::Color(0, RSGMeshVertexInputType::Vec4, 1, 0));
+    mesh.submeshes[0].index_count = Some(7);
+    let errors = mesh.validate(&buffers).err().unwrap();
+    assert!(errors == vec![
+        RSGMeshError::VertexViewOutOfRange { view_index: 0, end: 84, buffer_size: 80 },
+        RSGMeshError::InputOutsideStride { submesh_index: 0, semantic: RSGMeshVertexSemantic::Normal, end: 24, stride: 20 },
+        RSGMeshError::InvalidViewIndex { submesh_index: 0, semantic: RSGMeshVertexSemantic::Color(0), view_index: 1 },
+        RSGMeshError::IndexCountOutOfRange { submesh_index: 0, index_count: 7, available: 6 }
+    ]);
+    assert!(errors[0].to_string() == "vertex view 0 ends at byte 84, but its buffer has 80 bytes");
+
+    let mut mesh = quad.clone();
+    mesh.submeshes[0].vertex_count = 5;
+    let errors = mesh.validate(&buffers).err().unwrap();
+    assert!(errors[0] == RSGMeshError::VertexCountOutOfRange {
+        submesh_index: 0,
+        semantic: RSGMeshVertexSemantic::Position,
+        vertex_count: 5,
+        available: 4
+    });
+    mesh.submeshes[0].vertex_count = 3;
+    let errors = mesh.validate(&buffers).err().unwrap();
+    assert!(errors == vec![RSGMeshError::IndexOutOfRange { submesh_index: 0, position: 5, index: 3, vertex_count: 3 }]);
+    assert!(errors[0].to_string() == "index 5 of submesh 0 is 3, but the submesh has 3 vertices");
+
+    let mut mesh = quad.clone();
+    mesh.vertex_views[0].buffer_id = 7;
+    mesh.vertex_views.push(RSGMeshBufferView { buffer_id: 7, offset: 2, size: 0, stride: 4 });
+    mesh.submeshes[0].index_view = Some(RSGMeshIndexBufferView::U32(RSGMeshBufferView { buffer_id: 2, offset: 2, size: 12, stride: 4 }));
+    let errors = mesh.validate(&buffers).err().unwrap();
+    assert!(errors == vec![
+        RSGMeshError::MissingBuffer { buffer_id: 7 },
+        RSGMeshError::MisalignedVertexView { view_index: 1 },
+        RSGMeshError::MisalignedIndexView { submesh_index: 0 },
+        RSGMeshError::IndexCountOutOfRange { submesh_index: 0, index_count: 6, available: 3 },
+        RSGMeshError::IndexViewOutOfRange { submesh_index: 0, end: 14, buffer_size: 12 }
+    ]);
+    mesh.submeshes[0].index_view = None;
+    assert!(mesh.validate(&buffers).err().unwrap().contains(&RSGMeshError::InconsistentIndices { submesh_index: 0 }));
+
+    // ends past usize::MAX are out of range instead of overflowing
+    let mut mesh = quad.clone();
+    mesh.vertex_views[0].offset = usize::MAX - 3;
+    mesh.submeshes[0].inputs[1] = RSGMeshVertexInput::TexCoord(0, RSGMeshVertexInputType::Vec2, 0, usize::MAX - 4);
+    mesh.submeshes[0].index_view = Some(RSGMeshIndexBufferView::U16(RSGMeshBufferView { buffer_id: 2, offset: usize::MAX - 1, size: 12, stride: 2 }));
+    let errors = mesh.validate(&buffers).err().unwrap();
+    assert!(errors == vec![
+        RSGMeshError::VertexViewOutOfRange { view_index: 0, end: usize::MAX, buffer_size: 80 },
+        RSGMeshError::InputOutsideStride { submesh_index: 0, semantic: RSGMeshVertexSemantic::TexCoord(0), end: usize::MAX, stride: 20 },
+        RSGMeshError::IndexViewOutOfRange { submesh_index: 0, end: usize::MAX, buffer_size: 12 }
+    ]);
+    assert!(mesh.compute_bounds(&buffers).is_none());
+}
+
+#[test]
+fn mesh_bounds() {
+    let pool = scoped_pool::Pool::new(1);
+    let mut buffers = RSGMeshBufferTable::new();
+    // the last vertex is not referenced by the indices
+    buffers.insert(1, RSGMeshBuffer {
+        data: vec![
+            -1.0, 0.0, 2.0,
+            3.0, 0.0, 2.0,
+            3.0, 4.0, 2.0,
+            100.0, 100.0, 100.0
+        ],
+        source: Default::default()
+    });
+    buffers.insert(2, u16_index_buffer(&[0, 1, 2]));
+    let mesh = RSGMesh {
+        vertex_views: smallvec![RSGMeshBufferView { buffer_id: 1, offset: 0, size: 48, stride: 12 }],
+        submeshes: smallvec![RSGSubMesh {
+            topology: RSGMeshTopology::Triangles,
+            vertex_count: 4,
+            inputs: smallvec![RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 0, 0)],
+            index_count: Some(3),
+            index_view: Some(RSGMeshIndexBufferView::U16(RSGMeshBufferView { buffer_id: 2, offset: 0, size: 6, stride: 2 }))
+        }],
+        bounds_3d: None,
+        bounding_sphere: None
+    };
+    let (aabb, sphere) = mesh.compute_bounds(&buffers).unwrap();
+    assert!(aabb == RSGAabb { minimum: glm::vec3(-1.0, 0.0, 2.0), maximum: glm::vec3(3.0, 4.0, 2.0) });
+    assert!(sphere.center == glm::vec3(1.0, 2.0, 2.0));
+    assert!((sphere.radius - 8.0f32.sqrt()).abs() < 1e-6);
+
+    // without indices every vertex counts
+    let mut unindexed = mesh.clone();
+    unindexed.submeshes[0].index_count = None;
+    unindexed.submeshes[0].index_view = None;
+    assert!(unindexed.compute_bounds(&buffers).unwrap().0.maximum == glm::vec3(100.0, 100.0, 100.0));
+
+    // 2D positions lie at z = 0, and a second submesh adds to the bounds
+    let mut flat = unindexed.clone();
+    flat.vertex_views[0].stride = 8;
+    flat.vertex_views[0].size = 16;
+    flat.submeshes[0].vertex_count = 2;
+    flat.submeshes[0].inputs[0] = RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec2, 0, 0);
+    flat.submeshes.push(mesh.submeshes[0].clone());
+    flat.vertex_views.push(mesh.vertex_views[0]);
+    flat.submeshes[1].inputs[0] = RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 1, 0);
+    assert!(flat.compute_bounds(&buffers).unwrap().0 == RSGAabb { minimum: glm::vec3(-1.0, 0.0, 0.0), maximum: glm::vec3(3.0, 4.0, 2.0) });
+
+    // nothing to compute from
+    let mut broken = mesh.clone();
+    broken.submeshes[0].inputs[0] = RSGMeshVertexInput::Position(RSGMeshVertexInputType::Int3, 0, 0);
+    assert!(broken.compute_bounds(&buffers).is_none());
+    assert!(mesh.compute_bounds(&RSGMeshBufferTable::new()).is_none());
+    broken.submeshes[0].inputs[0] = RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 0, 0);
+    broken.vertex_views[0].size = 24;
+    assert!(broken.compute_bounds(&buffers).is_none());
+    // a stride of 0 would repeat the first vertex, validate rejects it as well
+    let mut zero_stride = mesh.clone();
+    zero_stride.vertex_views[0].stride = 0;
+    assert!(zero_stride.compute_bounds(&buffers).is_none());
+    assert!(zero_stride.validate(&buffers).is_err());
+
+    // the builder fills in missing bounds, but keeps given ones
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+    let layer_key = scene.append(root_key, RSGNode::with_component_links(RSGComponentBuilder::new(&mut components).layer().links()));
+    let links = RSGComponentBuilder::new(&mut components).auto_bounds(&buffers).transform(glm::one()).mesh(mesh.clone()).links();
+    assert!(components.mesh_data[links.mesh_key.unwrap()].bounds_3d == Some(aabb));
+    assert!(components.mesh_data[links.mesh_key.unwrap()].bounding_sphere == Some(sphere));
+    let given = mesh_with_bounds(glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 1.0, 1.0));
+    let links = RSGComponentBuilder::new(&mut components).auto_bounds(&buffers).mesh(given.clone()).links();
+    assert!(components.mesh_data[links.mesh_key.unwrap()].bounds_3d == given.bounds_3d);
+
+    // meshes without bounds are sorted by their origin instead of panicking
+    let links = RSGComponentBuilder::new(&mut components)
+        .transform(glm::translation(&glm::vec3(0.0, 0.0, -5.0)))
+        .mesh(mesh.clone())
+        .links();
+    let unbounded_key = scene.append(layer_key, RSGNode::with_component_links(links));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    let camera_properties = RSGCameraWorldTransformDerivedProperties::new(&glm::one());
+    let mut opaque_list = RSGRenderList::new();
+    let mut alpha_list = RSGRenderList::new();
+    build_layer_render_lists(&components, &scene, layer_key, Some(camera_properties), None, &mut opaque_list, &mut alpha_list);
+    assert!(opaque_list == vec![(unbounded_key, 5.0)]);
+
+    // the computed sphere, tighter than the one around the box, is used for culling
+    let links = RSGComponentBuilder::new(&mut components)
+        .auto_bounds(&buffers)
+        .transform(glm::scaling(&glm::vec3(2.0, 2.0, 2.0)))
+        .mesh(mesh.clone())
+        .links();
+    let bounded_key = scene.append(layer_key, RSGNode::with_component_links(links));
+    update_inherited_properties(&mut components, &scene, &[root_key], &[], &pool);
+    update_world_bounds(&mut components, &scene, &[root_key], &[]);
+    let world_sphere = components.node_bounds[bounded_key].world_sphere.unwrap();
+    assert!(world_sphere.center == glm::vec3(2.0, 4.0, 4.0));
+    assert!((world_sphere.radius - 2.0 * 8.0f32.sqrt()).abs() < 1e-5);
+
+    pool.shutdown();
+}
@@ -0,0 +1,86 @@
+use rsg::components::*;
+use rsg::materials::*;
+use rsg::uniforms::*;
+use nalgebra_glm as glm;
+
+#[test]
+fn builtin_shader_sets() {
+    let mut registry = RSGMaterialShaderSetRegistry::new();
+    assert!(registry.len() == RSGBuiltinShaderSet::ALL.len());
+    for builtin in RSGBuiltinShaderSet::ALL.iter() {
+        assert!(RSGBuiltinShaderSet::from_id(builtin.id()) == Some(*builtin));
+        let shader_set = registry.get(builtin.id()).unwrap();
+        assert!(*shader_set == builtin.shader_set());
+        for property in shader_set.properties.iter() {
+            let sources = format!("{}{}", shader_set.vertex_shader, shader_set.fragment_shader);
+            assert!(sources.contains(property.name()), "{:?} does not use {}", builtin, property.name());
+        }
+    }
+    assert!(RSGBuiltinShaderSet::from_id(RSGBuiltinShaderSet::FIRST_ID + RSGBuiltinShaderSet::ALL.len() as u32).is_none());
+    assert!(RSGBuiltinShaderSet::from_id(0).is_none());
+
+    // registering one again gives the builtin id, new ones do not clash with the builtins
+    assert!(registry.register(RSGBuiltinShaderSet::Textured.shader_set()).unwrap() == RSGBuiltinShaderSet::Textured.id());
+    let mut custom = RSGBuiltinShaderSet::FlatColor.shader_set();
+    custom.fragment_shader.push_str("// custom\n");
+    assert!(registry.register(custom).unwrap() == 0);
+
+    // matches the std140 block in the sources
+    let pbr = registry.get(RSGBuiltinShaderSet::PbrMetallicRoughness.id()).unwrap();
+    let layout = RSGUniformLayout::for_shader_set(pbr, RSGUniformLayoutRules::Std140);
+    assert!(layout.members.len() == 10);
+    assert!(layout.member("normal_matrix").unwrap().offset == 192);
+    assert!(layout.member("base_color_factor").unwrap().offset == 240);
+    assert!(layout.member("emissive_factor").unwrap().offset == 256);
+    assert!(layout.member("metallic_factor").unwrap().offset == 268);
+    assert!(layout.member("light_color").unwrap().offset == 304);
+    assert!(layout.size == 320);
+}
+
+#[test]
+fn builtin_materials() {
+    let mut components = RSGComponentContainer::default();
+    let red = glm::vec4(1.0, 0.0, 0.0, 1.0);
+    let materials = vec![
+        (RSGBuiltinShaderSet::FlatColor, RSGMaterial::flat_color(red)),
+        (RSGBuiltinShaderSet::VertexColor, RSGMaterial::vertex_color()),
+        (RSGBuiltinShaderSet::Textured, RSGMaterial::textured(3, red)),
+        (RSGBuiltinShaderSet::Sprite2D, RSGMaterial::sprite_2d(4)),
+        (RSGBuiltinShaderSet::PbrMetallicRoughness, RSGMaterial::pbr_metallic_roughness(red, 0.0, 0.5))
+    ];
+    for (builtin, material) in materials.into_iter() {
+        assert!(material.shader_set_id == builtin.id());
+        // complete already, validation has nothing to fill in
+        let mut validated = material.clone();
+        components.shader_sets.validate(&mut validated).unwrap();
+        assert!(validated == material);
+        assert!(material.property_values.len() == builtin.shader_set().properties.len());
+        let links = RSGComponentBuilder::new(&mut components).material(material).links();
+        assert!(components.material(links.material_key.unwrap()).is_some());
+    }
+
+    let custom = RSGMaterialPropertyValue::Custom;
+    let builtin = RSGMaterialPropertyValue::Builtin;
+    let flat = RSGMaterial::flat_color(red);
+    assert!(flat.property_values["mvp"] == builtin(RSGMaterialBuiltinValue::ModelViewProjectionMatrix));
+    assert!(flat.property_values["color"] == custom(RSGMaterialCustomValue::Vec4(red)));
+    assert!(flat.graphics_state == RSGMaterialGraphicsState::default());
+
+    let textured = RSGMaterial::textured(3, red);
+    assert!(textured.property_values["base_color"] == custom(RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(3))));
+    assert!(textured.property_values["base_color_sampler"] == custom(RSGMaterialCustomValue::Sampler(RSGSampler::default())));
+
+    let sprite = RSGMaterial::sprite_2d(4);
+    assert!(sprite.property_values["uv_rect"] == custom(RSGMaterialCustomValue::Vec4(glm::vec4(0.0, 0.0, 1.0, 1.0))));
+    assert!(sprite.graphics_state.blend.blend_enable);
+    assert!(sprite.graphics_state.blend.preset == Some(RSGMaterialBlendPreset::PremultipliedAlpha));
+    assert!(!sprite.graphics_state.depth_test && !sprite.graphics_state.depth_write);
+    assert!(sprite.graphics_state.cull_mode == RSGMaterialCullMode::None);
+
+    let pbr = RSGMaterial::pbr_metallic_roughness(red, 0.25, 0.5);
+    assert!(pbr.property_values["normal_matrix"] == builtin(RSGMaterialBuiltinValue::NormalMatrix));
+    assert!(pbr.property_values["metallic_factor"] == custom(RSGMaterialCustomValue::Float(0.25)));
+    assert!(pbr.property_values["roughness_factor"] == custom(RSGMaterialCustomValue::Float(0.5)));
+    assert!(pbr.property_values["base_color_texture"] == custom(RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, None)));
+    assert!(pbr.property_values["emissive_factor"] == custom(RSGMaterialCustomValue::Vec3(glm::zero())));
+}
@@ -0,0 +1,198 @@
+use rsg::scene::*;
+use rsg::components::*;
+use rsg::materials::*;
+use rsg::pipeline::*;
+
+type Scene = RSGScene::<RSGComponentLinks, RSGSceneObserver>;
+
+fn triangle_mesh(buffer_id: u32, offset: usize, stride: usize) -> RSGMesh {
+    RSGMesh {
+        vertex_views: smallvec::smallvec![RSGMeshBufferView { buffer_id, offset, size: 3 * stride, stride }],
+        submeshes: smallvec::smallvec![RSGSubMesh {
+            topology: RSGMeshTopology::Triangles,
+            vertex_count: 3,
+            inputs: smallvec::smallvec![RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 0, 0)],
+            index_count: None,
+            index_view: None
+        }],
+        bounds_3d: None,
+        bounding_sphere: None
+    }
+}
+
+fn descriptor(shader_set_id: u32, state: RSGMaterialGraphicsState, mesh: &RSGMesh) -> RSGPipelineDescriptor {
+    let layout = RSGVertexLayout::new(&RSGBuiltinShaderSet::FlatColor.shader_set(), mesh, 0).unwrap();
+    RSGPipelineDescriptor::new(shader_set_id, state, &mesh.submeshes[0], layout)
+}
+
+#[test]
+fn pipeline_descriptors() {
+    let state = RSGMaterialGraphicsState::default();
+    let a = descriptor(1, state, &triangle_mesh(1, 0, 12));
+    // where the data lives does not matter, its layout does
+    assert!(a == descriptor(1, state, &triangle_mesh(2, 64, 12)));
+    assert!(a != descriptor(1, state, &triangle_mesh(1, 0, 16)));
+    assert!(a != descriptor(2, state, &triangle_mesh(1, 0, 12)));
+    let mut blended = state;
+    blended.blend.blend_enable = true;
+    assert!(a != descriptor(1, blended, &triangle_mesh(1, 0, 12)));
+    let mut strip = triangle_mesh(1, 0, 12);
+    strip.submeshes[0].topology = RSGMeshTopology::TriangleStrip;
+    assert!(a != descriptor(1, state, &strip));
+    let mut indexed = triangle_mesh(1, 0, 12);
+    indexed.submeshes[0].index_view = Some(RSGMeshIndexBufferView::U16(RSGMeshBufferView { buffer_id: 3, offset: 0, size: 6, stride: 2 }));
+    assert!(descriptor(1, state, &indexed).index_format == Some(RSGPipelineIndexFormat::U16));
+
+    // neither do inputs the shader set does not consume
+    let mut with_colors = triangle_mesh(1, 0, 12);
+    with_colors.vertex_views.push(RSGMeshBufferView { buffer_id: 4, offset: 0, size: 48, stride: 16 });
+    with_colors.submeshes[0].inputs.push(RSGMeshVertexInput::Color(0, RSGMeshVertexInputType::Vec4, 1, 0));
+    assert!(a == descriptor(1, state, &with_colors));
+
+    let mut set = std::collections::HashSet::new();
+    set.insert(a.clone());
+    set.insert(descriptor(1, state, &triangle_mesh(5, 0, 12)));
+    assert!(set.len() == 1);
+
+    // from a node the inherited opacity is taken into account
+    let pool = scoped_pool::Pool::new(1);
+    let mut scene = Scene::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+    let shader_set_id = components.shader_sets.register(RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "".to_owned(),
+        properties: vec![],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    }).unwrap();
+    let material = RSGMaterial { shader_set_id, property_values: Default::default(), graphics_state: state };
+    let links = RSGComponentBuilder::new(&mut components).opacity(0.5).material(material).mesh(triangle_mesh(1, 0, 12)).links();
+    let node_key = scene.append(root_key, RSGNode::with_component_links(links));
+    let plain_key = scene.append(root_key, RSGNode::new());
+    update_inherited_properties(&mut components, &scene, &[root_key], &[root_key], &pool);
+    let d = RSGPipelineDescriptor::for_node(&components, &scene, node_key, 0).unwrap();
+    assert!(d.shader_set_id == shader_set_id);
+    assert!(d.graphics_state.blend.blend_enable && !d.graphics_state.depth_write);
+    // the shader set consumes no inputs, so neither does the pipeline
+    assert!(d.vertex_layout == RSGVertexLayout::default());
+    assert!(RSGPipelineDescriptor::for_node(&components, &scene, node_key, 1).is_none());
+    assert!(RSGPipelineDescriptor::for_node(&components, &scene, plain_key, 0).is_none());
+    pool.shutdown();
+}
+
+#[test]
+fn pipeline_cache() {
+    let state = RSGMaterialGraphicsState::default();
+    let a = descriptor(1, state, &triangle_mesh(1, 0, 12));
+    let b = descriptor(2, state, &triangle_mesh(1, 0, 12));
+    let mut cache = RSGPipelineCache::<u32>::new();
+    let mut next_handle = 100;
+    let mut create = |_: &RSGPipelineDescriptor| {
+        next_handle += 1;
+        next_handle
+    };
+
+    cache.begin_frame();
+    assert!(*cache.get_or_create(&a, &mut create) == 101);
+    assert!(*cache.get_or_create(&b, &mut create) == 102);
+    assert!(*cache.get_or_create(&a, &mut create) == 101);
+    assert!(cache.stats() == RSGPipelineCacheStats { hits: 1, created: 2, evicted: 0 });
+    assert!(cache.len() == 2 && cache.used_this_frame() == 2);
+
+    // failures are reported and not cached
+    let c = descriptor(3, state, &triangle_mesh(1, 0, 12));
+    assert!(cache.try_get_or_create(&c, |_| Err("shader does not compile")).err() == Some("shader does not compile"));
+    assert!(!cache.contains(&c));
+
+    cache.begin_frame();
+    assert!(cache.get(&a) == Some(&101));
+    assert!(cache.used_this_frame() == 1);
+    cache.begin_frame();
+    // b was last used two frames ago
+    assert!(cache.evict(2).is_empty());
+    let evicted = cache.evict(1);
+    assert!(evicted == vec![(b.clone(), 102)]);
+    assert!(cache.stats().evicted == 1);
+    assert!(cache.contains(&a) && !cache.contains(&b));
+
+    // recreated on demand after eviction
+    assert!(*cache.get_or_create(&b, &mut create) == 103);
+    assert!(cache.evict(0) == vec![(a, 101)]);
+    assert!(cache.clear() == vec![(b, 103)]);
+    assert!(cache.is_empty() && cache.get(&c).is_none());
+
+    // a NaN in the state still finds its pipeline
+    let mut nan_state = state;
+    nan_state.line_width = f32::NAN;
+    let d = descriptor(1, nan_state, &triangle_mesh(1, 0, 12));
+    assert!(*cache.get_or_create(&d, &mut create) == 104);
+    assert!(cache.get(&d.clone()) == Some(&104));
+}
+
+#[test]
+fn vertex_layouts() {
+    let mut mesh = triangle_mesh(1, 0, 32);
+    mesh.vertex_views.push(RSGMeshBufferView { buffer_id: 2, offset: 0, size: 48, stride: 16 });
+    mesh.submeshes[0].inputs = smallvec::smallvec![
+        RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 0, 0),
+        RSGMeshVertexInput::Normal(RSGMeshVertexInputType::Vec3, 0, 12),
+        RSGMeshVertexInput::TexCoord(0, RSGMeshVertexInputType::Vec2, 0, 24),
+        RSGMeshVertexInput::Color(0, RSGMeshVertexInputType::Vec4, 1, 0)
+    ];
+    let attribute = |location, format, buffer_index, offset| RSGVertexAttribute { location, format, buffer_index, offset };
+
+    // the unused color input is left out
+    let layout = RSGVertexLayout::new(&RSGBuiltinShaderSet::PbrMetallicRoughness.shader_set(), &mesh, 0).unwrap();
+    assert!(layout.buffers.as_slice() == [RSGVertexBufferLayout { view_index: 0, stride: 32 }]);
+    assert!(layout.attributes.as_slice() == [
+        attribute(0, RSGMeshVertexInputType::Vec3, 0, 0),
+        attribute(1, RSGMeshVertexInputType::Vec3, 0, 12),
+        attribute(4, RSGMeshVertexInputType::Vec2, 0, 24)
+    ]);
+    let layout = RSGVertexLayout::new(&RSGBuiltinShaderSet::VertexColor.shader_set(), &mesh, 0).unwrap();
+    assert!(layout.buffers.len() == 2 && layout.buffers[1] == RSGVertexBufferLayout { view_index: 1, stride: 16 });
+    assert!(layout.attributes[1] == attribute(3, RSGMeshVertexInputType::Vec4, 1, 0));
+    // a vec3 position feeds the vec2 input of sprites
+    assert!(RSGVertexLayout::new(&RSGBuiltinShaderSet::Sprite2D.shader_set(), &mesh, 0).is_ok());
+
+    // matrices take a location per column, optional inputs may be missing
+    mesh.submeshes[0].inputs.push(RSGMeshVertexInput::TexCoord(1, RSGMeshVertexInputType::Mat4, 1, 0));
+    let mut shader_set = RSGBuiltinShaderSet::FlatColor.shader_set();
+    shader_set.vertex_inputs.push(RSGShaderVertexInput::new(RSGMeshVertexSemantic::TexCoord(1), RSGMeshVertexInputType::Mat4, 5));
+    shader_set.vertex_inputs.push(RSGShaderVertexInput::optional(RSGMeshVertexSemantic::Tangent, RSGMeshVertexInputType::Vec4, 2));
+    let layout = RSGVertexLayout::new(&shader_set, &mesh, 0).unwrap();
+    assert!(layout.attributes.len() == 5);
+    assert!(layout.attributes[4] == attribute(8, RSGMeshVertexInputType::Vec4, 1, 48));
+
+    let mut broken = mesh.clone();
+    broken.submeshes[0].inputs = smallvec::smallvec![
+        RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 2, 0),
+        RSGMeshVertexInput::TexCoord(0, RSGMeshVertexInputType::Int2, 0, 24)
+    ];
+    let errors = RSGVertexLayout::new(&RSGBuiltinShaderSet::PbrMetallicRoughness.shader_set(), &broken, 0).err().unwrap();
+    assert!(errors == vec![
+        RSGVertexInputError::InvalidView { semantic: RSGMeshVertexSemantic::Position, view_index: 2, view_count: 2 },
+        RSGVertexInputError::Missing { semantic: RSGMeshVertexSemantic::Normal, location: 1 },
+        RSGVertexInputError::TypeMismatch {
+            semantic: RSGMeshVertexSemantic::TexCoord(0),
+            location: 4,
+            expected: RSGMeshVertexInputType::Vec2,
+            found: RSGMeshVertexInputType::Int2
+        }
+    ]);
+    assert!(errors[1].to_string() == "the mesh has no Normal input for location 1");
+    assert!(errors[2].to_string() == "the TexCoord(0) input for location 4 is a Int2, but the shader expects a Vec2");
+
+    let errors = RSGVertexLayout::new(&RSGBuiltinShaderSet::FlatColor.shader_set(), &mesh, 1).err().unwrap();
+    assert!(errors == vec![RSGVertexInputError::InvalidSubmesh { submesh_index: 1, submesh_count: 1 }]);
+    // the columns of the matrix at 5 run into the normal at 7
+    shader_set.vertex_inputs.push(RSGShaderVertexInput::optional(RSGMeshVertexSemantic::Normal, RSGMeshVertexInputType::Vec3, 7));
+    shader_set.vertex_inputs.push(RSGShaderVertexInput::optional(RSGMeshVertexSemantic::Color(1), RSGMeshVertexInputType::Vec4, 0));
+    let errors = RSGVertexLayout::new(&shader_set, &mesh, 0).err().unwrap();
+    assert!(errors == vec![
+        RSGVertexInputError::LocationOverlap { semantic: RSGMeshVertexSemantic::Normal, other: RSGMeshVertexSemantic::TexCoord(1), location: 7 },
+        RSGVertexInputError::LocationOverlap { semantic: RSGMeshVertexSemantic::Color(1), other: RSGMeshVertexSemantic::Position, location: 0 }
+    ]);
+    assert!(errors[0].to_string() == "the TexCoord(1) and Normal inputs both use location 7");
+}
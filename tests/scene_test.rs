@@ -829,6 +829,21 @@ fn visit_ancestors() {
     }
 }
 
+#[test]
+fn visit_children() {
+    let mut scene = TestScene::new();
+    // ROOT(NODE1(NODE11, NODE12), NODE2)
+    let root_key = scene.set_root(RSGNode::new());
+    let node1_key = scene.append(root_key, RSGNode::new());
+    let node2_key = scene.append(root_key, RSGNode::new());
+    let node11_key = scene.append(node1_key, RSGNode::new());
+    let node12_key = scene.append(node1_key, RSGNode::new());
+
+    assert!(scene.children(root_key).collect::<Vec<_>>() == vec![node1_key, node2_key]);
+    assert!(scene.children(node1_key).collect::<Vec<_>>() == vec![node11_key, node12_key]);
+    assert!(scene.children(node2_key).next().is_none());
+}
+
 #[test]
 fn component_links() {
     let mut scene = TestScene::new();
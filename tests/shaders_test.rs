@@ -0,0 +1,230 @@
+use rsg::scene::*;
+use rsg::components::*;
+use rsg::materials::*;
+use rsg::shaders::*;
+use nalgebra_glm as glm;
+
+type Scene = RSGScene::<RSGComponentLinks, RSGSceneObserver>;
+
+#[test]
+fn preprocess_includes_and_defines() {
+    let mut preprocessor = RSGShaderPreprocessor::new();
+    preprocessor.add_include("common.glsl", "#pragma once\nconst float PI = 3.14159265;\n#include \"color.glsl\"");
+    preprocessor.add_include("color.glsl", "vec4 tint(vec4 c) { return c; }");
+    preprocessor.define("RSG_MAX_LIGHTS", "4");
+
+    let source = "#version 450\n#include \"common.glsl\"\n  # include <common.glsl>\nvoid main() {}";
+    let defines = vec![("RSG_HAS_TEXTURE".to_owned(), "1".to_owned())];
+    let shader = preprocessor.preprocess(source, "main.frag", &defines).unwrap();
+    assert!(shader.source == "#version 450\n#define RSG_MAX_LIGHTS 4\n#define RSG_HAS_TEXTURE 1\n\
+        const float PI = 3.14159265;\nvec4 tint(vec4 c) { return c; }\nvoid main() {}\n");
+    let location = |file: &str, line| Some(RSGShaderSourceLocation { file: file.to_owned(), line });
+    assert!(shader.location(1) == location("main.frag", 1).as_ref());
+    assert!(shader.location(2).is_none());
+    assert!(shader.location(4) == location("common.glsl", 2).as_ref());
+    assert!(shader.location(5) == location("color.glsl", 1).as_ref());
+    assert!(shader.location(6) == location("main.frag", 4).as_ref());
+    assert!(shader.location(0).is_none() && shader.location(7).is_none());
+
+    // without #pragma once a file is included every time
+    let shader = preprocessor.preprocess("#include \"color.glsl\"\n#include \"color.glsl\"", "main.frag", &[]).unwrap();
+    assert!(shader.source == "#define RSG_MAX_LIGHTS 4\nvec4 tint(vec4 c) { return c; }\nvec4 tint(vec4 c) { return c; }\n");
+
+    let e = preprocessor.preprocess("void main() {}\n#include \"missing.glsl\"", "main.frag", &[]).err().unwrap();
+    assert!(e.to_string() == "main.frag:2: cannot find include 'missing.glsl'");
+    let e = preprocessor.preprocess("#include common.glsl", "main.frag", &[]).err().unwrap();
+    assert!(e == RSGShaderPreprocessError::MalformedInclude { file: "main.frag".to_owned(), line: 1 });
+    preprocessor.add_include("a.glsl", "#include \"b.glsl\"");
+    preprocessor.add_include("b.glsl", "\n#include \"a.glsl\"");
+    let e = preprocessor.preprocess("#include \"a.glsl\"", "main.frag", &[]).err().unwrap();
+    assert!(e == RSGShaderPreprocessError::RecursiveInclude { file: "b.glsl".to_owned(), line: 2, name: "a.glsl".to_owned() });
+    // not a directive
+    assert!(preprocessor.preprocess("#included", "main.frag", &[]).is_ok());
+
+    // blank lines and comments may come before #version
+    let source = "// license\n\n/* multi\n   line */  #version 450 core\nvoid main() {}";
+    let shader = preprocessor.preprocess(source, "main.frag", &[]).unwrap();
+    assert!(shader.source == "// license\n\n/* multi\n   line */  #version 450 core\n#define RSG_MAX_LIGHTS 4\nvoid main() {}\n");
+    assert!(shader.location(4) == location("main.frag", 4).as_ref() && shader.location(6) == location("main.frag", 5).as_ref());
+    // anything else before it means there is no #version to keep first
+    let shader = preprocessor.preprocess("// license\nvoid main() {}\n#version 450", "main.frag", &[]).unwrap();
+    assert!(shader.source.starts_with("#define RSG_MAX_LIGHTS 4\n// license\n"));
+}
+
+#[test]
+fn cached_variants() {
+    let mut components = RSGComponentContainer::default();
+    let mut preprocessor = RSGShaderPreprocessor::new();
+    let textured = RSGBuiltinShaderSet::Textured.id();
+
+    let key = RSGShaderPermutationKey::HAS_TEXTURE | RSGShaderPermutationKey::ALPHA_TEST;
+    let variant = preprocessor.variant(&components.shader_sets, textured, key).unwrap().clone();
+    assert!(variant.stage(RSGShaderStage::Fragment).source.starts_with("#version 450\n#define RSG_HAS_TEXTURE 1\n#define RSG_ALPHA_TEST 1\n"));
+    assert!(variant.vertex.location(4) == Some(&RSGShaderSourceLocation { file: "vertex_shader".to_owned(), line: 2 }));
+    assert!(preprocessor.variant(&components.shader_sets, textured, RSGShaderPermutationKey::empty()).is_ok());
+    assert!(preprocessor.variant_count() == 2);
+    assert!(*preprocessor.cached_variant(textured, key).unwrap() == variant);
+    preprocessor.invalidate(textured);
+    assert!(preprocessor.variant_count() == 0);
+    preprocessor.variant(&components.shader_sets, textured, key).unwrap();
+    preprocessor.define("RSG_DEBUG", "");
+    assert!(preprocessor.cached_variant(textured, key).is_none());
+    assert!(preprocessor.variant(&components.shader_sets, textured, key).unwrap().fragment.source.contains("\n#define RSG_DEBUG\n"));
+    assert!(preprocessor.variant(&components.shader_sets, 99, key).err().unwrap() == RSGShaderPreprocessError::UnknownShaderSet(99));
+
+    // the same id in another registry is not served from the cache
+    let mut other_registry = RSGMaterialShaderSetRegistry::new();
+    let mut shader_set = RSGBuiltinShaderSet::FlatColor.shader_set();
+    shader_set.fragment_shader.push_str("// other\n");
+    let other_id = other_registry.register(shader_set).unwrap();
+    let mut first = RSGBuiltinShaderSet::Textured.shader_set();
+    first.fragment_shader.push_str("// first\n");
+    let first_id = components.shader_sets.register(first).unwrap();
+    assert!(first_id == other_id);
+    assert!(preprocessor.variant(&components.shader_sets, first_id, key).unwrap().fragment.source.contains("// first\n"));
+    assert!(preprocessor.variant(&other_registry, other_id, key).unwrap().fragment.source.contains("// other\n"));
+    assert!(preprocessor.variant_count() == 2);
+
+    // keys from the material and mesh of a node
+    let mut scene = Scene::new();
+    let root_key = components.add_default_root(&mut scene);
+    let mesh = RSGMesh {
+        vertex_views: smallvec::smallvec![RSGMeshBufferView { buffer_id: 1, offset: 0, size: 84, stride: 28 }],
+        submeshes: smallvec::smallvec![RSGSubMesh {
+            topology: RSGMeshTopology::Triangles,
+            vertex_count: 3,
+            inputs: smallvec::smallvec![
+                RSGMeshVertexInput::Position(RSGMeshVertexInputType::Vec3, 0, 0),
+                RSGMeshVertexInput::Color(0, RSGMeshVertexInputType::Vec4, 0, 12)
+            ],
+            index_count: None,
+            index_view: None
+        }],
+        bounds_3d: None,
+        bounding_sphere: None
+    };
+    let links = RSGComponentBuilder::new(&mut components)
+        .mesh(mesh)
+        .material(RSGMaterial::textured(5, glm::vec4(1.0, 1.0, 1.0, 1.0)))
+        .links();
+    let node = scene.append(root_key, RSGNode::with_component_links(links));
+    let key = RSGShaderPermutationKey::for_node(&components, &scene, node, 0);
+    assert!(key == RSGShaderPermutationKey::HAS_TEXTURE | RSGShaderPermutationKey::VERTEX_COLOR);
+
+    // the shader set names its cutoff property, whatever it is called
+    let mut cutout_set = RSGBuiltinShaderSet::FlatColor.shader_set();
+    cutout_set.properties.push(RSGMaterialProperty::Float("discard_below".to_owned(), 0.0));
+    cutout_set.alpha_cutoff = Some("discard_below".to_owned());
+    let cutout_id = components.shader_sets.register(cutout_set.clone()).unwrap();
+    let mut material = RSGMaterial::flat_color(glm::vec4(1.0, 1.0, 1.0, 1.0));
+    material.shader_set_id = cutout_id;
+    let links = RSGComponentBuilder::new(&mut components).material(material.clone()).links();
+    let node = scene.append(root_key, RSGNode::with_component_links(links));
+    assert!(RSGShaderPermutationKey::for_node(&components, &scene, node, 0).is_empty());
+    components.set_material_override(links.material_key.unwrap(), "discard_below",
+        RSGMaterialPropertyValue::Custom(RSGMaterialCustomValue::Float(0.5))).unwrap();
+    assert!(RSGShaderPermutationKey::for_node(&components, &scene, node, 0) == RSGShaderPermutationKey::ALPHA_TEST);
+
+    // a property that is merely called alpha_cutoff does nothing
+    cutout_set.properties.push(RSGMaterialProperty::Float("alpha_cutoff".to_owned(), 0.5));
+    cutout_set.alpha_cutoff = None;
+    material.shader_set_id = components.shader_sets.register(cutout_set).unwrap();
+    let links = RSGComponentBuilder::new(&mut components).material(material).links();
+    let node = scene.append(root_key, RSGNode::with_component_links(links));
+    assert!(RSGShaderPermutationKey::for_node(&components, &scene, node, 0).is_empty());
+}
+
+#[test]
+fn reflect_builtin_shader_sets() {
+    for builtin in RSGBuiltinShaderSet::ALL.iter() {
+        let shader_set = builtin.shader_set();
+        let reflection = RSGShaderReflection::for_shader_set(&shader_set).unwrap();
+        assert!(reflection.compare(&shader_set.properties).is_empty(), "{:?}", builtin);
+        let names: Vec<&str> = reflection.properties.iter().map(|p| p.name()).collect();
+        let declared: Vec<&str> = shader_set.properties.iter().map(|p| p.name()).collect();
+        assert!(names == declared);
+    }
+    let reflection = RSGShaderReflection::for_shader_set(&RSGBuiltinShaderSet::Textured.shader_set()).unwrap();
+    let mvp = &reflection.uniforms[0];
+    assert!(mvp.name == "mvp" && mvp.type_name == "mat4" && mvp.block.as_deref() == Some("MaterialBlock"));
+    assert!(mvp.stages.as_slice() == [RSGShaderStage::Vertex, RSGShaderStage::Fragment]);
+    let texture = &reflection.uniforms[2];
+    assert!(texture.name == "base_color" && texture.block.is_none());
+    assert!(texture.stages.as_slice() == [RSGShaderStage::Fragment]);
+}
+
+#[test]
+fn reflect_uniforms() {
+    let vertex_shader = "#version 450
+        layout(std140, binding = 0) uniform Block {
+            layout(offset = 0) highp mat4 mvp; // the transform
+            vec4 lights[2];
+            /* float unused; */
+            vec2 a, b;
+        } block;
+        #ifdef RSG_HAS_TEXTURE
+        uniform sampler2D tex;
+        #endif
+        uniform float scale = 1.0, bias;
+        struct Light { vec4 color; };
+        void main() { gl_Position = mvp * vec4(scale); }";
+    let fragment_shader = "#version 450
+        precision mediump float;
+        uniform samplerCube env[2];
+        void main() {}";
+    let reflection = RSGShaderReflection::new(vertex_shader, fragment_shader).unwrap();
+    let names: Vec<&str> = reflection.properties.iter().map(|p| p.name()).collect();
+    assert!(names == ["mvp", "lights[0]", "lights[1]", "a", "b", "tex", "scale", "bias", "env[0]", "env[1]"]);
+    assert!(reflection.uniforms[1].array_size == Some(2));
+    assert!(reflection.uniforms[3].block.as_deref() == Some("Block"));
+    assert!(reflection.properties[5] == RSGMaterialProperty::Texture("tex".to_owned(), RSGTextureType::Texture2D, None));
+    assert!(reflection.properties[9] == RSGMaterialProperty::Texture("env[1]".to_owned(), RSGTextureType::Cube, None));
+
+    let declared = vec![
+        RSGMaterialProperty::Vec4("lights[0]".to_owned(), glm::zero()),
+        RSGMaterialProperty::Mat4("mvp".to_owned(), glm::one()),
+        RSGMaterialProperty::Vec4("lights[1]".to_owned(), glm::zero()),
+        RSGMaterialProperty::Vec2("a".to_owned(), glm::zero()),
+        RSGMaterialProperty::Vec3("b".to_owned(), glm::zero()),
+        RSGMaterialProperty::Float("gamma".to_owned(), 2.2),
+        RSGMaterialProperty::Texture("tex".to_owned(), RSGTextureType::Texture2D, None),
+        RSGMaterialProperty::Float("scale".to_owned(), 1.0),
+        RSGMaterialProperty::Float("bias".to_owned(), 0.0),
+        RSGMaterialProperty::Texture("env[0]".to_owned(), RSGTextureType::Cube, None)
+    ];
+    let mismatches = reflection.compare(&declared);
+    assert!(mismatches == vec![
+        RSGShaderPropertyMismatch::TypeMismatch { name: "b".to_owned(), declared: "vec3", reflected: "vec2" },
+        RSGShaderPropertyMismatch::Undeclared { name: "env[1]".to_owned(), type_name: "textureCube" },
+        RSGShaderPropertyMismatch::Unused { name: "gamma".to_owned() },
+        RSGShaderPropertyMismatch::OrderMismatch { name: "lights[0]".to_owned(), declared_index: 0, reflected_index: 1 },
+        RSGShaderPropertyMismatch::OrderMismatch { name: "mvp".to_owned(), declared_index: 1, reflected_index: 0 }
+    ]);
+    assert!(mismatches[0].to_string() == "property 'b' is declared as a vec3, but the shaders use a vec2");
+
+    // a resource on one side and a uniform on the other only reports the type
+    let reflection = RSGShaderReflection::new("uniform vec4 color;", "uniform texture2D tex;").unwrap();
+    let declared = vec![
+        RSGMaterialProperty::Float("tex".to_owned(), 0.0),
+        RSGMaterialProperty::Vec4("color".to_owned(), glm::zero())
+    ];
+    let mismatches = reflection.compare(&declared);
+    assert!(mismatches == vec![
+        RSGShaderPropertyMismatch::TypeMismatch { name: "tex".to_owned(), declared: "float", reflected: "texture2D" }
+    ]);
+
+    // comments may hold any UTF-8
+    let reflection = RSGShaderReflection::new("/* café, ½ */ uniform vec4 color; // naïve\n", "/*é*/uniform float gamma;").unwrap();
+    assert!(reflection.properties.len() == 2);
+
+    let e = RSGShaderReflection::new("uniform vec4 color;", "uniform vec3 color;").err().unwrap();
+    assert!(e.to_string() == "uniform 'color' is a vec4 in the vertex shader, but a vec3 in the fragment shader");
+    let e = RSGShaderReflection::new("uniform float weights[4];", "").err().unwrap();
+    assert!(e == RSGShaderReflectionError::UnsupportedArray { name: "weights".to_owned(), type_name: "float".to_owned() });
+    let e = RSGShaderReflection::new("uniform uint count;", "").err().unwrap();
+    assert!(e == RSGShaderReflectionError::UnsupportedType { name: "count".to_owned(), type_name: "uint".to_owned() });
+    let e = RSGShaderReflection::new("", "\nuniform vec4 colors[N];").err().unwrap();
+    assert!(e.to_string() == "fragment_shader:2: the size of array 'colors' is not an integer literal");
+    let e = RSGShaderReflection::new("void main() {", "").err().unwrap();
+    assert!(e == RSGShaderReflectionError::Syntax { stage: RSGShaderStage::Vertex, line: 1, message: "unbalanced braces".to_owned() });
+}
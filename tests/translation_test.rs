@@ -0,0 +1,83 @@
+#![cfg(feature = "shader-translation")]
+
+use rsg::components::*;
+use rsg::materials::*;
+use rsg::shaders::*;
+use rsg::translation::*;
+
+const TARGETS: [RSGShaderTarget; 5] = [
+    RSGShaderTarget::GlslEs(300),
+    RSGShaderTarget::Glsl(330),
+    RSGShaderTarget::Wgsl,
+    RSGShaderTarget::Hlsl,
+    RSGShaderTarget::Msl
+];
+
+#[test]
+fn translate_builtin_shader_sets() {
+    let shader_sets = RSGMaterialShaderSetRegistry::new();
+    let mut preprocessor = RSGShaderPreprocessor::new();
+    let mut translator = RSGShaderTranslator::new();
+    for builtin in RSGBuiltinShaderSet::ALL.iter() {
+        for target in TARGETS.iter() {
+            let translated = translator.translate(&mut preprocessor, &shader_sets, builtin.id(), RSGShaderPermutationKey::empty(), *target);
+            let translated = translated.unwrap_or_else(|e| panic!("{:?} to {:?}: {}", builtin, target, e));
+            assert!(translated.target == *target);
+            assert!(!translated.vertex.source.is_empty() && !translated.fragment.source.is_empty());
+        }
+    }
+    assert!(translator.len() == RSGBuiltinShaderSet::ALL.len() * TARGETS.len());
+
+    let flat_color = RSGBuiltinShaderSet::FlatColor.id();
+    let es = translator.translate(&mut preprocessor, &shader_sets, flat_color, RSGShaderPermutationKey::empty(), TARGETS[0]).unwrap();
+    assert!(es.vertex.source.starts_with("#version 300 es"));
+    let wgsl = translator.translate(&mut preprocessor, &shader_sets, flat_color, RSGShaderPermutationKey::empty(), RSGShaderTarget::Wgsl).unwrap();
+    assert!(wgsl.vertex.source.contains("@vertex") && wgsl.fragment.source.contains("@fragment"));
+    let msl = translator.translate(&mut preprocessor, &shader_sets, flat_color, RSGShaderPermutationKey::empty(), RSGShaderTarget::Msl).unwrap();
+    assert!(msl.fragment.source.contains(&msl.fragment.entry_point));
+    assert!(msl.fragment.entry_point != "main");
+    assert!(translator.len() == RSGBuiltinShaderSet::ALL.len() * TARGETS.len());
+}
+
+#[test]
+fn translation_errors() {
+    let mut shader_sets = RSGMaterialShaderSetRegistry::new();
+    let mut preprocessor = RSGShaderPreprocessor::new();
+    let mut translator = RSGShaderTranslator::new();
+    preprocessor.add_include("color.glsl", "vec4 tint() { return vec4(1.0); }\n");
+    let mut shader_set = RSGBuiltinShaderSet::FlatColor.shader_set();
+    shader_set.fragment_shader = shader_set.fragment_shader.replace("frag_color = color;", "frag_color = color * tint();");
+    shader_set.fragment_shader = shader_set.fragment_shader.replace("#version 450\n", "#version 450\n#include \"color.glsl\"\n");
+    let id = shader_sets.register(shader_set).unwrap();
+    let key = RSGShaderPermutationKey::empty();
+    let first = translator.translate(&mut preprocessor, &shader_sets, id, key, RSGShaderTarget::Wgsl).unwrap().clone();
+
+    // errors point at the original file and line
+    preprocessor.add_include("color.glsl", "\nvec4 tint() { return vec3(1.0); }\n");
+    let e = translator.translate(&mut preprocessor, &shader_sets, id, key, RSGShaderTarget::Wgsl).err().unwrap();
+    match &e {
+        RSGShaderTranslationError::Compile { stage, diagnostics } => {
+            assert!(*stage == RSGShaderStage::Fragment);
+            assert!(diagnostics[0].file == "color.glsl" && diagnostics[0].line == 2 && diagnostics[0].column > 0);
+        },
+        _ => panic!("unexpected error {:?}", e)
+    }
+    assert!(e.to_string().starts_with("color.glsl:2:"));
+
+    // fixing the include retranslates
+    preprocessor.add_include("color.glsl", "vec4 tint() { return vec4(0.5); }\n");
+    let second = translator.translate(&mut preprocessor, &shader_sets, id, key, RSGShaderTarget::Wgsl).unwrap();
+    assert!(*second != first && second.fragment.source.contains("0.5"));
+
+    preprocessor.remove_include("color.glsl");
+    let e = translator.translate(&mut preprocessor, &shader_sets, id, key, RSGShaderTarget::Wgsl).err().unwrap();
+    assert!(e == RSGShaderTranslationError::Preprocess(RSGShaderPreprocessError::MissingInclude {
+        file: "fragment_shader".to_owned(),
+        line: 2,
+        name: "color.glsl".to_owned()
+    }));
+
+    let shader = preprocessor.preprocess("#version 450\nvoid main() { gl_Position = undefined_value; }\n", "broken.vert", &[]).unwrap();
+    let e = translate_shader(&shader, RSGShaderStage::Vertex, RSGShaderTarget::Hlsl).err().unwrap();
+    assert!(e.to_string().starts_with("broken.vert:2:"), "{}", e);
+}
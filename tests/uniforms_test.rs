@@ -0,0 +1,185 @@
+use rsg::components::*;
+use rsg::uniforms::*;
+use nalgebra_glm as glm;
+
+fn reference_properties() -> Vec<RSGMaterialProperty> {
+    // float a; vec3 b; float c; mat3 d; vec2 e; mat2 f; vec4 g; int h; ivec3 i;
+    vec![
+        RSGMaterialProperty::Float("a".to_owned(), 0.0),
+        RSGMaterialProperty::Vec3("b".to_owned(), glm::zero()),
+        RSGMaterialProperty::Float("c".to_owned(), 0.0),
+        RSGMaterialProperty::Mat3("d".to_owned(), glm::one()),
+        RSGMaterialProperty::Vec2("e".to_owned(), glm::zero()),
+        RSGMaterialProperty::Mat2("f".to_owned(), glm::one()),
+        RSGMaterialProperty::Vec4("g".to_owned(), glm::zero()),
+        RSGMaterialProperty::Int("h".to_owned(), 0),
+        RSGMaterialProperty::Int3("i".to_owned(), glm::zero())
+    ]
+}
+
+fn offsets(layout: &RSGUniformLayout) -> Vec<usize> {
+    layout.members.iter().map(|m| m.offset).collect()
+}
+
+#[test]
+fn std140_offsets() {
+    let layout = RSGUniformLayout::new(&reference_properties(), RSGUniformLayoutRules::Std140);
+    assert!(offsets(&layout) == vec![0, 16, 28, 32, 80, 96, 128, 144, 160]);
+    assert!(layout.member("d").unwrap().size == 48);
+    assert!(layout.member("d").unwrap().matrix_column_stride == 16);
+    assert!(layout.member("f").unwrap().size == 32);
+    assert!(layout.size == 176);
+
+    let layout = RSGUniformLayout::new(&[RSGMaterialProperty::Mat4("mvp".to_owned(), glm::one()), RSGMaterialProperty::Float("opacity".to_owned(), 1.0)],
+        RSGUniformLayoutRules::Std140);
+    assert!(offsets(&layout) == vec![0, 64]);
+    assert!(layout.size == 80);
+}
+
+#[test]
+fn std430_offsets() {
+    let layout = RSGUniformLayout::new(&reference_properties(), RSGUniformLayoutRules::Std430);
+    assert!(offsets(&layout) == vec![0, 16, 28, 32, 80, 88, 112, 128, 144]);
+    assert!(layout.member("d").unwrap().matrix_column_stride == 16);
+    assert!(layout.member("f").unwrap().size == 16);
+    assert!(layout.member("f").unwrap().matrix_column_stride == 8);
+    assert!(layout.size == 160);
+
+    // no rounding up to vec4 for the block
+    let layout = RSGUniformLayout::new(&[RSGMaterialProperty::Float("a".to_owned(), 0.0), RSGMaterialProperty::Vec2("b".to_owned(), glm::zero())],
+        RSGUniformLayoutRules::Std430);
+    assert!(offsets(&layout) == vec![0, 8]);
+    assert!(layout.size == 16);
+    let layout = RSGUniformLayout::new(&[RSGMaterialProperty::Float("a".to_owned(), 0.0)], RSGUniformLayoutRules::Std430);
+    assert!(layout.size == 4);
+}
+
+fn read_f32(buf: &[u8], offset: usize) -> f32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&buf[offset..offset + 4]);
+    f32::from_ne_bytes(b)
+}
+
+fn read_i32(buf: &[u8], offset: usize) -> i32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&buf[offset..offset + 4]);
+    i32::from_ne_bytes(b)
+}
+
+#[test]
+fn write_values() {
+    let properties = reference_properties();
+    let mat3 = glm::mat3(1.0, 2.0, 3.0,
+                         4.0, 5.0, 6.0,
+                         7.0, 8.0, 9.0);
+    let mat2 = glm::mat2(1.0, 2.0,
+                         3.0, 4.0);
+    let values = vec![
+        RSGMaterialCustomValue::Float(0.5),
+        RSGMaterialCustomValue::Vec3(glm::vec3(1.0, 2.0, 3.0)),
+        RSGMaterialCustomValue::Float(4.0),
+        RSGMaterialCustomValue::Mat3(mat3),
+        RSGMaterialCustomValue::Vec2(glm::vec2(5.0, 6.0)),
+        RSGMaterialCustomValue::Mat2(mat2),
+        RSGMaterialCustomValue::Vec4(glm::vec4(7.0, 8.0, 9.0, 10.0)),
+        RSGMaterialCustomValue::Int(-3),
+        RSGMaterialCustomValue::Int3(glm::vec3(11, 12, 13))
+    ];
+
+    let layout = RSGUniformLayout::new(&properties, RSGUniformLayoutRules::Std140);
+    let mut buf = vec![];
+    assert!(layout.append(&values, &mut buf, 256) == 0);
+    assert!(buf.len() == layout.size);
+    assert!(read_f32(&buf, 0) == 0.5);
+    assert!(read_f32(&buf, 16) == 1.0 && read_f32(&buf, 24) == 3.0);
+    assert!(read_f32(&buf, 28) == 4.0);
+    // columns of the mat3, each padded to a vec4: (1, 4, 7), (2, 5, 8), (3, 6, 9)
+    assert!(read_f32(&buf, 32) == 1.0 && read_f32(&buf, 36) == 4.0 && read_f32(&buf, 40) == 7.0);
+    assert!(read_f32(&buf, 48) == 2.0 && read_f32(&buf, 64) == 3.0 && read_f32(&buf, 72) == 9.0);
+    assert!(read_f32(&buf, 80) == 5.0 && read_f32(&buf, 84) == 6.0);
+    assert!(read_f32(&buf, 96) == 1.0 && read_f32(&buf, 100) == 3.0 && read_f32(&buf, 112) == 2.0 && read_f32(&buf, 116) == 4.0);
+    assert!(read_f32(&buf, 128) == 7.0 && read_f32(&buf, 140) == 10.0);
+    assert!(read_i32(&buf, 144) == -3);
+    assert!(read_i32(&buf, 160) == 11 && read_i32(&buf, 168) == 13);
+
+    // second block goes to the next aligned offset
+    assert!(layout.append(&values, &mut buf, 256) == 256);
+    assert!(buf.len() == 256 + layout.size);
+
+    let layout = RSGUniformLayout::new(&properties, RSGUniformLayoutRules::Std430);
+    let mut buf = vec![0; layout.size];
+    layout.write(&values, &mut buf);
+    assert!(read_f32(&buf, 88) == 1.0 && read_f32(&buf, 92) == 3.0 && read_f32(&buf, 96) == 2.0 && read_f32(&buf, 100) == 4.0);
+    assert!(read_f32(&buf, 112) == 7.0);
+}
+
+#[test]
+fn pack_resolved_draw_values() {
+    let shader_set = RSGMaterialShaderSet {
+        vertex_shader: "".to_owned(),
+        fragment_shader: "".to_owned(),
+        properties: vec![
+            RSGMaterialProperty::Mat4("mvp".to_owned(), glm::one()),
+            RSGMaterialProperty::Vec3("color".to_owned(), glm::zero())
+        ],
+        vertex_inputs: vec![],
+        alpha_cutoff: None
+    };
+    let layout = RSGUniformLayout::for_shader_set(&shader_set, RSGUniformLayoutRules::Std140);
+    assert!(layout.size == 80);
+
+    let mut table = RSGDrawValueTable::default();
+    let mut scene = rsg::scene::RSGScene::<RSGComponentLinks, RSGSceneObserver>::new();
+    let mut components = RSGComponentContainer::default();
+    let root_key = components.add_default_root(&mut scene);
+    for i in 0..2 {
+        table.draws.push(RSGDrawValueRange { node_key: root_key, shader_set_id: Some(1), first: table.values.len(), count: 2 });
+        table.values.push(RSGMaterialCustomValue::Mat4(glm::translation(&glm::vec3(i as f32, 0.0, 0.0))));
+        table.values.push(RSGMaterialCustomValue::Vec3(glm::vec3(1.0, 0.0, i as f32)));
+    }
+    let mut buf = vec![];
+    let offsets = pack_draw_values(&layout, &table, &[0, 1], 256, &mut buf);
+    assert!(offsets == vec![0, 256]);
+    assert!(read_f32(&buf, 48) == 0.0 && read_f32(&buf, 256 + 48) == 1.0);
+    assert!(read_f32(&buf, 64) == 1.0 && read_f32(&buf, 256 + 72) == 1.0);
+
+    // a draw without values, e.g. a node without material, gets a zeroed block
+    table.draws.push(RSGDrawValueRange { node_key: root_key, shader_set_id: None, first: table.values.len(), count: 0 });
+    let offsets = pack_draw_values(&layout, &table, &[2], 256, &mut buf);
+    assert!(offsets == vec![512] && buf.len() == 512 + 80);
+    assert!(buf[512..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn resources_are_not_uniforms() {
+    let properties = vec![
+        RSGMaterialProperty::Float("a".to_owned(), 0.0),
+        RSGMaterialProperty::Texture("tex".to_owned(), RSGTextureType::Texture2D, None),
+        RSGMaterialProperty::Sampler("tex_sampler".to_owned(), Default::default()),
+        RSGMaterialProperty::Vec2("b".to_owned(), glm::zero())
+    ];
+    let layout = RSGUniformLayout::new(&properties, RSGUniformLayoutRules::Std140);
+    assert!(offsets(&layout) == vec![0, 8]);
+    assert!(layout.members.iter().map(|m| m.property_index).collect::<Vec<_>>() == vec![0, 3]);
+    assert!(layout.member("tex").is_none());
+
+    let values = vec![
+        RSGMaterialCustomValue::Float(1.0),
+        RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(3)),
+        RSGMaterialCustomValue::Sampler(Default::default()),
+        RSGMaterialCustomValue::Vec2(glm::vec2(2.0, 3.0))
+    ];
+    let mut buf = vec![0; layout.size];
+    layout.write(&values, &mut buf);
+    assert!(read_f32(&buf, 0) == 1.0 && read_f32(&buf, 8) == 2.0 && read_f32(&buf, 12) == 3.0);
+
+    // a value of another type than the member is not written
+    let mismatched = vec![
+        RSGMaterialCustomValue::Int(5),
+        RSGMaterialCustomValue::Texture(RSGTextureType::Texture2D, Some(3)),
+        RSGMaterialCustomValue::Sampler(Default::default()),
+        RSGMaterialCustomValue::Vec4(glm::vec4(4.0, 5.0, 6.0, 7.0))
+    ];
+    layout.write(&mismatched, &mut buf);
+    assert!(read_f32(&buf, 0) == 1.0 && read_f32(&buf, 8) == 2.0 && read_f32(&buf, 12) == 3.0);
+}